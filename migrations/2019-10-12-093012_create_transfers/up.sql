@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE transfers ( -- 用户之间的现金、股票转账
+    id BIGSERIAL PRIMARY KEY,
+    from_user_id BIGINT NOT NULL REFERENCES users(id),
+    to_user_id BIGINT NOT NULL REFERENCES users(id),
+    stock_id BIGINT REFERENCES stocks(id) NULL,  -- 当是 NULL 时，表示转账现金
+    amount BIGINT NOT NULL,
+    memo VARCHAR NOT NULL DEFAULT '',
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX transfers_from_index ON transfers(from_user_id, created_at);
+CREATE INDEX transfers_to_index ON transfers(to_user_id, created_at);
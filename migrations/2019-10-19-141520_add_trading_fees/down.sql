@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+
+DELETE FROM users WHERE name = '__house__' AND password_hashed = '!';
+
+ALTER TABLE deals DROP COLUMN IF EXISTS stamp_duty;
+ALTER TABLE deals DROP COLUMN IF EXISTS seller_commission;
+ALTER TABLE deals DROP COLUMN IF EXISTS buyer_commission;
+
+ALTER TABLE user_bid_orders DROP COLUMN IF EXISTS fee_paid;
+ALTER TABLE user_ask_orders DROP COLUMN IF EXISTS fee_paid;
+ALTER TABLE user_ask_orders DROP COLUMN IF EXISTS fee_frozen;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+ALTER TABLE user_ask_orders ADD COLUMN fee_frozen BIGINT NOT NULL DEFAULT 0; -- 买入委托为佣金冻结的余额
+ALTER TABLE user_ask_orders ADD COLUMN fee_paid BIGINT NOT NULL DEFAULT 0; -- 买入委托已收取的佣金，最低佣金按委托计算
+ALTER TABLE user_bid_orders ADD COLUMN fee_paid BIGINT NOT NULL DEFAULT 0; -- 卖出委托已收取的佣金，最低佣金按委托计算
+
+ALTER TABLE deals ADD COLUMN buyer_commission BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE deals ADD COLUMN seller_commission BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE deals ADD COLUMN stamp_duty BIGINT NOT NULL DEFAULT 0;
+
+-- 收取手续费的平台账户，密码不是合法的哈希值，所以无法登录
+INSERT INTO users (password_hashed, name, created_at, balance)
+    VALUES ('!', '__house__', CURRENT_TIMESTAMP, 0)
+    ON CONFLICT (name) DO NOTHING;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE pending_settlements ( -- 尚未交收的股票或现金
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    stock_id BIGINT REFERENCES stocks(id) NULL,  -- 当是 NULL 时，表示现金
+    amount BIGINT NOT NULL,
+    settle_at TIMESTAMP NOT NULL,   -- 交收时间，此后才可卖出或使用
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX pending_settlements_index ON pending_settlements(user_id, settle_at);
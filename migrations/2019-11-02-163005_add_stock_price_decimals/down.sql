@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE stocks DROP COLUMN IF EXISTS price_decimals;
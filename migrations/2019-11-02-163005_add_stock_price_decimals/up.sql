@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- 价格保留的小数位数：2 表示精确到分，1 表示精确到角，0 表示精确到元
+ALTER TABLE stocks ADD COLUMN price_decimals SMALLINT NOT NULL DEFAULT 2
+    CHECK (price_decimals >= 0 AND price_decimals <= 2);
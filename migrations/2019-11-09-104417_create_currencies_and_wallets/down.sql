@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS conversions;
+DROP TABLE IF EXISTS fx_rates;
+
+ALTER TABLE transfers DROP COLUMN IF EXISTS currency;
+ALTER TABLE pending_settlements DROP COLUMN IF EXISTS currency;
+
+-- 只能恢复人民币余额，其他币种的余额会丢失
+ALTER TABLE users ADD COLUMN balance BIGINT NOT NULL DEFAULT 0;
+UPDATE users SET balance = user_wallets.balance
+    FROM user_wallets
+    WHERE user_wallets.user_id = users.id AND user_wallets.currency = 'CNY';
+ALTER TABLE users ALTER COLUMN balance DROP DEFAULT;
+DROP TABLE IF EXISTS user_wallets;
+
+ALTER TABLE stocks DROP COLUMN IF EXISTS currency;
+DROP TABLE IF EXISTS currencies;
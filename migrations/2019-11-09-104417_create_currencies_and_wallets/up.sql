@@ -0,0 +1,48 @@
+-- Your SQL goes here
+CREATE TABLE currencies (
+    code VARCHAR(3) PRIMARY KEY,   -- ISO 4217 代码，如 CNY、USD
+    name VARCHAR NOT NULL
+);
+INSERT INTO currencies (code, name) VALUES ('CNY', '人民币'), ('USD', '美元');
+
+-- 股票的报价币种
+ALTER TABLE stocks ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'CNY' REFERENCES currencies(code);
+
+-- 每个用户每种币种一个钱包，原有余额转入人民币钱包
+CREATE TABLE user_wallets (
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    balance BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, currency)
+);
+INSERT INTO user_wallets (user_id, currency, balance, updated_at)
+    SELECT id, 'CNY', balance, CURRENT_TIMESTAMP FROM users;
+ALTER TABLE users DROP COLUMN balance;
+
+-- 现金的待交收记录和转账记录需要记录币种，股票时为 NULL
+ALTER TABLE pending_settlements ADD COLUMN currency VARCHAR(3) REFERENCES currencies(code);
+UPDATE pending_settlements SET currency = 'CNY' WHERE stock_id IS NULL;
+ALTER TABLE transfers ADD COLUMN currency VARCHAR(3) REFERENCES currencies(code);
+UPDATE transfers SET currency = 'CNY' WHERE stock_id IS NULL;
+
+-- 汇率由管理员维护：1 单位 base_currency 可兑换 rate / 1000000 单位 quote_currency
+CREATE TABLE fx_rates (
+    base_currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    quote_currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    rate BIGINT NOT NULL CHECK (rate > 0),
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (base_currency, quote_currency)
+);
+
+CREATE TABLE conversions (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    from_currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    to_currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    from_amount BIGINT NOT NULL,
+    to_amount BIGINT NOT NULL,
+    rate BIGINT NOT NULL,      -- 兑换时使用的汇率，含义同 fx_rates.rate，单位为 from_currency 兑 to_currency
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX conversions_user_index ON conversions(user_id, created_at);
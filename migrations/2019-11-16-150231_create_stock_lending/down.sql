@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE user_ask_orders DROP COLUMN IF EXISTS cover;
+
+DROP TABLE IF EXISTS stock_loans;
+DROP TABLE IF EXISTS lending_pool;
@@ -0,0 +1,32 @@
+-- Your SQL goes here
+-- 借券池：持有者自愿把股票放入借券池，供卖空者借入
+CREATE TABLE lending_pool (
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    stock_id BIGINT NOT NULL REFERENCES stocks(id),
+    available BIGINT NOT NULL DEFAULT 0,   -- 可借出的数量
+    lent BIGINT NOT NULL DEFAULT 0,        -- 已借出的数量
+    updated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (user_id, stock_id)
+);
+
+-- 借券记录，一次卖空可能从多个出借人处借入，每个出借人一条
+CREATE TABLE stock_loans (
+    id BIGSERIAL PRIMARY KEY,
+    borrower_id BIGINT NOT NULL REFERENCES users(id),
+    lender_id BIGINT NOT NULL REFERENCES users(id),
+    stock_id BIGINT NOT NULL REFERENCES stocks(id),
+    amount BIGINT NOT NULL,                -- 尚未归还的数量
+    price INTEGER NOT NULL,                -- 借入时的卖空委托价，用于计算借券费用
+    collateral BIGINT NOT NULL,            -- 剩余的现金担保
+    currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    fee_rate BIGINT NOT NULL,              -- 每日借券费率，百万分之一
+    fees_paid BIGINT NOT NULL DEFAULT 0,
+    fee_accrued_at TIMESTAMP NOT NULL,     -- 借券费用已计提到的时间
+    created_at TIMESTAMP NOT NULL,
+    closed_at TIMESTAMP                    -- 全部归还的时间，未还清时为 NULL
+);
+CREATE INDEX stock_loans_borrower_index ON stock_loans(borrower_id, stock_id, closed_at);
+CREATE INDEX stock_loans_lender_index ON stock_loans(lender_id, closed_at);
+
+-- 买入平仓：成交的股票优先用于归还借入的股票
+ALTER TABLE user_ask_orders ADD COLUMN cover BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE user_bid_orders DROP COLUMN IF EXISTS liquidation;
+
+DROP TABLE IF EXISTS margin_loans;
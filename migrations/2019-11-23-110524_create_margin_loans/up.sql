@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- 融资负债：每个用户每种币种一条，借入的现金以持有的股票为担保
+CREATE TABLE margin_loans (
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    principal BIGINT NOT NULL DEFAULT 0,   -- 尚未归还的借款
+    updated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (user_id, currency)
+);
+
+-- 强制平仓：维持担保比例不足时由撮合引擎自动提交的卖出委托
+ALTER TABLE user_bid_orders ADD COLUMN liquidation BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS dividend_entitlements;
+DROP TABLE IF EXISTS dividends;
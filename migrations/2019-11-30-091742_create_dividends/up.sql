@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- 发行人宣告的现金分红
+CREATE TABLE dividends (
+    id BIGSERIAL PRIMARY KEY,
+    stock_id BIGINT NOT NULL REFERENCES stocks(id),
+    issuer_id BIGINT NOT NULL REFERENCES users(id),
+    currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    amount_per_share BIGINT NOT NULL,       -- 每股派发的现金，单位为分
+    record_date TIMESTAMP NOT NULL,         -- 股权登记时间，此时登记持股的用户可以获得分红
+    pay_date TIMESTAMP NOT NULL,            -- 派发时间
+    total BIGINT NOT NULL DEFAULT 0,        -- 登记后应派发的总额
+    recorded_at TIMESTAMP,                  -- 实际登记的时间，未登记时为 NULL
+    paid_at TIMESTAMP,                      -- 实际派发的时间，未派发时为 NULL
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX dividends_record_index ON dividends(recorded_at, record_date);
+CREATE INDEX dividends_pay_index ON dividends(paid_at, pay_date);
+CREATE INDEX dividends_stock_index ON dividends(stock_id);
+
+-- 股权登记时每个持股用户应得的分红
+CREATE TABLE dividend_entitlements (
+    dividend_id BIGINT NOT NULL REFERENCES dividends(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    shares BIGINT NOT NULL,                 -- 登记的持股数，包括卖出委托冻结的股票
+    amount BIGINT NOT NULL,
+    PRIMARY KEY (dividend_id, user_id)
+);
+CREATE INDEX dividend_entitlements_user_index ON dividend_entitlements(user_id);
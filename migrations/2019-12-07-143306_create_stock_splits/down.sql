@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP FUNCTION IF EXISTS split_adjustment(BIGINT, TIMESTAMP);
+DROP TABLE IF EXISTS stock_splits;
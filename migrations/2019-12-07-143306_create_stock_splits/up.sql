@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- 拆股和合股：每 denominator 股旧股变为 numerator 股新股
+CREATE TABLE stock_splits (
+    id BIGSERIAL PRIMARY KEY,
+    stock_id BIGINT NOT NULL REFERENCES stocks(id),
+    numerator BIGINT NOT NULL,
+    denominator BIGINT NOT NULL,
+    reference_price INTEGER NOT NULL,      -- 调整后的参考价，用于折算零股现金
+    cash_paid BIGINT NOT NULL DEFAULT 0,   -- 发行人为零股支付的现金总额
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX stock_splits_stock_index ON stock_splits(stock_id, created_at);
+
+-- 复权因子：at 时刻的价格乘以该因子，即可与当前的股数口径一致
+CREATE FUNCTION split_adjustment(p_stock_id BIGINT, p_at TIMESTAMP) RETURNS NUMERIC AS $$
+    SELECT COALESCE(EXP(SUM(LN(denominator::NUMERIC / numerator))), 1)
+    FROM stock_splits
+    WHERE stock_id = p_stock_id AND created_at > p_at
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS offering_subscriptions;
+DROP TABLE IF EXISTS offerings;
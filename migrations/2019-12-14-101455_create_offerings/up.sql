@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- 已上市股票的增发，每次增发有自己的价格、数量和认购时间
+CREATE TABLE offerings (
+    id BIGSERIAL PRIMARY KEY,
+    stock_id BIGINT NOT NULL REFERENCES stocks(id),
+    issuer_id BIGINT NOT NULL REFERENCES users(id),
+    price INTEGER NOT NULL,
+    size BIGINT NOT NULL,                  -- 增发的股数
+    remaining BIGINT NOT NULL,             -- 尚未被认购的股数
+    currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    starts_at TIMESTAMP NOT NULL,
+    ends_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX offerings_stock_index ON offerings(stock_id, created_at);
+
+-- 增发的认购记录
+CREATE TABLE offering_subscriptions (
+    id BIGSERIAL PRIMARY KEY,
+    offering_id BIGINT NOT NULL REFERENCES offerings(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    amount BIGINT NOT NULL,
+    cost BIGINT NOT NULL,                  -- 认购金额，不含佣金
+    commission BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX offering_subscriptions_offering_index ON offering_subscriptions(offering_id);
+CREATE INDEX offering_subscriptions_user_index ON offering_subscriptions(user_id);
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS ipo_subscriptions;
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS allocated_at;
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS lottery_seed;
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS lot_size;
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS allocation;
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS subscribe_ends_at;
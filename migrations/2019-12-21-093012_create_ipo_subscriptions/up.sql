@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- 新股认购期：认购期内冻结资金，认购期结束后按摇号或比例配售
+ALTER TABLE new_stocks ADD COLUMN subscribe_ends_at TIMESTAMP;         -- 为 null 时先到先得
+ALTER TABLE new_stocks ADD COLUMN allocation VARCHAR;                  -- lottery 或 pro_rata
+ALTER TABLE new_stocks ADD COLUMN lot_size BIGINT NOT NULL DEFAULT 1;  -- 每手股数
+ALTER TABLE new_stocks ADD COLUMN lottery_seed BIGINT;                   -- 摇号种子，认购期结束配售时抽取，公布以便复现
+ALTER TABLE new_stocks ADD COLUMN allocated_at TIMESTAMP;
+
+CREATE TABLE ipo_subscriptions (
+    id BIGSERIAL PRIMARY KEY,
+    stock_id BIGINT NOT NULL REFERENCES stocks(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    amount BIGINT NOT NULL,                -- 认购股数
+    frozen BIGINT NOT NULL,                -- 冻结的资金，含佣金
+    allocated BIGINT NOT NULL DEFAULT 0,   -- 配售到的股数
+    refund BIGINT NOT NULL DEFAULT 0,      -- 退还的资金
+    created_at TIMESTAMP NOT NULL,
+    allocated_at TIMESTAMP,
+    UNIQUE (stock_id, user_id)
+);
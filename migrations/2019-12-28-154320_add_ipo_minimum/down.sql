@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS failed_at;
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS deadline;
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS min_subscription;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- 最低发行量和截止时间：截止时未达到最低发行量则发行失败，全额退款
+ALTER TABLE new_stocks ADD COLUMN min_subscription BIGINT;   -- 为 null 时不设最低发行量
+ALTER TABLE new_stocks ADD COLUMN deadline TIMESTAMP;        -- 为 null 时由发行人手动上市
+ALTER TABLE new_stocks ADD COLUMN failed_at TIMESTAMP;       -- 发行失败的时间
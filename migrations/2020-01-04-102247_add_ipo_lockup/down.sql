@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS lockup_allottees;
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS lockup_days;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- 上市后的锁定期，锁定期内发行人（以及可选的新股认购者）不能卖出该股票
+ALTER TABLE new_stocks ADD COLUMN lockup_days INTEGER;       -- 为 null 时不设锁定期
+ALTER TABLE new_stocks ADD COLUMN lockup_allottees BOOLEAN NOT NULL DEFAULT FALSE;
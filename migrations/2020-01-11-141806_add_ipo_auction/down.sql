@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE ipo_subscriptions DROP COLUMN IF EXISTS price;
+ALTER TABLE new_stocks DROP COLUMN IF EXISTS auction;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- 荷兰式拍卖发行：认购时给出价格，认购期结束后以清算价格统一配售
+ALTER TABLE new_stocks ADD COLUMN auction BOOLEAN NOT NULL DEFAULT FALSE;   -- 为 true 时 offer_price 在配售前为最低价格，配售后为清算价格
+ALTER TABLE ipo_subscriptions ADD COLUMN price INTEGER;                      -- 拍卖发行的投标价格
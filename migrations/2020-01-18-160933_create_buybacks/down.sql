@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE user_ask_orders DROP COLUMN IF EXISTS buyback_id;
+DROP TABLE IF EXISTS buybacks;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- 发行人的股票回购计划，由后端在发行人账户中代为挂出买入委托
+CREATE TABLE buybacks (
+    id BIGSERIAL PRIMARY KEY,
+    stock_id BIGINT NOT NULL REFERENCES stocks(id),
+    issuer_id BIGINT NOT NULL REFERENCES users(id),
+    currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    budget BIGINT NOT NULL,                -- 回购预算，含佣金
+    max_price INTEGER NOT NULL,            -- 回购价格上限
+    spent BIGINT NOT NULL DEFAULT 0,       -- 已花费的金额，含佣金
+    repurchased BIGINT NOT NULL DEFAULT 0, -- 已回购并注销的股数
+    created_at TIMESTAMP NOT NULL,
+    closed_at TIMESTAMP                    -- 预算用完或发行人终止的时间
+);
+CREATE INDEX buybacks_stock_index ON buybacks(stock_id, created_at);
+
+ALTER TABLE user_ask_orders ADD COLUMN buyback_id BIGINT REFERENCES buybacks(id);  -- 回购计划挂出的买入委托
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- 预先汇总的 K 线，每笔撮合成交时更新；价格和成交量按当前股数复权，成交额为原始金额
+CREATE TABLE candles (
+    stock_id BIGINT NOT NULL REFERENCES stocks(id),
+    period VARCHAR(3) NOT NULL,     -- K 线周期：1m、5m、15m、1h、1d、1w
+    bucket BIGINT NOT NULL,         -- K 线序号，开始时刻为 bucket * 周期秒数（周线从星期一开始）
+    open INTEGER NOT NULL,
+    high INTEGER NOT NULL,
+    low INTEGER NOT NULL,
+    close INTEGER NOT NULL,
+    volume BIGINT NOT NULL,
+    turnover BIGINT NOT NULL,
+    PRIMARY KEY (stock_id, period, bucket)
+);
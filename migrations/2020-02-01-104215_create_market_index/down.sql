@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS index_candles;
+DROP TABLE IF EXISTS market_indices;
@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- 市值加权的综合指数：指数点位 = 成分股总市值 / 除数，成分股调入调出时调整除数使点位连续
+CREATE TABLE market_indices (
+    name VARCHAR(32) PRIMARY KEY,
+    currency VARCHAR(3) NOT NULL REFERENCES currencies(code),
+    members BIGINT[] NOT NULL,          -- 上次计算时的成分股
+    shares BIGINT[] NOT NULL,           -- 上次计算时各成分股的流通股数，与 members 一一对应，流通股数变化时据此调整除数
+    divisor BIGINT NOT NULL,            -- 定点整数，1000000 表示 1，避免浮点误差在多次调整中累积
+    updated_at TIMESTAMP NOT NULL
+);
+
+-- 指数的 K 线，周期和序号同 candles 表，点位的单位为 0.01 点
+CREATE TABLE index_candles (
+    index_name VARCHAR(32) NOT NULL REFERENCES market_indices(name),
+    period VARCHAR(3) NOT NULL,
+    bucket BIGINT NOT NULL,
+    open INTEGER NOT NULL,
+    high INTEGER NOT NULL,
+    low INTEGER NOT NULL,
+    close INTEGER NOT NULL,
+    PRIMARY KEY (index_name, period, bucket)
+);
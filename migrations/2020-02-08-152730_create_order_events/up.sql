@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- 委托的变化记录，用于重建任意时刻的委托簿。side 为 ask（买入）或 bid（卖出），
+-- kind 为 inserted、filled、amended、cancelled 或 expired，记录变化后委托的价格和未成交数量
+CREATE TABLE order_events (
+    id BIGSERIAL PRIMARY KEY,
+    order_id BIGINT NOT NULL,
+    side VARCHAR(3) NOT NULL,
+    stock_id BIGINT NOT NULL REFERENCES stocks(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    kind VARCHAR(16) NOT NULL,
+    price INTEGER NOT NULL,
+    volume BIGINT NOT NULL,
+    unfulfilled BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX order_events_stock_index ON order_events(stock_id, created_at);
+
+-- 已有的委托没有历史，以当前状态记为委托时刻的插入
+INSERT INTO order_events (order_id, side, stock_id, user_id, kind, price, volume, unfulfilled, created_at)
+SELECT id, 'ask', stock_id, user_id, 'inserted', price, volume, unfulfilled, created_at FROM user_ask_orders;
+INSERT INTO order_events (order_id, side, stock_id, user_id, kind, price, volume, unfulfilled, created_at)
+SELECT id, 'bid', stock_id, user_id, 'inserted', price, volume, unfulfilled, created_at FROM user_bid_orders;
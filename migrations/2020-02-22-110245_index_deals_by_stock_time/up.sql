@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- 按股票取最新成交价（最新成交、指数市值、融资账户）时按时间倒序扫描，不必排序全部成交记录
+CREATE INDEX deals_stock_time_index ON deals(stock_id, created_at, id);
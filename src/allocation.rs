@@ -0,0 +1,197 @@
+use crate::money::Price;
+
+// 超额认购时新股的配售方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Allocation {
+    Lottery,    // 按签摇号，每签一手
+    ProRata     // 按认购数量比例配售，按手取整
+}
+
+impl Allocation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Allocation::Lottery => "lottery",
+            Allocation::ProRata => "pro_rata",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Allocation> {
+        match s {
+            "lottery" => Some(Allocation::Lottery),
+            "pro_rata" => Some(Allocation::ProRata),
+            _ => None
+        }
+    }
+
+    // requests 为每笔认购的手数，返回每笔认购配售到的手数
+    pub fn allocate(&self, requests: &[i64], available: i64, seed: u64) -> Vec<i64> {
+        match self {
+            Allocation::Lottery => lottery(requests, available, seed),
+            Allocation::ProRata => pro_rata(requests, available),
+        }
+    }
+}
+
+// 确定性的伪随机数发生器（SplitMix64），公布种子后任何人都能复现摇号结果
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        SeededRng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // [0, bound) 中的随机数
+    pub fn below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+// 从 population 个签中不放回地抽 draws 个，其中 successes 个是中签号，返回抽到的中签号个数
+fn hypergeometric(rng: &mut SeededRng, mut population: i64, mut successes: i64, draws: i64) -> i64 {
+    let mut hits = 0;
+    for _ in 0..draws {
+        if (rng.below(population as u64) as i64) < successes {
+            hits += 1;
+            successes -= 1;
+        }
+        population -= 1;
+    }
+    hits
+}
+
+// 摇号：available 个中签号在所有签中等概率不放回地抽取
+pub fn lottery(requests: &[i64], available: i64, seed: u64) -> Vec<i64> {
+    let total: i64 = requests.iter().sum();
+    if total <= available {
+        return requests.to_vec();
+    }
+
+    let mut rng = SeededRng::new(seed);
+    let mut population = total;
+    let mut winners = available;
+    requests.iter().map(|&lots| {
+        // 超几何分布关于抽取数和中签数对称，取较小者作为抽取次数
+        let won = hypergeometric(&mut rng, population, std::cmp::max(lots, winners), std::cmp::min(lots, winners));
+        population -= lots;
+        winners -= won;
+        won
+    }).collect()
+}
+
+// 比例配售：先按比例向下取整，剩余的手数按舍去部分从大到小逐笔补一手，相同时先认购者优先
+pub fn pro_rata(requests: &[i64], available: i64) -> Vec<i64> {
+    let total: i64 = requests.iter().sum();
+    if total <= available {
+        return requests.to_vec();
+    }
+
+    let mut allocated: Vec<i64> = requests.iter()
+        .map(|&lots| (lots as i128 * available as i128 / total as i128) as i64)
+        .collect();
+    let mut leftover = available - allocated.iter().sum::<i64>();
+
+    let mut order: Vec<usize> = (0..requests.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(requests[i] as i128 * available as i128 % total as i128));
+    for i in order {
+        if leftover == 0 {
+            break;
+        }
+        if allocated[i] < requests[i] {
+            allocated[i] += 1;
+            leftover -= 1;
+        }
+    }
+
+    allocated
+}
+
+// 荷兰式拍卖：bids 为每笔投标的 (价格, 手数)，按价格从高到低累计，累计手数达到发行量时的价格即清算价格；
+// 投标不足时以最低投标价格清算。高于清算价格的投标全额配售，等于清算价格的投标按 method 分配剩余的手数
+pub fn auction(bids: &[(Price, i64)], available: i64, method: Allocation, seed: u64) -> (Option<Price>, Vec<i64>) {
+    let mut prices: Vec<Price> = bids.iter().map(|&(price, _)| price).collect();
+    prices.sort_by(|a, b| b.cmp(a));
+    prices.dedup();
+
+    let mut clearing = None;
+    for price in prices {
+        clearing = Some(price);
+        let demand: i64 = bids.iter().filter(|&&(bid, _)| bid >= price).map(|&(_, lots)| lots).sum();
+        if demand >= available {
+            break;
+        }
+    }
+
+    let clearing_price = match clearing {
+        Some(price) => price,
+        None => return (None, Vec::new())
+    };
+
+    let mut allocated: Vec<i64> = bids.iter()
+        .map(|&(price, lots)| if price > clearing_price { lots } else { 0 })
+        .collect();
+    let remaining = available - allocated.iter().sum::<i64>();
+
+    let marginal: Vec<usize> = (0..bids.len()).filter(|&i| bids[i].0 == clearing_price).collect();
+    let requests: Vec<i64> = marginal.iter().map(|&i| bids[i].1).collect();
+    for (i, lots) in marginal.into_iter().zip(method.allocate(&requests, remaining, seed)) {
+        allocated[i] = lots;
+    }
+
+    (Some(clearing_price), allocated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pro_rata() {
+        assert_eq!(pro_rata(&[3, 4], 10), vec![3, 4]);
+        // 10 手按 5:3:2 分配 7 手：3.5、2.1、1.4
+        assert_eq!(pro_rata(&[5, 3, 2], 7), vec![4, 2, 1]);
+        // 舍去部分相同时先认购者优先
+        assert_eq!(pro_rata(&[1, 1, 1], 2), vec![1, 1, 0]);
+    }
+
+    #[test]
+    fn test_lottery() {
+        assert_eq!(lottery(&[3, 4], 10, 42), vec![3, 4]);
+
+        let requests = [5, 30, 1, 64];
+        let won = lottery(&requests, 20, 42);
+        assert_eq!(won.iter().sum::<i64>(), 20);
+        assert!(won.iter().zip(requests.iter()).all(|(w, r)| *w >= 0 && w <= r));
+        // 相同的种子得到相同的结果
+        assert_eq!(won, lottery(&requests, 20, 42));
+    }
+
+    #[test]
+    fn test_auction() {
+        assert_eq!(auction(&[], 10, Allocation::ProRata, 0), (None, vec![]));
+
+        // 发行 10 手：10.50 元 4 手，10.20 元 5 手，10.00 元 6 手，清算价 10.00 元，剩余 1 手按比例给 10.00 元的投标
+        let bids = [(Price(1000), 6), (Price(1050), 4), (Price(1020), 5), (Price(990), 3)];
+        assert_eq!(auction(&bids, 10, Allocation::ProRata, 0), (Some(Price(1000)), vec![1, 4, 5, 0]));
+
+        // 恰好售完时清算价为累计手数达到发行量的价格
+        assert_eq!(auction(&bids, 9, Allocation::ProRata, 0), (Some(Price(1020)), vec![0, 4, 5, 0]));
+
+        // 投标不足时以最低投标价格全部成交
+        assert_eq!(auction(&bids, 100, Allocation::Lottery, 0), (Some(Price(990)), vec![6, 4, 5, 3]));
+    }
+
+    #[test]
+    fn test_allocation_names() {
+        assert_eq!(Allocation::from_name(Allocation::ProRata.as_str()), Some(Allocation::ProRata));
+        assert_eq!(Allocation::from_name("first_come"), None);
+    }
+}
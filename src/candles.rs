@@ -0,0 +1,163 @@
+use crate::money::{Money, Price};
+
+// K 线周期，时间均为 UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interval {
+    OneMinute,
+    FiveMinutes,
+    FifteenMinutes,
+    OneHour,
+    OneDay,
+    OneWeek
+}
+
+// 1970-01-01 是星期四，周线从其后的星期一开始计算
+const WEEK_ORIGIN_SECS : i64 = 4 * 86400;
+
+impl Interval {
+    // 预先汇总到 candles 表的全部周期
+    pub const ALL : [Interval; 6] = [
+        Interval::OneMinute,
+        Interval::FiveMinutes,
+        Interval::FifteenMinutes,
+        Interval::OneHour,
+        Interval::OneDay,
+        Interval::OneWeek
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Interval::OneMinute => "1m",
+            Interval::FiveMinutes => "5m",
+            Interval::FifteenMinutes => "15m",
+            Interval::OneHour => "1h",
+            Interval::OneDay => "1d",
+            Interval::OneWeek => "1w",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Interval> {
+        match s {
+            "1m" => Some(Interval::OneMinute),
+            "5m" => Some(Interval::FiveMinutes),
+            "15m" => Some(Interval::FifteenMinutes),
+            "1h" => Some(Interval::OneHour),
+            "1d" => Some(Interval::OneDay),
+            "1w" => Some(Interval::OneWeek),
+            _ => None
+        }
+    }
+
+    pub fn seconds(&self) -> i64 {
+        match self {
+            Interval::OneMinute => 60,
+            Interval::FiveMinutes => 5 * 60,
+            Interval::FifteenMinutes => 15 * 60,
+            Interval::OneHour => 3600,
+            Interval::OneDay => 86400,
+            Interval::OneWeek => 7 * 86400,
+        }
+    }
+
+    // 第 0 根 K 线开始时刻相对 UNIX 纪元的秒数
+    pub fn origin(&self) -> i64 {
+        match self {
+            Interval::OneWeek => WEEK_ORIGIN_SECS,
+            _ => 0
+        }
+    }
+
+    // ts 所在 K 线的序号
+    pub fn bucket(&self, ts: chrono::NaiveDateTime) -> i64 {
+        (ts.timestamp() - self.origin()).div_euclid(self.seconds())
+    }
+
+    pub fn bucket_start(&self, bucket: i64) -> chrono::NaiveDateTime {
+        chrono::NaiveDateTime::from_timestamp(bucket * self.seconds() + self.origin(), 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Candle {
+    pub time: chrono::NaiveDateTime,    // K 线开始时刻
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: i64,
+    pub turnover: Money
+}
+
+// 将有成交的 K 线（按序号升序）补齐为 [first, last) 的连续序列：没有成交的 K 线开高低收都取前一根的收盘价，
+// prev_close 为 first 之前最后一笔成交的价格；第一笔成交之前的 K 线没有价格，不返回
+pub fn fill_gaps(candles: Vec<(i64, Candle)>, first: i64, last: i64, interval: Interval, prev_close: Option<Price>) -> Vec<Candle> {
+    let mut filled = Vec::new();
+    let mut close = prev_close;
+    let mut candles = candles.into_iter().peekable();
+
+    for bucket in first..last {
+        match candles.peek() {
+            Some((index, _)) if *index == bucket => {
+                let (_, candle) = candles.next().unwrap();
+                close = Some(candle.close);
+                filled.push(candle);
+            },
+            _ => if let Some(price) = close {
+                filled.push(Candle {
+                    time: interval.bucket_start(bucket),
+                    open: price,
+                    high: price,
+                    low: price,
+                    close: price,
+                    volume: 0,
+                    turnover: Money::zero()
+                });
+            }
+        }
+    }
+
+    filled
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candle(interval: Interval, bucket: i64, open: i32, close: i32) -> (i64, Candle) {
+        (bucket, Candle {
+            time: interval.bucket_start(bucket),
+            open: Price(open),
+            high: Price(std::cmp::max(open, close)),
+            low: Price(std::cmp::min(open, close)),
+            close: Price(close),
+            volume: 100,
+            turnover: Money(100 * open as i64)
+        })
+    }
+
+    #[test]
+    fn test_buckets() {
+        let ts = chrono::NaiveDate::from_ymd(2020, 1, 22).and_hms(10, 37, 15);
+        assert_eq!(Interval::FifteenMinutes.bucket_start(Interval::FifteenMinutes.bucket(ts)), chrono::NaiveDate::from_ymd(2020, 1, 22).and_hms(10, 30, 0));
+        assert_eq!(Interval::OneDay.bucket_start(Interval::OneDay.bucket(ts)), chrono::NaiveDate::from_ymd(2020, 1, 22).and_hms(0, 0, 0));
+        // 2020-01-22 是星期三，周线从星期一开始
+        assert_eq!(Interval::OneWeek.bucket_start(Interval::OneWeek.bucket(ts)), chrono::NaiveDate::from_ymd(2020, 1, 20).and_hms(0, 0, 0));
+        assert_eq!(Interval::from_name(Interval::OneHour.as_str()), Some(Interval::OneHour));
+        assert_eq!(Interval::from_name("2h"), None);
+    }
+
+    #[test]
+    fn test_fill_gaps() {
+        let interval = Interval::OneMinute;
+        let filled = fill_gaps(vec![candle(interval, 11, 1000, 1010), candle(interval, 13, 990, 1000)], 10, 15, interval, Some(Price(995)));
+        assert_eq!(filled.len(), 5);
+        assert_eq!((filled[0].open, filled[0].close, filled[0].volume), (Price(995), Price(995), 0));
+        assert_eq!(filled[1], candle(interval, 11, 1000, 1010).1);
+        assert_eq!((filled[2].time, filled[2].high, filled[2].low), (interval.bucket_start(12), Price(1010), Price(1010)));
+        assert_eq!(filled[4].close, Price(1000));
+
+        // 第一笔成交之前没有价格
+        let filled = fill_gaps(vec![candle(interval, 12, 1000, 1010)], 10, 14, interval, None);
+        assert_eq!(filled.iter().map(|c| c.time).collect::<Vec<_>>(), vec![interval.bucket_start(12), interval.bucket_start(13)]);
+    }
+}
@@ -1 +1,15 @@
-pub type Pool = r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>;
\ No newline at end of file
+pub type Pool = r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>;
+
+// 读取非负整数的环境变量，未设置时为 default，格式错误或为负数时拒绝启动
+pub(crate) fn env_non_negative<T>(key: &str, default: T) -> T
+where
+    T: std::str::FromStr + PartialOrd + Default
+{
+    match std::env::var(key) {
+        Ok(value) => match value.parse::<T>() {
+            Ok(number) if number >= T::default() => number,
+            _ => panic!("环境变量 {} 必须是非负整数！", key)
+        },
+        Err(_) => default
+    }
+}
\ No newline at end of file
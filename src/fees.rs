@@ -0,0 +1,200 @@
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use crate::errors::EngineError;
+use crate::common::env_non_negative;
+use crate::money::{Money, Price};
+
+// 费率的单位：百万分之一。例如万分之二点五的佣金写作 250，千分之一的印花税写作 1000
+pub const RATE_UNIT : i64 = 1_000_000;
+
+const DEFAULT_HOUSE_USER_NAME : &str = "__house__";
+
+#[derive(Debug, Clone)]
+pub struct FeeSchedule {
+    pub maker_rate: i64,        // 挂单方（已在委托簿中的委托）佣金费率
+    pub taker_rate: i64,        // 吃单方（新进入的委托）佣金费率
+    pub min_commission: Money,  // 最低佣金，买卖双方都按每笔委托计算
+    pub stamp_duty_rate: i64,   // 卖方印花税费率
+    pub house_user_id: i64,     // 收取手续费的平台账户
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DealFees {
+    pub buyer_commission: Money,
+    pub seller_commission: Money,
+    pub stamp_duty: Money,
+}
+
+impl DealFees {
+    pub fn total(&self) -> Money {
+        self.buyer_commission + self.seller_commission + self.stamp_duty
+    }
+}
+
+// 按费率计算，不足一分按一分计
+fn apply_rate(value: Money, rate: i64) -> Money {
+    value.ratio_ceil(rate, RATE_UNIT)
+}
+
+impl FeeSchedule {
+    pub fn from_env(conn: &PgConnection) -> Result<FeeSchedule, EngineError> {
+        use crate::schema::users::dsl as usrdsl;
+
+        let house_user_name = std::env::var("HOUSE_USER_NAME").unwrap_or(DEFAULT_HOUSE_USER_NAME.to_owned());
+
+        let house_user_id = usrdsl::users
+            .filter(usrdsl::name.eq(&house_user_name))
+            .select(usrdsl::id)
+            .get_result::<i64>(conn)
+            .optional()
+            .map_err(|db_err| EngineError::InternalError(format!("数据库查询错误：{}", db_err)))?
+            .ok_or_else(|| EngineError::InternalError(format!("找不到收取手续费的平台账户 {}，请检查 HOUSE_USER_NAME 或是否执行了 diesel migration run。", house_user_name)))?;
+
+        Ok(FeeSchedule {
+            maker_rate: env_non_negative("FEE_MAKER_RATE", 0),
+            taker_rate: env_non_negative("FEE_TAKER_RATE", 0),
+            min_commission: Money(env_non_negative("FEE_MIN_COMMISSION", 0)),
+            stamp_duty_rate: env_non_negative("FEE_STAMP_DUTY_RATE", 0),
+            house_user_id,
+        })
+    }
+
+    fn rate(&self, liquidity: Liquidity) -> i64 {
+        match liquidity {
+            Liquidity::Maker => self.maker_rate,
+            Liquidity::Taker => self.taker_rate,
+        }
+    }
+
+    // 单笔成交的佣金，不低于最低佣金，但不超过成交金额
+    pub fn commission(&self, value: Money, liquidity: Liquidity) -> Money {
+        std::cmp::min(std::cmp::max(apply_rate(value, self.rate(liquidity)), self.min_commission), value)
+    }
+
+    // 买入委托一笔成交的佣金。paid 为该委托已收取的佣金，最低佣金按整笔委托计算，由第一笔成交补足；
+    // frozen 为委托剩余冻结的佣金，一笔委托的佣金总额不超过提交时冻结的佣金
+    pub fn buy_commission(&self, value: Money, liquidity: Liquidity, paid: Money, frozen: Money) -> Money {
+        let commission = std::cmp::max(apply_rate(value, self.rate(liquidity)), self.min_commission - paid);
+        commission.min(value).min(frozen).max(Money::zero())
+    }
+
+    // 卖出委托一笔成交的佣金。paid 为该委托已收取的佣金，最低佣金按整笔委托计算，由第一笔成交补足；不超过成交金额
+    pub fn sell_commission(&self, value: Money, liquidity: Liquidity, paid: Money) -> Money {
+        let commission = std::cmp::max(apply_rate(value, self.rate(liquidity)), self.min_commission - paid);
+        commission.min(value).max(Money::zero())
+    }
+
+    pub fn stamp_duty(&self, value: Money) -> Money {
+        apply_rate(value, self.stamp_duty_rate)
+    }
+
+    // 一笔撮合成交的全部费用；卖方的费用总额不超过成交金额，buyer_paid、buyer_frozen 为买入委托已收取和剩余冻结的佣金，
+    // seller_paid 为卖出委托已收取的佣金
+    pub fn deal_fees(&self, value: Money, buyer: Liquidity, buyer_paid: Money, buyer_frozen: Money, seller_paid: Money) -> DealFees {
+        let seller = match buyer {
+            Liquidity::Maker => Liquidity::Taker,
+            Liquidity::Taker => Liquidity::Maker,
+        };
+        let seller_commission = self.sell_commission(value, seller, seller_paid);
+        DealFees {
+            buyer_commission: self.buy_commission(value, buyer, buyer_paid, buyer_frozen),
+            seller_commission,
+            stamp_duty: std::cmp::min(self.stamp_duty(value), value - seller_commission),
+        }
+    }
+
+    // 买入委托提交时需要冻结的佣金，按挂单、吃单中较高的费率估算，也是该委托佣金总额的上限
+    pub fn buy_fee_reserve(&self, value: Money) -> Money {
+        self.commission(value, Liquidity::Maker).max(self.commission(value, Liquidity::Taker))
+    }
+
+    // 以 price 买入时，budget 连同冻结的佣金最多能买的股数
+    pub fn affordable_volume(&self, budget: Money, price: Price) -> i64 {
+        if price <= Price(0) || budget <= Money::zero() {
+            return 0;
+        }
+        let (mut low, mut high) = (0, budget.0 / price.0 as i64);
+        while low < high {
+            let mid = high - (high - low) / 2;
+            let value = price.value(mid);
+            if value + self.buy_fee_reserve(value) <= budget {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn schedule() -> FeeSchedule {
+        FeeSchedule {
+            maker_rate: 200,
+            taker_rate: 300,
+            min_commission: Money(500),
+            stamp_duty_rate: 1000,
+            house_user_id: 1,
+        }
+    }
+
+    #[test]
+    fn test_deal_fees() {
+        let fees = schedule();
+        // 10 万元成交：吃单佣金 30 元，挂单佣金 20 元，印花税 100 元
+        assert_eq!(fees.deal_fees(Money(10_000_000), Liquidity::Taker, Money::zero(), Money(10_000_000), Money::zero()), DealFees { buyer_commission: Money(3000), seller_commission: Money(2000), stamp_duty: Money(10000) });
+        assert_eq!(fees.deal_fees(Money(10_000_000), Liquidity::Maker, Money::zero(), Money(10_000_000), Money::zero()), DealFees { buyer_commission: Money(2000), seller_commission: Money(3000), stamp_duty: Money(10000) });
+        // 小额成交收取最低佣金，印花税不足一分按一分计
+        assert_eq!(fees.deal_fees(Money(10_001), Liquidity::Taker, Money::zero(), Money(10_000_000), Money::zero()), DealFees { buyer_commission: Money(500), seller_commission: Money(500), stamp_duty: Money(11) });
+        // 卖出委托已在之前的成交中付足最低佣金，之后只按费率收取
+        assert_eq!(fees.deal_fees(Money(10_001), Liquidity::Taker, Money::zero(), Money(10_000_000), Money(500)), DealFees { buyer_commission: Money(500), seller_commission: Money(3), stamp_duty: Money(11) });
+        // 费用不超过成交金额
+        assert_eq!(fees.deal_fees(Money(300), Liquidity::Taker, Money::zero(), Money(10_000_000), Money::zero()), DealFees { buyer_commission: Money(300), seller_commission: Money(300), stamp_duty: Money(0) });
+        assert_eq!(fees.buy_fee_reserve(Money(10_000_000)), Money(3000));
+    }
+
+    #[test]
+    fn test_buy_commission() {
+        let fees = schedule();
+        // 1 万元的委托冻结最低佣金 5 元，分两笔成交：第一笔补足最低佣金，之后冻结的佣金已用完，总额不超过冻结的佣金
+        let reserve = fees.buy_fee_reserve(Money(1_000_000));
+        assert_eq!(reserve, Money(500));
+        assert_eq!(fees.buy_commission(Money(100_000), Liquidity::Taker, Money::zero(), reserve), Money(500));
+        assert_eq!(fees.buy_commission(Money(400_000), Liquidity::Taker, Money(500), Money::zero()), Money::zero());
+        // 大额委托：达到最低佣金后按费率收取，最后一笔的进位不超过剩余冻结的佣金
+        assert_eq!(fees.buy_commission(Money(10_001), Liquidity::Maker, Money::zero(), Money(3000)), Money(500));
+        assert_eq!(fees.buy_commission(Money(3_000_000), Liquidity::Taker, Money(500), Money(2500)), Money(900));
+        assert_eq!(fees.buy_commission(Money(6_989_999), Liquidity::Taker, Money(1400), Money(1600)), Money(1600));
+    }
+
+    #[test]
+    fn test_sell_commission() {
+        let fees = schedule();
+        // 1 万元的卖出委托分两笔成交：第一笔补足最低佣金 5 元，第二笔只按费率收取
+        assert_eq!(fees.sell_commission(Money(100_000), Liquidity::Maker, Money::zero()), Money(500));
+        assert_eq!(fees.sell_commission(Money(900_000), Liquidity::Maker, Money(500)), Money(180));
+        // 第一笔不足最低佣金的部分由下一笔补足
+        assert_eq!(fees.sell_commission(Money(300), Liquidity::Maker, Money::zero()), Money(300));
+        assert_eq!(fees.sell_commission(Money(100_000), Liquidity::Maker, Money(300)), Money(200));
+    }
+
+    #[test]
+    fn test_affordable_volume() {
+        let fees = schedule();
+        // 1 万元按 10 元买入：1000 股需 1 万元加最低佣金 5 元，超出预算
+        assert_eq!(fees.affordable_volume(Money(1_000_000), Price(1000)), 999);
+        assert_eq!(fees.affordable_volume(Money(1_000_500), Price(1000)), 1000);
+        assert_eq!(fees.affordable_volume(Money(400), Price(1000)), 0);
+        assert_eq!(fees.affordable_volume(Money(1_000_000), Price(0)), 0);
+    }
+}
@@ -0,0 +1,26 @@
+SELECT
+    book.id AS id,
+    book.price AS price,
+    book.unfulfilled AS unfulfilled,
+    book.created_at AS created_at
+FROM (
+    SELECT
+        user_ask_orders.id,
+        user_ask_orders.price,
+        user_ask_orders.unfulfilled,
+        user_ask_orders.created_at,
+        DENSE_RANK() OVER (ORDER BY user_ask_orders.price DESC) AS level
+    FROM user_ask_orders
+    WHERE
+        user_ask_orders.stock_id = $1
+            AND
+        user_ask_orders.unfulfilled != 0
+) AS book
+WHERE
+    $2 IS NULL
+        OR
+    book.level <= $2
+ORDER BY
+    book.price DESC,
+    book.created_at ASC,
+    book.id ASC;
\ No newline at end of file
@@ -10,4 +10,4 @@ GROUP BY
     user_ask_orders.price
 ORDER BY
     user_ask_orders.price DESC
-LIMIT 5;
\ No newline at end of file
+LIMIT $2;
\ No newline at end of file
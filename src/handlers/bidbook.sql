@@ -0,0 +1,26 @@
+SELECT
+    book.id AS id,
+    book.price AS price,
+    book.unfulfilled AS unfulfilled,
+    book.created_at AS created_at
+FROM (
+    SELECT
+        user_bid_orders.id,
+        user_bid_orders.price,
+        user_bid_orders.unfulfilled,
+        user_bid_orders.created_at,
+        DENSE_RANK() OVER (ORDER BY user_bid_orders.price ASC) AS level
+    FROM user_bid_orders
+    WHERE
+        user_bid_orders.stock_id = $1
+            AND
+        user_bid_orders.unfulfilled != 0
+) AS book
+WHERE
+    $2 IS NULL
+        OR
+    book.level <= $2
+ORDER BY
+    book.price ASC,
+    book.created_at ASC,
+    book.id ASC;
\ No newline at end of file
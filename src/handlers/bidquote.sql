@@ -10,4 +10,4 @@ GROUP BY
     user_bid_orders.price
 ORDER BY
     user_bid_orders.price ASC
-LIMIT 5;
\ No newline at end of file
+LIMIT $2;
\ No newline at end of file
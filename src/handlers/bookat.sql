@@ -0,0 +1,43 @@
+SELECT
+    book.order_id AS id,
+    book.price AS price,
+    book.unfulfilled AS unfulfilled,
+    book.placed_at AS created_at
+FROM (
+    SELECT
+        latest.order_id,
+        latest.price,
+        latest.unfulfilled,
+        latest.placed_at,
+        DENSE_RANK() OVER (ORDER BY CASE WHEN $2 = 'ask' THEN -latest.price ELSE latest.price END) AS level
+    FROM (
+        SELECT DISTINCT ON (order_events.order_id)
+            order_events.order_id,
+            order_events.kind,
+            order_events.price,
+            order_events.unfulfilled,
+            MIN(order_events.created_at) OVER (PARTITION BY order_events.order_id) AS placed_at
+        FROM order_events
+        WHERE
+            order_events.stock_id = $1
+                AND
+            order_events.side = $2
+                AND
+            order_events.created_at <= $3
+        ORDER BY
+            order_events.order_id,
+            order_events.id DESC
+    ) AS latest
+    WHERE
+        latest.unfulfilled != 0
+            AND
+        latest.kind NOT IN ('cancelled', 'expired')
+) AS book
+WHERE
+    $4 IS NULL
+        OR
+    book.level <= $4
+ORDER BY
+    CASE WHEN $2 = 'ask' THEN -book.price ELSE book.price END,
+    book.placed_at ASC,
+    book.order_id ASC;
\ No newline at end of file
@@ -0,0 +1,543 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+use crate::models::{Buyback, AskOrder};
+use crate::money::{Money, Price, price_tick};
+use crate::fees::FeeSchedule;
+use crate::settlement::SettlementSchedule;
+use crate::margin::MarginSettings;
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::wallets::add_balance;
+use super::orders::{AskOrderModel, EngineOrder, match_ask, publish_engine_orders};
+use super::margin::enforce_maintenance;
+use super::market::MarketFeed;
+use super::notify::{UserFeed, OrderUpdate, OrderStatus};
+use super::history::{OrderEventKind, record_ask};
+use super::PagingModel;
+
+use crate::schema::*;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/buybacks")
+        .service(
+            web::resource("/")  // Scope 会自动加尾 /，所以 /buybacks 无法匹配
+                .route(web::post().to_async(create_buyback))       // 发行人宣布回购计划
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/stock/{id}")
+                .route(web::get().to_async(get_stock_buybacks))     // 查询某只股票的历次回购
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/{id}")
+                .route(web::delete().to_async(close_buyback))     // 发行人终止回购计划
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+// 为一个回购计划挂出买入委托：委托簿中已有未成交的回购委托时不做处理；
+// 否则按剩余预算能买到的最大股数以价格上限挂单，成交后剩余的预算再挂新的委托，预算用完时结束回购计划。
+// 返回回购委托和由此引起的强制平仓委托的撮合结果，须在事务提交后推送
+pub fn manage_buyback(buyback_id: i64, fees: &FeeSchedule, settlement: &SettlementSchedule, margin: &MarginSettings, conn: &PgConnection) -> Result<Vec<EngineOrder>, EngineError> {
+    use crate::schema::buybacks::dsl as bbdsl;
+    use crate::schema::user_ask_orders::dsl as askdsl;
+
+    let mut orders = Vec::new();
+    conn.transaction::<_, EngineError, _>(|| {
+        // 保证原子性
+        loop {
+            let query = bbdsl::buybacks
+                            .find(buyback_id)
+                            .for_update();
+
+            debug!("Manage buyback SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+            let mut buyback = query.get_result::<Buyback>(conn)
+                .optional()
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+                })?
+                .ok_or_else(|| EngineError::NotFound(format!("没有该回购计划！")))?;
+
+            if buyback.closed_at.is_some() {
+                return Ok(());
+            }
+
+            let query = askdsl::user_ask_orders
+                            .filter(
+                                askdsl::buyback_id.eq(buyback_id).and(
+                                    askdsl::unfulfilled.ne(0)
+                                )
+                            )
+                            .count();
+
+            debug!("Open buyback orders SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+            let open_orders = query.get_result::<i64>(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+                })?;
+
+            if open_orders > 0 {
+                return Ok(());
+            }
+
+            let volume = fees.affordable_volume(buyback.budget - buyback.spent, buyback.max_price);
+            if volume == 0 {
+                info!("Buyback {} of stock {} finished, repurchased {} shares", buyback.id, buyback.stock_id, buyback.repurchased);
+                buyback.closed_at = Some(chrono::Utc::now().naive_utc());
+                buyback.save_changes::<Buyback>(conn).map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库结束回购计划错误：{}", db_err))
+                })?;
+                return Ok(());
+            }
+
+            // 与普通买入委托一样冻结委托金额和预估佣金
+            let order_value = buyback.max_price.value(volume);
+            let fee_reserve = fees.buy_fee_reserve(order_value);
+            let balance_after = add_balance(buyback.issuer_id, &buyback.currency, -(order_value + fee_reserve), conn)?;
+            if balance_after < Money::zero() {
+                // 发行人余额不足时暂停挂单，余额充足后由后台任务继续
+                add_balance(buyback.issuer_id, &buyback.currency, order_value + fee_reserve, conn)?;
+                warn!("Buyback {} paused, issuer {} lacks {} {}", buyback.id, buyback.issuer_id, -balance_after, buyback.currency);
+                return Ok(());
+            }
+
+            let now = chrono::Utc::now().naive_utc();
+            let query = diesel::insert_into(askdsl::user_ask_orders)
+                            .values(AskOrderModel {
+                                user_id: buyback.issuer_id,
+                                stock_id: buyback.stock_id,
+                                price: buyback.max_price,
+                                volume,
+                                unfulfilled: volume,
+                                created_at: now,
+                                updated_at: now,
+                                fee_frozen: fee_reserve,
+                                cover: false,
+                                buyback_id: Some(buyback.id)
+                            });
+
+            debug!("New buyback order SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+            let mut new_ask = query.get_result::<AskOrder>(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库插入委托错误：{}", db_err))
+                })?;
+            record_ask(OrderEventKind::Inserted, &new_ask, conn)?;
+
+            let accepted = OrderUpdate::from_ask(&new_ask);
+            let matched = match_ask(&mut new_ask, &buyback.currency, fees, settlement, conn)?;
+            let traded = !matched.deals.is_empty();
+            orders.push(EngineOrder { stock_id: buyback.stock_id, accepted, taker: OrderUpdate::from_ask(&new_ask), matched });
+
+            // 成交价变化后，检查持有该股票的融资用户的维持担保比例
+            if traded {
+                orders.extend(enforce_maintenance(&buyback.currency, buyback.stock_id, margin, fees, settlement, conn)?);
+            }
+        }
+    })?;
+
+    Ok(orders)
+}
+
+// 回购成交的股票注销：计入回购进度，并从流通股本中扣除
+pub fn retire_shares(buyback_id: i64, stock_id: i64, amount: i64, cost: Money, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::buybacks::dsl as bbdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+
+    let query = diesel::update(bbdsl::buybacks.find(buyback_id))
+                    .set((
+                        bbdsl::repurchased.eq(bbdsl::repurchased + amount),
+                        bbdsl::spent.eq(bbdsl::spent + cost)
+                    ));
+
+    debug!("Buyback progress SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库更新回购进度错误：{}", db_err))
+        })?;
+
+    let query = diesel::update(newdsl::new_stocks.find(stock_id))
+                    .set(newdsl::offer_circ.eq(newdsl::offer_circ - amount));
+
+    debug!("Retire shares SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库注销股票错误：{}", db_err))
+        })?;
+
+    Ok(())
+}
+
+// 某只股票进行中的回购计划
+pub fn active_buybacks(stock_id: i64, conn: &PgConnection) -> Result<Vec<Buyback>, EngineError> {
+    use crate::schema::buybacks::dsl as bbdsl;
+
+    let query = bbdsl::buybacks
+                    .filter(
+                        bbdsl::stock_id.eq(stock_id).and(
+                            bbdsl::closed_at.is_null()
+                        )
+                    )
+                    .order(bbdsl::created_at.asc());
+
+    debug!("Active buybacks SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<Buyback>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+// 为所有进行中的回购计划补挂委托，每个回购计划提交后推送成交
+pub fn process_buybacks(fees: &FeeSchedule, settlement: &SettlementSchedule, margin: &MarginSettings, feed: &MarketFeed, notify: &UserFeed, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::buybacks::dsl as bbdsl;
+
+    let query = bbdsl::buybacks
+                    .filter(bbdsl::closed_at.is_null())
+                    .order(bbdsl::created_at.asc())
+                    .select(bbdsl::id);
+
+    debug!("Buybacks to manage SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let to_manage = query.get_results::<i64>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    for buyback_id in to_manage {
+        let orders = manage_buyback(buyback_id, fees, settlement, margin, conn)?;
+        publish_engine_orders(&orders, feed, notify, conn);
+    }
+
+    Ok(())
+}
+
+pub fn run_buyback_worker(pool: Pool, fees: FeeSchedule, settlement: SettlementSchedule, margin: MarginSettings, feed: MarketFeed, notify: UserFeed, interval: std::time::Duration) {
+    std::thread::spawn(move || {
+        loop {
+            match pool.get() {
+                Ok(conn) => {
+                    if let Err(err) = process_buybacks(&fees, &settlement, &margin, &feed, &notify, &conn) {
+                        error!("Processing buybacks failed: {}", err);
+                    }
+                },
+                Err(pool_err) => error!("Processing buybacks failed, cannot get database connection: {}", pool_err)
+            }
+            std::thread::sleep(interval);
+        }
+    });
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct CreateBuybackModel {
+    pub stock_id: u64,
+    pub budget: Money,      // 含佣金
+    pub max_price: Price
+}
+
+pub fn create_buyback(
+    create: web::Json<CreateBuybackModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    fees: web::Data<FeeSchedule>,
+    settlement: web::Data<SettlementSchedule>,
+    margin: web::Data<MarginSettings>,
+    feed: web::Data<MarketFeed>,
+    notify: web::Data<UserFeed>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            create_buyback_query(create.into_inner(), curr_user, pool, fees, settlement, margin, feed, notify)
+        }
+    ).then(
+        move |res: Result<Buyback, BlockingError<EngineError>>|
+            match res {
+                Ok(buyback) => Ok(HttpResponse::Ok().json(buyback)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn create_buyback_query(create: CreateBuybackModel, user: RememberUserModel, pool: web::Data<Pool>, fees: web::Data<FeeSchedule>, settlement: web::Data<SettlementSchedule>, margin: web::Data<MarginSettings>, feed: web::Data<MarketFeed>, notify: web::Data<UserFeed>) -> Result<Buyback, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+    use crate::schema::buybacks::dsl as bbdsl;
+
+    let stock_id = i64::try_from(create.stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    if create.budget <= Money::zero() || create.max_price <= Price(0) {
+        return Err(EngineError::BadRequest(format!("回购预算和价格上限必须为正数！")));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let (buyback, orders) = conn.transaction::<_, EngineError, _>(|| {
+        // 保证原子性
+        // 只有已上市股票的发行人可以回购
+        let query = stkdsl::stocks
+                        .inner_join(newdsl::new_stocks)
+                        .filter(
+                            stkdsl::id.eq(stock_id).and(
+                                stkdsl::into_market.eq(true)
+                            )
+                        )
+                        .select((newdsl::issuer_id, stkdsl::currency, stkdsl::price_decimals))
+                        .for_update();
+
+        debug!("Create buyback stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let (issuer_id, currency, price_decimals) = query.get_result::<(i64, String, i16)>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::BadRequest(format!("该股票还未上市，不能回购！")))?;
+
+        if issuer_id != user.id {
+            return Err(EngineError::Unauthorized(format!("只有该股票的发行人可以回购。")));
+        }
+
+        if !create.max_price.is_on_tick(price_decimals) {
+            return Err(EngineError::BadRequest(format!("回购价格上限 {} 元不符合该股票的最小变动单位 {} 元！", create.max_price, Price(price_tick(price_decimals)))));
+        }
+
+        if fees.affordable_volume(create.budget, create.max_price) == 0 {
+            return Err(EngineError::BadRequest(format!("回购预算不足以按价格上限买入一股（含佣金）！")));
+        }
+
+        if !active_buybacks(stock_id, conn)?.is_empty() {
+            return Err(EngineError::BadRequest(format!("该股票已有进行中的回购计划，请在其结束或终止后再宣布新的回购。")));
+        }
+
+        let query = diesel::insert_into(bbdsl::buybacks)
+                        .values((
+                            bbdsl::stock_id.eq(stock_id),
+                            bbdsl::issuer_id.eq(issuer_id),
+                            bbdsl::currency.eq(currency),
+                            bbdsl::budget.eq(create.budget),
+                            bbdsl::max_price.eq(create.max_price),
+                            bbdsl::created_at.eq(chrono::Utc::now().naive_utc())
+                        ));
+
+        debug!("Create buyback SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let buyback = query.get_result::<Buyback>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库插入回购计划错误：{}", db_err))
+            })?;
+
+        // 立即挂出第一笔回购委托
+        let orders = manage_buyback(buyback.id, &fees, &settlement, &margin, conn)?;
+
+        let buyback = bbdsl::buybacks
+            .find(buyback.id)
+            .get_result::<Buyback>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        Ok((buyback, orders))
+    })?;
+
+    // 提交后推送回购委托的成交和盘口变化，以及相关用户的委托和账户变化
+    publish_engine_orders(&orders, &feed, &notify, conn);
+
+    Ok(buyback)
+}
+
+//////////////////
+pub fn close_buyback(
+    buyback_id: web::Path<u64>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    feed: web::Data<MarketFeed>,
+    notify: web::Data<UserFeed>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            close_buyback_query(buyback_id.into_inner(), curr_user, pool, feed, notify)
+        }
+    ).then(
+        move |res: Result<Buyback, BlockingError<EngineError>>|
+            match res {
+                Ok(buyback) => Ok(HttpResponse::Ok().json(buyback)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+// 终止回购计划：撤销未成交的回购委托并返还冻结的金额和佣金，已注销的股票不恢复
+fn close_buyback_query(buyback_id: u64, user: RememberUserModel, pool: web::Data<Pool>, feed: web::Data<MarketFeed>, notify: web::Data<UserFeed>) -> Result<Buyback, EngineError> {
+    use crate::schema::buybacks::dsl as bbdsl;
+    use crate::schema::user_ask_orders::dsl as askdsl;
+
+    let buyback_id = i64::try_from(buyback_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let (buyback, cancelled) = conn.transaction(|| {
+        // 保证原子性
+        let query = bbdsl::buybacks
+                        .find(buyback_id)
+                        .for_update();
+
+        debug!("Close buyback SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let mut buyback = query.get_result::<Buyback>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::NotFound(format!("没有该回购计划！")))?;
+
+        if buyback.issuer_id != user.id {
+            return Err(EngineError::Unauthorized(format!("只有该股票的发行人可以终止回购计划。")));
+        }
+        if buyback.closed_at.is_some() {
+            return Err(EngineError::BadRequest(format!("该回购计划已经结束！")));
+        }
+
+        let query = askdsl::user_ask_orders
+                        .filter(
+                            askdsl::buyback_id.eq(buyback_id).and(
+                                askdsl::unfulfilled.ne(0)
+                            )
+                        );
+
+        debug!("Open buyback orders SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let open_orders = query.get_results::<AskOrder>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let mut cancelled = Vec::new();
+        for order in open_orders {
+            add_balance(buyback.issuer_id, &buyback.currency, order.price.value(order.unfulfilled) + order.fee_frozen, conn)?;
+
+            let query = diesel::delete(askdsl::user_ask_orders.find(order.id));
+
+            debug!("Revoke buyback order SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+            query.execute(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库删除委托错误：{}", db_err))
+                })?;
+            record_ask(OrderEventKind::Cancelled, &order, conn)?;
+            cancelled.push(OrderUpdate::from_ask(&order));
+        }
+
+        buyback.closed_at = Some(chrono::Utc::now().naive_utc());
+        let buyback = buyback.save_changes::<Buyback>(conn).map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库结束回购计划错误：{}", db_err))
+        })?;
+
+        Ok((buyback, cancelled))
+    })?;
+
+    // 提交后推送撤销的回购委托和发行人账户变化，推送失败不影响终止
+    if !cancelled.is_empty() {
+        if let Err(err) = feed.publish_book(buyback.stock_id, conn) {
+            error!("Publishing market data failed: {}", err);
+        }
+        for order in &cancelled {
+            notify.publish_order(OrderStatus::Cancelled, order);
+        }
+        if let Err(err) = notify.publish_account(buyback.issuer_id, None, conn) {
+            error!("Publishing user events failed: {}", err);
+        }
+    }
+
+    Ok(buyback)
+}
+
+//////////////////
+pub fn get_stock_buybacks(
+    stock_id: web::Path<u64>,
+    paging: web::Query<PagingModel>,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_stock_buybacks_query(stock_id.into_inner(), paging, pool)
+        }
+    ).then(
+        move |res: Result<Vec<Buyback>, BlockingError<EngineError>>|
+            match res {
+                Ok(buybacks) => Ok(HttpResponse::Ok().json(buybacks)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_stock_buybacks_query(stock_id: u64, paging: PagingModel, pool: web::Data<Pool>) -> Result<Vec<Buyback>, EngineError> {
+    use crate::schema::buybacks::dsl as bbdsl;
+
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = bbdsl::buybacks
+                    .filter(bbdsl::stock_id.eq(stock_id))
+                    .order((bbdsl::created_at.desc(), bbdsl::id.desc()))
+                    .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get stock buybacks SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<Buyback>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
@@ -0,0 +1,14 @@
+SELECT
+    bucket,
+    open,
+    high,
+    low,
+    close,
+    volume,
+    turnover
+FROM candles
+WHERE stock_id = $1
+    AND period = $2
+    AND bucket >= $3
+    AND bucket < $4
+ORDER BY bucket;
@@ -0,0 +1,19 @@
+INSERT INTO dividend_entitlements (dividend_id, user_id, shares, amount)
+SELECT
+	$1 AS dividend_id,
+	t.user_id AS user_id,
+	SUM(t.shares)::BIGINT AS shares,
+	(SUM(t.shares) * $3)::BIGINT AS amount
+FROM (
+	SELECT user_id, hold AS shares FROM user_hold_stock WHERE stock_id = $2
+	UNION ALL
+	SELECT user_id, unfulfilled AS shares FROM user_bid_orders WHERE stock_id = $2
+	UNION ALL
+	SELECT user_id, amount AS shares FROM pending_settlements WHERE stock_id = $2
+	UNION ALL
+	SELECT user_id, available + lent AS shares FROM lending_pool WHERE stock_id = $2
+	UNION ALL
+	SELECT borrower_id AS user_id, -amount AS shares FROM stock_loans WHERE stock_id = $2 AND closed_at IS NULL
+) AS t
+GROUP BY t.user_id
+HAVING SUM(t.shares) > 0;
@@ -0,0 +1,566 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+use crate::models::Dividend;
+use crate::money::Money;
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::wallets::add_balance;
+use super::notify::UserFeed;
+use super::PagingModel;
+
+use crate::schema::*;
+use diesel::sql_types;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/dividends")
+        .service(
+            web::resource("/")  // Scope 会自动加尾 /，所以 /dividends 无法匹配
+                .route(web::post().to_async(declare_dividend))       // 发行人宣告分红
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/my/")
+                .route(web::get().to_async(get_my_dividends))     // 查询自己登记到的分红
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/stock/{id}")
+                .route(web::get().to_async(get_stock_dividends))     // 查询某只股票宣告过的分红
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+// 处理所有到期的分红：先做股权登记，再派发到期的分红
+pub fn process_dividends(notify: &UserFeed, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::dividends::dsl as divdsl;
+
+    record_dividends(conn)?;
+
+    let now = chrono::Utc::now().naive_utc();
+
+    let query = divdsl::dividends
+                    .filter(
+                        divdsl::recorded_at.is_not_null().and(
+                            divdsl::paid_at.is_null()
+                        ).and(
+                            divdsl::pay_date.le(now)
+                        )
+                    )
+                    .order(divdsl::pay_date.asc())
+                    .select(divdsl::id);
+
+    debug!("Dividends to pay SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let to_pay = query.get_results::<i64>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    for dividend_id in to_pay {
+        let paid = pay_dividend(dividend_id, now, conn)?;
+
+        // 提交后推送发行人和收到分红的用户的余额变化，推送失败不影响派发
+        for user_id in paid {
+            if let Err(err) = notify.publish_account(user_id, None, conn) {
+                error!("Publishing user events failed: {}", err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// 股权登记时间已到的分红做登记。持有量变化前调用，保证登记的是登记时间时的持有量
+pub fn record_dividends(conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::dividends::dsl as divdsl;
+
+    let now = chrono::Utc::now().naive_utc();
+
+    conn.transaction(|| {
+        // 股权登记：快照该股票的持有量，包括卖出委托冻结、尚未交收和放入借券池的股票，借入的股票不计
+        let query = divdsl::dividends
+                        .filter(
+                            divdsl::recorded_at.is_null().and(
+                                divdsl::record_date.le(now)
+                            )
+                        )
+                        .order(divdsl::record_date.asc())
+                        .for_update();
+
+        debug!("Dividends to record SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let to_record = query.get_results::<Dividend>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        for mut dividend in to_record {
+            let query = diesel::sql_query(include_str!("dividendrecord.sql"))
+                            .bind::<sql_types::BigInt, _>(dividend.id)
+                            .bind::<sql_types::BigInt, _>(dividend.stock_id)
+                            .bind::<sql_types::BigInt, _>(dividend.amount_per_share);
+
+            debug!("Record dividend SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+            query.execute(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库股权登记错误：{}", db_err))
+                })?;
+
+            dividend.total = dividend_total(dividend.id, conn)?;
+            dividend.recorded_at = Some(now);
+            dividend.save_changes::<Dividend>(conn).map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库重设分红错误：{}", db_err))
+                })?;
+        }
+
+        Ok(())
+    })
+}
+
+fn dividend_total(dividend_id: i64, conn: &PgConnection) -> Result<Money, EngineError> {
+    use crate::schema::dividend_entitlements::dsl as entdsl;
+
+    let query = entdsl::dividend_entitlements
+                    .filter(entdsl::dividend_id.eq(dividend_id))
+                    .select(entdsl::amount);
+
+    debug!("Dividend total SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let amounts = query.get_results::<Money>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    Ok(amounts.into_iter().sum())
+}
+
+// 派发一笔分红：从发行人的钱包扣除总额，记入各登记用户的钱包。发行人余额不足时暂不派发。
+// 返回余额有变化的用户，包括发行人；没有派发时为空
+fn pay_dividend(dividend_id: i64, now: chrono::NaiveDateTime, conn: &PgConnection) -> Result<Vec<i64>, EngineError> {
+    use crate::schema::dividends::dsl as divdsl;
+    use crate::schema::dividend_entitlements::dsl as entdsl;
+
+    conn.transaction(|| {
+        // 保证原子性
+        let query = divdsl::dividends
+                        .find(dividend_id)
+                        .filter(divdsl::paid_at.is_null())
+                        .for_update();
+
+        debug!("Pay dividend SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let mut dividend = match query.get_result::<Dividend>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })? {
+            Some(dividend) => dividend,
+            None => return Ok(Vec::new())      // 已被其他请求派发
+        };
+
+        let issuer_balance_after = add_balance(dividend.issuer_id, &dividend.currency, -dividend.total, conn)?;
+        if issuer_balance_after < Money::zero() {
+            warn!("Issuer {} cannot afford dividend {} ({} {}), postponed", dividend.issuer_id, dividend.id, dividend.total, dividend.currency);
+            add_balance(dividend.issuer_id, &dividend.currency, dividend.total, conn)?;
+            return Ok(Vec::new());
+        }
+
+        let query = entdsl::dividend_entitlements
+                        .filter(entdsl::dividend_id.eq(dividend.id))
+                        .select((entdsl::user_id, entdsl::amount));
+
+        debug!("Dividend entitlements SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let entitlements = query.get_results::<(i64, Money)>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let mut paid = vec![dividend.issuer_id];
+        for (user_id, amount) in entitlements {
+            add_balance(user_id, &dividend.currency, amount, conn)?;
+            if !paid.contains(&user_id) {
+                paid.push(user_id);
+            }
+        }
+
+        dividend.paid_at = Some(now);
+        dividend.save_changes::<Dividend>(conn).map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库重设分红错误：{}", db_err))
+            })?;
+
+        Ok(paid)
+    })
+}
+
+// 定期处理到期的分红，在单独的线程中运行
+pub fn run_dividend_worker(pool: Pool, notify: UserFeed, interval: std::time::Duration) {
+    std::thread::spawn(move || {
+        loop {
+            match pool.get() {
+                Ok(conn) => {
+                    if let Err(err) = process_dividends(&notify, &conn) {
+                        error!("Processing dividends failed: {}", err);
+                    }
+                },
+                Err(pool_err) => error!("Processing dividends failed, cannot get database connection: {}", pool_err)
+            }
+            std::thread::sleep(interval);
+        }
+    });
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeclareDividendModel {
+    pub stock_id: u64,
+    pub amount_per_share: Money,
+    pub record_date: chrono::NaiveDateTime,
+    pub pay_date: chrono::NaiveDateTime,
+}
+
+pub fn declare_dividend(
+    declare: web::Json<DeclareDividendModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            declare_dividend_query(declare.into_inner(), curr_user, pool)
+        }
+    ).then(
+        move |res: Result<Dividend, BlockingError<EngineError>>|
+            match res {
+                Ok(dividend) => Ok(HttpResponse::Ok().json(dividend)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn declare_dividend_query(declare: DeclareDividendModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Dividend, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+    use crate::schema::dividends::dsl as divdsl;
+
+    let stock_id = i64::try_from(declare.stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    if declare.amount_per_share <= Money::zero() {
+        return Err(EngineError::BadRequest(format!("每股分红金额必须为正数！")));
+    }
+    let now = chrono::Utc::now().naive_utc();
+    if declare.record_date <= now {
+        return Err(EngineError::BadRequest(format!("股权登记时间必须晚于当前时间！")));
+    }
+    if declare.pay_date < declare.record_date {
+        return Err(EngineError::BadRequest(format!("派发时间不能早于股权登记时间！")));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    conn.transaction(|| {
+        // 保证原子性
+        // 只有已上市股票的发行人可以宣告分红
+        let query = stkdsl::stocks
+                        .inner_join(newdsl::new_stocks)
+                        .filter(
+                            stkdsl::id.eq(stock_id).and(
+                                stkdsl::into_market.eq(true)
+                            )
+                        )
+                        .select((newdsl::issuer_id, stkdsl::currency));
+
+        debug!("Declare dividend stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let (issuer_id, currency) = query.get_result::<(i64, String)>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::BadRequest(format!("该股票还未上市！")))?;
+
+        if issuer_id != user.id {
+            return Err(EngineError::Unauthorized(format!("只有该股票的发行人可以宣告分红。")));
+        }
+
+        let query = diesel::insert_into(divdsl::dividends)
+                        .values((
+                            divdsl::stock_id.eq(stock_id),
+                            divdsl::issuer_id.eq(issuer_id),
+                            divdsl::currency.eq(currency),
+                            divdsl::amount_per_share.eq(declare.amount_per_share),
+                            divdsl::record_date.eq(declare.record_date),
+                            divdsl::pay_date.eq(declare.pay_date),
+                            divdsl::created_at.eq(now)
+                        ));
+
+        debug!("Declare dividend SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        query.get_result::<Dividend>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库插入分红错误：{}", db_err))
+            })
+    })
+}
+
+//////////////////
+pub fn get_stock_dividends(
+    stock_id: web::Path<u64>,
+    paging: web::Query<PagingModel>,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_stock_dividends_query(stock_id.into_inner(), paging, pool)
+        }
+    ).then(
+        move |res: Result<Vec<Dividend>, BlockingError<EngineError>>|
+            match res {
+                Ok(dividends) => Ok(HttpResponse::Ok().json(dividends)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_stock_dividends_query(stock_id: u64, paging: PagingModel, pool: web::Data<Pool>) -> Result<Vec<Dividend>, EngineError> {
+    use crate::schema::dividends::dsl as divdsl;
+
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = divdsl::dividends
+                    .filter(divdsl::stock_id.eq(stock_id))
+                    .order((divdsl::record_date.desc(), divdsl::id.desc()))
+                    .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get stock dividends SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<Dividend>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+//////////////////
+#[derive(QueryableByName, Serialize)]
+pub struct DividendEntitlementModel {
+    #[sql_type = "sql_types::BigInt"]
+    pub dividend_id: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub stock_id: i64,
+    #[sql_type = "sql_types::Varchar"]
+    pub stock_name: String,
+    #[sql_type = "sql_types::Varchar"]
+    pub currency: String,
+    #[sql_type = "sql_types::BigInt"]
+    pub amount_per_share: Money,
+    #[sql_type = "sql_types::BigInt"]
+    pub shares: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub amount: Money,
+    #[sql_type = "sql_types::Timestamp"]
+    pub record_date: chrono::NaiveDateTime,
+    #[sql_type = "sql_types::Timestamp"]
+    pub pay_date: chrono::NaiveDateTime,
+    #[sql_type = "sql_types::Nullable<sql_types::Timestamp>"]
+    pub paid_at: Option<chrono::NaiveDateTime>      // 尚未派发时为 null
+}
+
+pub fn get_my_dividends(
+    paging: web::Query<PagingModel>,
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_my_dividends_query(paging, user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<DividendEntitlementModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(dividends) => Ok(HttpResponse::Ok().json(dividends)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_dividends_query(paging: PagingModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<DividendEntitlementModel>, EngineError> {
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = diesel::sql_query(include_str!("mydividends.sql"))
+                    .bind::<sql_types::BigInt, _>(user.id)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.offset.unwrap_or(0)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.limit.unwrap_or(10)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get my dividends SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.load::<DividendEntitlementModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+#[test]
+fn test_record_dividend_pending_settlement() {
+    use crate::schema::users::dsl as usrdsl;
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::user_bid_orders::dsl as biddsl;
+    use crate::schema::pending_settlements::dsl as pnddsl;
+    use crate::schema::lending_pool::dsl as lnddsl;
+    use crate::schema::stock_loans::dsl as loandsl;
+    use crate::schema::dividends::dsl as divdsl;
+    use crate::schema::dividend_entitlements::dsl as entdsl;
+    use crate::money::Price;
+    let conn = crate::test_get_data_connection();
+
+    // 在测试事务中进行，结束后回滚
+    conn.test_transaction::<_, EngineError, _>(|| {
+        let now = chrono::Utc::now().naive_utc();
+        let user_ids = diesel::insert_into(usrdsl::users)
+            .values(&vec![
+                (usrdsl::name.eq("分红测试发行人"), usrdsl::password_hashed.eq(""), usrdsl::created_at.eq(now)),
+                (usrdsl::name.eq("分红测试卖方"), usrdsl::password_hashed.eq(""), usrdsl::created_at.eq(now)),
+                (usrdsl::name.eq("分红测试买方"), usrdsl::password_hashed.eq(""), usrdsl::created_at.eq(now)),
+                (usrdsl::name.eq("分红测试出借人"), usrdsl::password_hashed.eq(""), usrdsl::created_at.eq(now)),
+                (usrdsl::name.eq("分红测试卖空者"), usrdsl::password_hashed.eq(""), usrdsl::created_at.eq(now))
+            ])
+            .returning(usrdsl::id)
+            .get_results::<i64>(&conn)?;
+        let (issuer_id, seller_id, buyer_id, lender_id, short_id) = (user_ids[0], user_ids[1], user_ids[2], user_ids[3], user_ids[4]);
+
+        let stock_id = diesel::insert_into(stkdsl::stocks)
+            .values((
+                stkdsl::name.eq("分红测试股票"),
+                stkdsl::into_market.eq(true),
+                stkdsl::into_market_at.eq(now),
+                stkdsl::price_decimals.eq(2i16),
+                stkdsl::currency.eq("CNY")
+            ))
+            .returning(stkdsl::id)
+            .get_result::<i64>(&conn)?;
+
+        // 卖方卖出 50 股后还持有 100 股，卖出的 50 股在登记时尚未交收给买方
+        diesel::insert_into(reldsl::user_hold_stock)
+            .values((reldsl::user_id.eq(seller_id), reldsl::stock_id.eq(stock_id), reldsl::hold.eq(100i64), reldsl::updated_at.eq(now)))
+            .execute(&conn)?;
+        diesel::insert_into(pnddsl::pending_settlements)
+            .values((
+                pnddsl::user_id.eq(buyer_id),
+                pnddsl::stock_id.eq(stock_id),
+                pnddsl::amount.eq(50i64),
+                pnddsl::settle_at.eq(now + chrono::Duration::days(1)),
+                pnddsl::created_at.eq(now)
+            ))
+            .execute(&conn)?;
+
+        // 出借人放入借券池 50 股，其中 20 股被借出，卖空者正在卖出借入的 20 股
+        diesel::insert_into(lnddsl::lending_pool)
+            .values((lnddsl::user_id.eq(lender_id), lnddsl::stock_id.eq(stock_id), lnddsl::available.eq(30i64), lnddsl::lent.eq(20i64), lnddsl::updated_at.eq(now)))
+            .execute(&conn)?;
+        diesel::insert_into(loandsl::stock_loans)
+            .values((
+                loandsl::borrower_id.eq(short_id),
+                loandsl::lender_id.eq(lender_id),
+                loandsl::stock_id.eq(stock_id),
+                loandsl::amount.eq(20i64),
+                loandsl::price.eq(Price(100)),
+                loandsl::collateral.eq(Money(3000)),
+                loandsl::currency.eq("CNY"),
+                loandsl::fee_rate.eq(0i64),
+                loandsl::fees_paid.eq(Money::zero()),
+                loandsl::fee_accrued_at.eq(now),
+                loandsl::created_at.eq(now)
+            ))
+            .execute(&conn)?;
+        diesel::insert_into(biddsl::user_bid_orders)
+            .values((
+                biddsl::user_id.eq(short_id),
+                biddsl::stock_id.eq(stock_id),
+                biddsl::price.eq(Price(100)),
+                biddsl::volume.eq(20i64),
+                biddsl::unfulfilled.eq(20i64),
+                biddsl::created_at.eq(now),
+                biddsl::updated_at.eq(now),
+                biddsl::liquidation.eq(false)
+            ))
+            .execute(&conn)?;
+
+        let dividend_id = diesel::insert_into(divdsl::dividends)
+            .values((
+                divdsl::stock_id.eq(stock_id),
+                divdsl::issuer_id.eq(issuer_id),
+                divdsl::currency.eq("CNY"),
+                divdsl::amount_per_share.eq(Money(10)),
+                divdsl::record_date.eq(now - chrono::Duration::minutes(1)),
+                divdsl::pay_date.eq(now + chrono::Duration::days(1)),
+                divdsl::total.eq(Money::zero()),
+                divdsl::created_at.eq(now)
+            ))
+            .returning(divdsl::id)
+            .get_result::<i64>(&conn)?;
+
+        record_dividends(&conn)?;
+
+        let mut shares = entdsl::dividend_entitlements
+            .filter(entdsl::dividend_id.eq(dividend_id))
+            .select((entdsl::user_id, entdsl::shares))
+            .get_results::<(i64, i64)>(&conn)?;
+        shares.sort();
+        let mut expected = vec![(seller_id, 100), (buyer_id, 50), (lender_id, 50)];
+        expected.sort();
+        assert_eq!(shares, expected);
+
+        let total = divdsl::dividends
+            .find(dividend_id)
+            .select(divdsl::total)
+            .get_result::<Money>(&conn)?;
+        assert_eq!(total, Money(2000));
+
+        Ok(())
+    });
+}
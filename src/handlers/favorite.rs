@@ -9,6 +9,7 @@ use crate::models::Stock;
 use crate::models::AskOrder;
 use crate::models::BidOrder;
 use crate::models::Deal;
+use crate::money::Price;
 
 use std::convert::TryFrom;
 use std::convert::TryInto;
@@ -171,8 +172,10 @@ pub struct FavoriteModel {
     pub name: String,
     pub into_market: bool,
     pub into_market_at: Option<chrono::NaiveDateTime>,
+    pub price_decimals: i16,
+    pub currency: String,
     pub offer_circ: Option<i64>,
-    pub offer_price: Option<i32>,
+    pub offer_price: Option<Price>,
     pub offer_unfulfilled: Option<i64>,
     pub created_at: Option<chrono::NaiveDateTime>,
     pub favorited_at: chrono::NaiveDateTime
@@ -214,7 +217,7 @@ fn get_favorites_query(paging: PagingModel, user: RememberUserModel, pool: web::
                     favdsl::user_id.eq(user.id)
                 )
                 .select(
-                    (crate::schema::stocks::dsl::id, crate::schema::users::dsl::name.nullable(), crate::schema::stocks::dsl::name, stkdsl::into_market, stkdsl::into_market_at, newdsl::offer_circ.nullable(), newdsl::offer_price.nullable(), newdsl::offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable(), favdsl::created_at)
+                    (crate::schema::stocks::dsl::id, crate::schema::users::dsl::name.nullable(), crate::schema::stocks::dsl::name, stkdsl::into_market, stkdsl::into_market_at, stkdsl::price_decimals, stkdsl::currency, newdsl::offer_circ.nullable(), newdsl::offer_price.nullable(), newdsl::offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable(), favdsl::created_at)
                 )
                 .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                 .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
@@ -0,0 +1,256 @@
+use actix_web::{
+    web, HttpResponse
+};
+use actix_web::error::BlockingError;
+use crate::models::{AskOrder, BidOrder, OrderEvent};
+use crate::schema::order_events;
+use crate::money::Price;
+
+use std::convert::TryFrom;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::quotation::{BookOrderModel, OrderBookModel, DepthQueryModel};
+
+use diesel::sql_types;
+
+// 委托的变化，重建委托簿时取每笔委托在该时刻之前的最后一次变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderEventKind {
+    Inserted,       // 委托进入委托簿
+    Filled,         // 成交，未成交数量减少
+    Amended,        // 由撮合引擎调整价格或数量，例如拆股合股
+    Cancelled,      // 用户撤单，或发行人终止回购计划
+    Expired         // 由撮合引擎撤销剩余部分
+}
+
+impl OrderEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderEventKind::Inserted => "inserted",
+            OrderEventKind::Filled => "filled",
+            OrderEventKind::Amended => "amended",
+            OrderEventKind::Cancelled => "cancelled",
+            OrderEventKind::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name="order_events"]
+struct NewOrderEvent {
+    order_id: i64,
+    side: &'static str,
+    stock_id: i64,
+    user_id: i64,
+    kind: &'static str,
+    price: Price,
+    volume: i64,
+    unfulfilled: i64,
+    created_at: chrono::NaiveDateTime
+}
+
+fn add_event(event: NewOrderEvent, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::order_events::dsl as evtdsl;
+
+    let query = diesel::insert_into(evtdsl::order_events)
+                    .values(&event);
+
+    debug!("New order event SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库插入委托记录错误：{}", db_err))
+        })?;
+
+    Ok(())
+}
+
+// 记录买入委托变化后的状态，须与变化在同一事务中
+pub fn record_ask(kind: OrderEventKind, ask: &AskOrder, conn: &PgConnection) -> Result<(), EngineError> {
+    add_event(NewOrderEvent {
+        order_id: ask.id,
+        side: "ask",
+        stock_id: ask.stock_id,
+        user_id: ask.user_id,
+        kind: kind.as_str(),
+        price: ask.price,
+        volume: ask.volume,
+        unfulfilled: ask.unfulfilled,
+        created_at: chrono::Utc::now().naive_utc()
+    }, conn)
+}
+
+// 记录卖出委托变化后的状态，须与变化在同一事务中
+pub fn record_bid(kind: OrderEventKind, bid: &BidOrder, conn: &PgConnection) -> Result<(), EngineError> {
+    add_event(NewOrderEvent {
+        order_id: bid.id,
+        side: "bid",
+        stock_id: bid.stock_id,
+        user_id: bid.user_id,
+        kind: kind.as_str(),
+        price: bid.price,
+        volume: bid.volume,
+        unfulfilled: bid.unfulfilled,
+        created_at: chrono::Utc::now().naive_utc()
+    }, conn)
+}
+
+// 保存买入委托的修改并记录变化
+pub fn save_ask(kind: OrderEventKind, ask: &AskOrder, conn: &PgConnection) -> Result<(), EngineError> {
+    ask.save_changes::<AskOrder>(conn).map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库重设买委托错误：{}", db_err))
+        })?;
+    record_ask(kind, ask, conn)
+}
+
+// 保存卖出委托的修改并记录变化
+pub fn save_bid(kind: OrderEventKind, bid: &BidOrder, conn: &PgConnection) -> Result<(), EngineError> {
+    bid.save_changes::<BidOrder>(conn).map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库重设卖委托错误：{}", db_err))
+        })?;
+    record_bid(kind, bid, conn)
+}
+
+//////////////////
+pub fn get_ask_events(
+    ask_id: web::Path<u64>,
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    get_order_events("ask", ask_id.into_inner(), user, pool)
+}
+
+pub fn get_bid_events(
+    bid_id: web::Path<u64>,
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    get_order_events("bid", bid_id.into_inner(), user, pool)
+}
+
+fn get_order_events(side: &'static str, order_id: u64, user: RememberUserModel, pool: web::Data<Pool>) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    web::block(
+        move || {
+            get_order_events_query(side, order_id, user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<OrderEvent>, BlockingError<EngineError>>|
+            match res {
+                Ok(m) => Ok(HttpResponse::Ok().json(m)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+// 委托撤销后委托单被删除，变化记录仍然保留
+fn get_order_events_query(side: &str, order_id: u64, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<OrderEvent>, EngineError> {
+    use crate::schema::order_events::dsl as evtdsl;
+
+    let order_id = i64::try_from(order_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = evtdsl::order_events
+                    .filter(
+                        evtdsl::order_id.eq(order_id).and(
+                            evtdsl::side.eq(side)
+                        ).and(
+                            evtdsl::user_id.eq(user.id)
+                        )
+                    )
+                    .order_by(evtdsl::id.asc());
+
+    debug!("Get order events SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let events = query.get_results::<OrderEvent>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    if events.is_empty() {
+        return Err(EngineError::NotFound(format!("未找到请求的委托。")));
+    }
+
+    Ok(events)
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct BookAtQueryModel {
+    pub at: chrono::NaiveDateTime,  // UTC 时刻，含该时刻的变化
+    pub depth: Option<u32>          // 同 quotation 的 depth
+}
+
+pub fn get_order_book_at(
+    stock_id: web::Path<u64>,
+    book_at: web::Query<BookAtQueryModel>,
+    _: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let stock_id = stock_id.into_inner();
+    let book_at = book_at.into_inner();
+
+    web::block(
+        move || {
+            get_order_book_at_query(stock_id, book_at, pool)
+        }
+    ).then(
+        move |res: Result<OrderBookModel, BlockingError<EngineError>>|
+            match res {
+                Ok(m) => Ok(HttpResponse::Ok().json(m)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_order_book_at_query(stock_id: u64, book_at: BookAtQueryModel, pool: web::Data<Pool>) -> Result<OrderBookModel, EngineError> {
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+    let levels = DepthQueryModel { depth: book_at.depth }.levels();
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    replay_book(stock_id, book_at.at, levels, conn)
+}
+
+// 重放委托记录，重建某一时刻的委托簿，同一价位按委托时间先后排列
+pub fn replay_book(stock_id: i64, at: chrono::NaiveDateTime, levels: Option<i64>, conn: &PgConnection) -> Result<OrderBookModel, EngineError> {
+    Ok(OrderBookModel {
+        ask_orders: replay_side(stock_id, "ask", at, levels, conn)?,
+        bid_orders: replay_side(stock_id, "bid", at, levels, conn)?
+    })
+}
+
+fn replay_side(stock_id: i64, side: &str, at: chrono::NaiveDateTime, levels: Option<i64>, conn: &PgConnection) -> Result<Vec<BookOrderModel>, EngineError> {
+    let query = diesel::sql_query(include_str!("bookat.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Varchar, _>(side)
+                    .bind::<sql_types::Timestamp, _>(at)
+                    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(levels);
+
+    debug!("Replay order book SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.load::<BookOrderModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
@@ -1,10 +1,12 @@
 SELECT
-	COALESCE(t.hold, 0) AS hold
+	COALESCE(t.hold, 0) AS hold,
+	COALESCE(t.short, 0) AS short
 FROM (
 	SELECT
 		query_stock_id,
 		t1.stock_id AS stock_id,
-		t1.hold AS hold
+		t1.hold AS hold,
+		t2.short AS short
 	FROM
 		unnest( $1 ) WITH ORDINALITY AS query(query_stock_id, ordinality)
 	LEFT JOIN
@@ -17,5 +19,15 @@ FROM (
 		WHERE user_hold_stock.user_id = ( $2 ) AND user_hold_stock.stock_id = ANY( $1 )
 	) AS t1
 	ON t1.stock_id = query_stock_id
+	LEFT JOIN
+	(
+		SELECT
+			stock_loans.stock_id AS stock_id,
+			SUM(stock_loans.amount)::BIGINT AS short
+		FROM stock_loans
+		WHERE stock_loans.borrower_id = ( $2 ) AND stock_loans.closed_at IS NULL AND stock_loans.stock_id = ANY( $1 )
+		GROUP BY stock_loans.stock_id
+	) AS t2
+	ON t2.stock_id = query_stock_id
 	ORDER BY ordinality
 ) AS t;
\ No newline at end of file
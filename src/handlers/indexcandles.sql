@@ -0,0 +1,14 @@
+SELECT
+    bucket,
+    open,
+    high,
+    low,
+    close,
+    0::BIGINT AS volume,
+    0::BIGINT AS turnover
+FROM index_candles
+WHERE index_name = $1
+    AND period = $2
+    AND bucket >= $3
+    AND bucket < $4
+ORDER BY bucket;
\ No newline at end of file
@@ -0,0 +1,32 @@
+SELECT
+    member.stock_id,
+    member.shares,
+    (member.shares * member.price)::BIGINT AS cap,
+    CASE
+        WHEN prev.ord IS NULL THEN 0
+        ELSE (COALESCE(($4::BIGINT[])[prev.ord] / split_adjustment(member.stock_id, $5), member.shares) * member.price)::BIGINT
+    END AS continuing_cap
+FROM (
+    SELECT
+        stocks.id AS stock_id,
+        new_stocks.offer_circ - new_stocks.offer_unfulfilled AS shares,
+        COALESCE(last_deal.price, new_stocks.offer_price) AS price
+    FROM stocks
+    INNER JOIN new_stocks
+    ON new_stocks.id = stocks.id
+    LEFT JOIN LATERAL (
+        SELECT
+            ROUND(deals.price * split_adjustment(deals.stock_id, deals.created_at))::INTEGER AS price
+        FROM deals
+        WHERE deals.stock_id = stocks.id
+            AND deals.sell_user_id IS NOT NULL
+        ORDER BY deals.created_at DESC, deals.id DESC
+        LIMIT 1
+    ) AS last_deal ON TRUE
+    WHERE stocks.into_market
+        AND stocks.currency = $1
+        AND (cardinality($2::BIGINT[]) = 0 OR stocks.id = ANY($2))
+) AS member
+LEFT JOIN unnest($3::BIGINT[]) WITH ORDINALITY AS prev(stock_id, ord)
+ON prev.stock_id = member.stock_id
+ORDER BY member.stock_id;
\ No newline at end of file
@@ -0,0 +1,8 @@
+SELECT
+    close AS price
+FROM index_candles
+WHERE index_name = $1
+    AND period = $2
+    AND bucket < $3
+ORDER BY bucket DESC
+LIMIT 1;
\ No newline at end of file
@@ -0,0 +1,194 @@
+use actix_web::{
+    web, HttpResponse
+};
+use actix_web::error::BlockingError;
+use crate::money::Price;
+use crate::indicators::{self, Bollinger, Macd};
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+
+use super::users::{RememberUserModel};
+use super::quotation::{CandlesQueryModel, candle_range, load_candles};
+
+// 指标周期的上限
+const MAX_PERIOD : usize = 200;
+
+// 指数平滑类指标多读取的 K 线为周期的倍数，使起点的影响可以忽略
+const SMOOTHING_WARMUP : usize = 3;
+
+const DEFAULT_BOLLINGER_K : f64 = 2.0;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct IndicatorsQueryModel {
+    pub interval: String,   // 同 K 线
+    pub from: Option<chrono::NaiveDateTime>,
+    pub to: Option<chrono::NaiveDateTime>,
+    pub sma: Option<String>,        // 以逗号分隔的周期，如 5,20
+    pub ema: Option<String>,        // 以逗号分隔的周期，如 12,26
+    pub rsi: Option<String>,        // 以逗号分隔的周期，如 14
+    pub bollinger: Option<String>,  // 周期和标准差倍数，如 20,2，倍数不填为 2
+    pub macd: Option<String>        // 快线、慢线和信号线的周期，如 12,26,9
+}
+
+// 各序列与 time 逐一对齐，数据不足以计算的位置为 null
+#[derive(Serialize)]
+pub struct IndicatorsModel {
+    pub time: Vec<chrono::NaiveDateTime>,   // K 线开始时刻
+    pub close: Vec<Price>,
+    pub sma: BTreeMap<usize, Vec<Option<f64>>>,     // 以周期为键
+    pub ema: BTreeMap<usize, Vec<Option<f64>>>,
+    pub rsi: BTreeMap<usize, Vec<Option<f64>>>,
+    pub bollinger: Option<Bollinger>,
+    pub macd: Option<Macd>
+}
+
+pub fn get_indicators(
+    stock_id: web::Path<u64>,
+    query: web::Query<IndicatorsQueryModel>,
+    _: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let stock_id = stock_id.into_inner();
+    let query = query.into_inner();
+
+    web::block(
+        move || {
+            get_indicators_query(stock_id, query, pool)
+        }
+    ).then(
+        move |res: Result<IndicatorsModel, BlockingError<EngineError>>|
+            match res {
+                Ok(m) => Ok(HttpResponse::Ok().json(m)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+// 解析以逗号分隔的参数
+fn parse_params(name: &str, value: &str) -> Result<Vec<String>, EngineError> {
+    let params: Vec<String> = value.split(',')
+        .map(|param| param.trim().to_owned())
+        .collect();
+    if params.iter().any(String::is_empty) {
+        return Err(EngineError::BadRequest(format!("指标 {} 的参数应以逗号分隔。", name)));
+    }
+    Ok(params)
+}
+
+fn parse_period(name: &str, param: &str) -> Result<usize, EngineError> {
+    match param.parse::<usize>() {
+        Ok(period) if (1..=MAX_PERIOD).contains(&period) => Ok(period),
+        _ => Err(EngineError::BadRequest(format!("指标 {} 的周期必须是 1 到 {} 之间的整数。", name, MAX_PERIOD)))
+    }
+}
+
+fn parse_periods(name: &str, value: &Option<String>) -> Result<Vec<usize>, EngineError> {
+    match value {
+        Some(value) => parse_params(name, value)?.iter()
+            .map(|param| parse_period(name, param))
+            .collect(),
+        None => Ok(Vec::new())
+    }
+}
+
+fn get_indicators_query(stock_id: u64, query: IndicatorsQueryModel, pool: web::Data<Pool>) -> Result<IndicatorsModel, EngineError> {
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    let sma_periods = parse_periods("sma", &query.sma)?;
+    let ema_periods = parse_periods("ema", &query.ema)?;
+    let rsi_periods = parse_periods("rsi", &query.rsi)?;
+
+    let bollinger_params = match &query.bollinger {
+        Some(value) => {
+            let params = parse_params("bollinger", value)?;
+            if params.len() > 2 {
+                return Err(EngineError::BadRequest(format!("布林带的参数为周期和标准差倍数，如 20,2。")));
+            }
+            let k = match params.get(1) {
+                Some(k) => match k.parse::<f64>() {
+                    Ok(k) if k > 0.0 && k.is_finite() => k,
+                    _ => return Err(EngineError::BadRequest(format!("布林带的标准差倍数必须是正数。")))
+                },
+                None => DEFAULT_BOLLINGER_K
+            };
+            Some((parse_period("bollinger", &params[0])?, k))
+        },
+        None => None
+    };
+
+    let macd_params = match &query.macd {
+        Some(value) => {
+            let params = parse_params("macd", value)?;
+            if params.len() != 3 {
+                return Err(EngineError::BadRequest(format!("MACD 的参数为快线、慢线和信号线的周期，如 12,26,9。")));
+            }
+            let fast = parse_period("macd", &params[0])?;
+            let slow = parse_period("macd", &params[1])?;
+            let signal = parse_period("macd", &params[2])?;
+            if fast >= slow {
+                return Err(EngineError::BadRequest(format!("MACD 的快线周期必须小于慢线周期。")));
+            }
+            Some((fast, slow, signal))
+        },
+        None => None
+    };
+
+    let (interval, first, last) = candle_range(&CandlesQueryModel {
+        interval: query.interval,
+        from: query.from,
+        to: query.to
+    })?;
+
+    // 在查询范围之前多读取的 K 线根数，使范围内第一根 K 线的指标也有值
+    let warmup = sma_periods.iter().cloned()
+        .chain(bollinger_params.map(|(period, _)| period))
+        .chain(ema_periods.iter().chain(rsi_periods.iter()).map(|period| period * SMOOTHING_WARMUP))
+        .chain(macd_params.map(|(_, slow, signal)| (slow + signal) * SMOOTHING_WARMUP))
+        .max()
+        .unwrap_or(0);
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let candles = load_candles(stock_id, interval, first - warmup as i64, last, conn)?;
+    let closes: Vec<f64> = candles.iter().map(|candle| candle.close.0 as f64).collect();
+
+    // 计算后去掉查询范围之前的部分
+    let start_time = interval.bucket_start(first);
+    let skip = candles.iter().position(|candle| candle.time >= start_time).unwrap_or(candles.len());
+    let trim = |mut series: Vec<Option<f64>>| series.split_off(skip);
+
+    Ok(IndicatorsModel {
+        time: candles[skip..].iter().map(|candle| candle.time).collect(),
+        close: candles[skip..].iter().map(|candle| candle.close).collect(),
+        sma: sma_periods.iter().map(|&period| (period, trim(indicators::sma(&closes, period)))).collect(),
+        ema: ema_periods.iter().map(|&period| (period, trim(indicators::ema(&closes, period)))).collect(),
+        rsi: rsi_periods.iter().map(|&period| (period, trim(indicators::rsi(&closes, period)))).collect(),
+        bollinger: bollinger_params.map(|(period, k)| {
+            let bands = indicators::bollinger(&closes, period, k);
+            Bollinger {
+                middle: trim(bands.middle),
+                upper: trim(bands.upper),
+                lower: trim(bands.lower)
+            }
+        }),
+        macd: macd_params.map(|(fast, slow, signal)| {
+            let result = indicators::macd(&closes, fast, slow, signal);
+            Macd {
+                macd: trim(result.macd),
+                signal: trim(result.signal),
+                histogram: trim(result.histogram)
+            }
+        })
+    })
+}
@@ -0,0 +1,773 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+use crate::models::{NewStock, IPOSubscription};
+use crate::money::{Money, Price, price_tick};
+use crate::fees::{FeeSchedule, Liquidity};
+use crate::allocation::{Allocation, auction};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use std::convert::TryFrom;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::wallets::add_balance;
+use super::notify::UserFeed;
+use super::orders::{OrderResult, NewDeal, UserStockRel, credit_house};
+use super::PagingModel;
+
+use crate::schema::*;
+use diesel::sql_types;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/ipo")
+        .service(
+            web::resource("/my/")
+                .route(web::get().to_async(get_my_ipo_subscriptions))     // 查询自己的新股认购和配售结果
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/{id}/subscribe")
+                .route(web::post().to_async(subscribe_ipo))     // 认购期内认购新股
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/{id}/results")
+                .route(web::get().to_async(get_ipo_results))     // 公布每个用户的配售结果
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+// 处理所有认购期已结束但尚未配售的新股，以及截止时间已到但尚未上市的新股
+pub fn process_ipos(fees: &FeeSchedule, notify: &UserFeed, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+
+    let query = newdsl::new_stocks
+                    .filter(
+                        newdsl::subscribe_ends_at.le(chrono::Utc::now().naive_utc()).and(
+                            newdsl::allocated_at.is_null()
+                        )
+                    )
+                    .order(newdsl::subscribe_ends_at.asc())
+                    .select(newdsl::id);
+
+    debug!("IPOs to allocate SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let to_allocate = query.get_results::<i64>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    for stock_id in to_allocate {
+        if allocate_ipo(stock_id, fees, conn)? {
+            publish_ipo_accounts(stock_id, notify, conn);
+        }
+    }
+
+    let query = newdsl::new_stocks
+                    .inner_join(stkdsl::stocks)
+                    .filter(
+                        newdsl::deadline.le(chrono::Utc::now().naive_utc()).and(
+                            newdsl::failed_at.is_null()
+                        ).and(
+                            stkdsl::into_market.eq(false)
+                        )
+                    )
+                    .order(newdsl::deadline.asc())
+                    .select(newdsl::id);
+
+    debug!("IPOs to close SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let to_close = query.get_results::<i64>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    for stock_id in to_close {
+        if close_ipo(stock_id, fees, conn)? {
+            publish_ipo_accounts(stock_id, notify, conn);
+        }
+    }
+
+    Ok(())
+}
+
+// 截止时间已到：达到最低发行量则自动上市，否则发行失败。返回是否发行失败并已退款
+fn close_ipo(stock_id: i64, fees: &FeeSchedule, conn: &PgConnection) -> Result<bool, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+
+    conn.transaction(|| {
+        // 保证原子性
+        allocate_ipo(stock_id, fees, conn)?;
+
+        let query = newdsl::new_stocks
+                        .inner_join(stkdsl::stocks)
+                        .filter(
+                            newdsl::id.eq(stock_id).and(
+                                newdsl::failed_at.is_null()
+                            ).and(
+                                stkdsl::into_market.eq(false)
+                            )
+                        )
+                        .select((crate::schema::new_stocks::all_columns, stkdsl::currency))
+                        .for_update();
+
+        debug!("Close IPO SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let (new_stock, currency) = match query.get_result::<(NewStock, String)>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })? {
+            Some(found) => found,
+            None => return Ok(false)      // 已被发行人上市或已被其他请求处理
+        };
+
+        if new_stock.reached_minimum() {
+            super::stocks::mark_listed(stock_id, conn)?;
+            Ok(false)
+        } else {
+            cancel_ipo(new_stock, &currency, fees, conn)?;
+            Ok(true)
+        }
+    })
+}
+
+// 发行失败：全额退还所有新股认购款和佣金，收回已发行的股票
+fn cancel_ipo(mut new_stock: NewStock, currency: &str, fees: &FeeSchedule, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::deals::dsl as dldsl;
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::lending_pool::dsl as lenddsl;
+
+    warn!("IPO of stock {} failed: {} sold, minimum {:?}", new_stock.id, new_stock.sold(), new_stock.min_subscription);
+
+    let query = dldsl::deals
+                    .filter(
+                        dldsl::stock_id.eq(new_stock.id).and(
+                            dldsl::sell_user_id.is_null()
+                        )
+                    )
+                    .select((dldsl::buy_user_id, dldsl::price, dldsl::amount, dldsl::buyer_commission));
+
+    debug!("Cancel IPO deals SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let purchases = query.get_results::<(i64, Price, i64, Money)>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    for (buyer_id, price, amount, commission) in purchases {
+        add_balance(buyer_id, currency, price.value(amount) + commission, conn)?;
+        add_balance(fees.house_user_id, currency, -commission, conn)?;
+    }
+
+    // 未上市的股票只能来自新股发行，全部收回
+    let query = diesel::delete(reldsl::user_hold_stock.filter(reldsl::stock_id.eq(new_stock.id)));
+
+    debug!("Cancel IPO holdings SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库收回股票错误：{}", db_err))
+        })?;
+
+    let query = diesel::delete(lenddsl::lending_pool.filter(lenddsl::stock_id.eq(new_stock.id)));
+
+    debug!("Cancel IPO lending pool SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库收回出借股票错误：{}", db_err))
+        })?;
+
+    new_stock.offer_unfulfilled = new_stock.offer_circ;
+    new_stock.failed_at = Some(chrono::Utc::now().naive_utc());
+    new_stock.save_changes::<NewStock>(conn).map_err(|db_err| {
+        debug!("Database query error: {}", db_err);
+        EngineError::InternalError(format!("数据库设置发行失败错误：{}", db_err))
+    })?;
+
+    Ok(())
+}
+
+// 提交后推送新股认购者和认购到新股的用户的余额和持有量（配售、退款、发行失败收回），推送失败不影响配售
+pub fn publish_ipo_accounts(stock_id: i64, notify: &UserFeed, conn: &PgConnection) {
+    use crate::schema::ipo_subscriptions::dsl as subdsl;
+    use crate::schema::deals::dsl as dldsl;
+
+    let query = subdsl::ipo_subscriptions
+                    .filter(subdsl::stock_id.eq(stock_id))
+                    .select(subdsl::user_id);
+
+    debug!("IPO subscribers SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let mut users = match query.load::<i64>(conn) {
+        Ok(users) => users,
+        Err(db_err) => {
+            error!("Publishing user events failed: {}", db_err);
+            return;
+        }
+    };
+
+    // 先到先得的发行没有认购记录，认购到新股的用户从交易记录中取
+    let query = dldsl::deals
+                    .filter(
+                        dldsl::stock_id.eq(stock_id).and(
+                            dldsl::sell_user_id.is_null()
+                        )
+                    )
+                    .select(dldsl::buy_user_id);
+
+    debug!("IPO allottees SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    match query.load::<i64>(conn) {
+        Ok(allottees) => users.extend(allottees),
+        Err(db_err) => {
+            error!("Publishing user events failed: {}", db_err);
+            return;
+        }
+    }
+    users.sort();
+    users.dedup();
+
+    for user_id in users {
+        if let Err(err) = notify.publish_account(user_id, Some(stock_id), conn) {
+            error!("Publishing user events failed: {}", err);
+        }
+    }
+}
+
+// 由系统随机数生成器抽取摇号种子
+fn draw_lottery_seed() -> Result<u64, EngineError> {
+    let mut bytes = [0u8; 8];
+    SystemRandom::new().fill(&mut bytes)
+        .map_err(|_| EngineError::InternalError(format!("服务端遇到错误，无法生成摇号种子。")))?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// 认购期结束后配售新股：配售到的股票记入持有量，未用完的冻结资金退还。返回是否已完成配售
+pub fn allocate_ipo(stock_id: i64, fees: &FeeSchedule, conn: &PgConnection) -> Result<bool, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+    use crate::schema::ipo_subscriptions::dsl as subdsl;
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::deals::dsl as dldsl;
+
+    conn.transaction(|| {
+        // 保证原子性
+        let query = newdsl::new_stocks
+                        .find(stock_id)
+                        .for_update();
+
+        debug!("Allocate IPO SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let mut new_stock = query.get_result::<NewStock>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let now = chrono::Utc::now().naive_utc();
+        match new_stock.subscribe_ends_at {
+            None => return Ok(true),        // 先到先得，不需要配售
+            Some(ends_at) => if ends_at > now {
+                return Ok(false);
+            }
+        }
+        if new_stock.allocated_at.is_some() {
+            return Ok(true);
+        }
+
+        let query = stkdsl::stocks
+                        .find(stock_id)
+                        .select(stkdsl::currency);
+
+        debug!("Allocate IPO currency SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let currency = query.get_result::<String>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let query = subdsl::ipo_subscriptions
+                        .filter(subdsl::stock_id.eq(stock_id))
+                        .order(subdsl::id.asc())
+                        .for_update();
+
+        debug!("Allocate IPO subscriptions SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let subscriptions = query.get_results::<IPOSubscription>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        // 认购期结束后才抽取摇号种子，配售完成后随结果公布
+        let seed = draw_lottery_seed()?;
+        new_stock.lottery_seed = Some(seed as i64);
+
+        let method = new_stock.allocation.as_ref()
+            .and_then(|allocation| Allocation::from_name(allocation))
+            .unwrap_or(Allocation::Lottery);
+        let available = new_stock.offer_unfulfilled / new_stock.lot_size;
+        let allocated_lots = if new_stock.auction {
+            // 拍卖发行以清算价格配售，发行价改为清算价格；没有投标时保持最低价格
+            let bids: Vec<(Price, i64)> = subscriptions.iter()
+                .map(|sub| (sub.price.unwrap_or(new_stock.offer_price), sub.amount / new_stock.lot_size))
+                .collect();
+            let (clearing_price, allocated_lots) = auction(&bids, available, method, seed);
+            if let Some(clearing_price) = clearing_price {
+                new_stock.offer_price = clearing_price;
+            }
+            allocated_lots
+        } else {
+            let requests: Vec<i64> = subscriptions.iter().map(|sub| sub.amount / new_stock.lot_size).collect();
+            method.allocate(&requests, available, seed)
+        };
+
+        let mut total_allocated = 0;
+        for (mut sub, lots) in subscriptions.into_iter().zip(allocated_lots) {
+            let allocated = lots * new_stock.lot_size;
+            let cost = new_stock.offer_price.value(allocated);
+            let commission = fees.commission(cost, Liquidity::Taker);
+
+            sub.allocated = allocated;
+            sub.refund = sub.frozen - cost - commission;
+            sub.allocated_at = Some(now);
+            sub.save_changes::<IPOSubscription>(conn).map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库重设新股认购错误：{}", db_err))
+                })?;
+
+            add_balance(sub.user_id, &currency, sub.refund, conn)?;
+
+            if allocated == 0 {
+                continue;
+            }
+            total_allocated += allocated;
+
+            credit_house(commission, &currency, fees, conn)?;
+
+            // 加交易、加股票
+            let deal = NewDeal {
+                buy_user_id: sub.user_id,
+                sell_user_id: None,
+                stock_id: stock_id,
+                price: new_stock.offer_price,
+                amount: allocated,
+                created_at: now,
+                buyer_commission: commission,
+                seller_commission: Money::zero(),
+                stamp_duty: Money::zero()
+            };
+
+            diesel::insert_into(dldsl::deals).values(&deal)
+                .execute(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库插入交易错误：{}", db_err))
+                })?;
+
+            diesel::insert_into(reldsl::user_hold_stock)
+                .values(
+                    UserStockRel {
+                        user_id: deal.buy_user_id,
+                        stock_id: deal.stock_id,
+                        hold: deal.amount,
+                        updated_at: now
+                    }
+                )
+                .on_conflict((reldsl::user_id, reldsl::stock_id))
+                .do_update()
+                .set((
+                    reldsl::hold.eq(reldsl::hold + deal.amount),
+                    reldsl::updated_at.eq(now)
+                ))
+                .execute(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库重设买家股票数量错误：{}", db_err))
+                })?;
+        }
+
+        new_stock.offer_unfulfilled -= total_allocated;
+        new_stock.allocated_at = Some(now);
+        new_stock.save_changes::<NewStock>(conn).map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库重设 IPO 发行余量错误：{}", db_err))
+        })?;
+
+        Ok(true)
+    })
+}
+
+// 锁定期内发行人不能卖出、转让或出借该股票；设置了锁定新股认购者时，上市前认购到新股的用户也不能
+pub fn check_lockup(stock_id: i64, user_id: i64, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+    use crate::schema::deals::dsl as dldsl;
+
+    let query = newdsl::new_stocks
+                    .inner_join(stkdsl::stocks)
+                    .filter(newdsl::id.eq(stock_id))
+                    .select((newdsl::issuer_id, newdsl::lockup_days, newdsl::lockup_allottees, stkdsl::into_market_at));
+
+    debug!("Check lockup SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let (issuer_id, lockup_days, lockup_allottees, into_market_at) = match query.get_result::<(i64, Option<i32>, bool, Option<chrono::NaiveDateTime>)>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })? {
+        Some(found) => found,
+        None => return Ok(())
+    };
+
+    let (lockup_days, listed_at) = match (lockup_days, into_market_at) {
+        (Some(days), Some(listed_at)) => (days, listed_at),
+        _ => return Ok(())
+    };
+    let lockup_ends = listed_at + chrono::Duration::days(lockup_days as i64);
+    if chrono::Utc::now().naive_utc() >= lockup_ends {
+        return Ok(());
+    }
+
+    if user_id == issuer_id {
+        return Err(EngineError::BadRequest(format!("该股票的锁定期至 {} 结束，发行人在锁定期内不能卖出、转让或出借。", lockup_ends)));
+    }
+
+    if lockup_allottees {
+        let query = dldsl::deals
+                        .filter(
+                            dldsl::stock_id.eq(stock_id).and(
+                                dldsl::buy_user_id.eq(user_id)
+                            ).and(
+                                dldsl::sell_user_id.is_null()
+                            ).and(
+                                dldsl::created_at.le(listed_at)
+                            )
+                        )
+                        .count();
+
+        debug!("Check lockup allottee SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let allotments = query.get_result::<i64>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        if allotments > 0 {
+            return Err(EngineError::BadRequest(format!("该股票的锁定期至 {} 结束，新股认购者在锁定期内不能卖出、转让或出借。", lockup_ends)));
+        }
+    }
+
+    Ok(())
+}
+
+// 定期配售认购期已结束的新股，并处理截止时间已到的新股，在单独的线程中运行
+pub fn run_ipo_worker(pool: Pool, fees: FeeSchedule, notify: UserFeed, interval: std::time::Duration) {
+    std::thread::spawn(move || {
+        loop {
+            match pool.get() {
+                Ok(conn) => {
+                    if let Err(err) = process_ipos(&fees, &notify, &conn) {
+                        error!("Processing IPOs failed: {}", err);
+                    }
+                },
+                Err(pool_err) => error!("Processing IPOs failed, cannot get database connection: {}", pool_err)
+            }
+            std::thread::sleep(interval);
+        }
+    });
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct SubscribeIPOModel {
+    pub amount: u64,
+    pub price: Option<Price>    // 拍卖发行的投标价格，固定价格发行时不填
+}
+
+pub fn subscribe_ipo(
+    stock_id: web::Path<u64>,
+    subscribe: web::Json<SubscribeIPOModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    fees: web::Data<FeeSchedule>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            subscribe_ipo_query(stock_id.into_inner(), subscribe.into_inner(), curr_user, pool, fees)
+        }
+    ).then(
+        move |res: Result<IPOSubscription, BlockingError<EngineError>>|
+            match res {
+                Ok(subscription) => Ok(HttpResponse::Ok().json(subscription)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+// 认购时冻结认购金额和佣金，每个用户每只新股只能认购一次。拍卖发行按投标价格冻结
+fn subscribe_ipo_query(stock_id: u64, subscribe: SubscribeIPOModel, user: RememberUserModel, pool: web::Data<Pool>, fees: web::Data<FeeSchedule>) -> Result<IPOSubscription, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+    use crate::schema::ipo_subscriptions::dsl as subdsl;
+
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+    let amount = i64::try_from(subscribe.amount).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    conn.transaction(|| {
+        // 保证原子性
+        let query = newdsl::new_stocks
+                        .inner_join(stkdsl::stocks)
+                        .filter(
+                            newdsl::id.eq(stock_id).and(
+                                stkdsl::into_market.eq(false)
+                            )
+                        )
+                        .select((crate::schema::new_stocks::all_columns, stkdsl::currency, stkdsl::price_decimals));
+
+        debug!("Subscribe IPO SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let (new_stock, currency, price_decimals) = query.get_result::<(NewStock, String, i16)>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::BadRequest(format!("没有该股票或该股票已经上市，不能认购！")))?;
+
+        let ends_at = new_stock.subscribe_ends_at
+            .ok_or_else(|| EngineError::BadRequest(format!("该股票先到先得，请直接买入新股！")))?;
+        if ends_at <= chrono::Utc::now().naive_utc() {
+            return Err(EngineError::BadRequest(format!("该股票的认购期已于 {} 结束！", ends_at)));
+        }
+        if amount <= 0 || amount % new_stock.lot_size != 0 {
+            return Err(EngineError::BadRequest(format!("认购数量必须是每手股数 {} 的正整数倍！", new_stock.lot_size)));
+        }
+
+        let price = if new_stock.auction {
+            let price = subscribe.price
+                .ok_or_else(|| EngineError::BadRequest(format!("拍卖发行必须给出投标价格！")))?;
+            if price < new_stock.offer_price {
+                return Err(EngineError::BadRequest(format!("投标价格不能低于最低价格 {} 元！", new_stock.offer_price)));
+            }
+            if !price.is_on_tick(price_decimals) {
+                return Err(EngineError::BadRequest(format!("投标价格 {} 元不符合该股票的最小变动单位 {} 元！", price, Price(price_tick(price_decimals)))));
+            }
+            price
+        } else {
+            if subscribe.price.is_some() {
+                return Err(EngineError::BadRequest(format!("固定价格发行不能给出投标价格！")));
+            }
+            new_stock.offer_price
+        };
+
+        // 检查钱，冻结（认购新股按吃单费率收取佣金）
+        let cost = price.checked_value(amount)
+            .ok_or_else(|| EngineError::BadRequest(format!("认购金额过大！")))?;
+        let frozen = cost + fees.commission(cost, Liquidity::Taker);
+
+        let balance_after = add_balance(user.id, &currency, -frozen, conn)?;
+
+        if balance_after < Money::zero() {
+            let err_msg = format!("{} 账户余额不足（含佣金），你还需要 {} {}来认购新股。", currency, -balance_after, currency);
+            return Err(EngineError::Insufficient(
+                OrderResult {
+                    succeed: false,
+                    message: Some(err_msg.clone()),
+                    error: Some(err_msg),
+                    deal_amount: None,
+                    lack: Some((-balance_after).0)
+                }
+            ));
+        }
+
+        let query = diesel::insert_into(subdsl::ipo_subscriptions)
+                        .values((
+                            subdsl::stock_id.eq(stock_id),
+                            subdsl::user_id.eq(user.id),
+                            subdsl::amount.eq(amount),
+                            subdsl::frozen.eq(frozen),
+                            subdsl::created_at.eq(chrono::Utc::now().naive_utc()),
+                            subdsl::price.eq(if new_stock.auction { Some(price) } else { None })
+                        ))
+                        .on_conflict_do_nothing();
+
+        debug!("Insert IPO subscription SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        query.get_result::<IPOSubscription>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库插入新股认购错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::BadRequest(format!("你已经认购过该股票，不能重复认购！")))
+    })
+}
+
+//////////////////
+#[derive(QueryableByName, Serialize)]
+pub struct IPOResultModel {
+    #[sql_type = "sql_types::BigInt"]
+    pub stock_id: i64,
+    #[sql_type = "sql_types::Varchar"]
+    pub stock_name: String,
+    #[sql_type = "sql_types::BigInt"]
+    pub user_id: i64,
+    #[sql_type = "sql_types::Varchar"]
+    pub user_name: String,
+    #[sql_type = "sql_types::BigInt"]
+    pub amount: i64,
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub price: Option<Price>,       // 拍卖发行的投标价格
+    #[sql_type = "sql_types::Int4"]
+    pub offer_price: Price,         // 发行价，拍卖发行配售后为清算价格
+    #[sql_type = "sql_types::BigInt"]
+    pub allocated: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub frozen: Money,
+    #[sql_type = "sql_types::BigInt"]
+    pub refund: Money,
+    #[sql_type = "sql_types::Nullable<sql_types::Varchar>"]
+    pub allocation: Option<String>,
+    #[sql_type = "sql_types::Nullable<sql_types::BigInt>"]
+    pub lottery_seed: Option<i64>,      // 摇号种子，可用于复现摇号结果，配售完成前为 null
+    #[sql_type = "sql_types::Timestamp"]
+    pub created_at: chrono::NaiveDateTime,
+    #[sql_type = "sql_types::Nullable<sql_types::Timestamp>"]
+    pub allocated_at: Option<chrono::NaiveDateTime>     // 尚未配售时为 null
+}
+
+pub fn get_ipo_results(
+    stock_id: web::Path<u64>,
+    paging: web::Query<PagingModel>,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_ipo_results_query(stock_id.into_inner(), paging, pool)
+        }
+    ).then(
+        move |res: Result<Vec<IPOResultModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(results) => Ok(HttpResponse::Ok().json(results)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_ipo_results_query(stock_id: u64, paging: PagingModel, pool: web::Data<Pool>) -> Result<Vec<IPOResultModel>, EngineError> {
+    use crate::schema::new_stocks::dsl as newdsl;
+
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    // 配售由后台任务或发行人上市时完成，查询不做配售
+    let query = newdsl::new_stocks
+                    .find(stock_id)
+                    .select((newdsl::subscribe_ends_at, newdsl::allocated_at));
+
+    debug!("Get IPO results allocated SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let (subscribe_ends_at, allocated_at) = query.get_result::<(Option<chrono::NaiveDateTime>, Option<chrono::NaiveDateTime>)>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?
+        .ok_or_else(|| EngineError::NotFound(format!("没有这只新股！")))?;
+
+    if subscribe_ends_at.is_some() && allocated_at.is_none() {
+        return Err(EngineError::BadRequest(format!("该新股尚未配售，还没有配售结果。")));
+    }
+
+    let query = diesel::sql_query(include_str!("iporesults.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.offset.unwrap_or(0)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.limit.unwrap_or(10)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get IPO results SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.load::<IPOResultModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+//////////////////
+pub fn get_my_ipo_subscriptions(
+    paging: web::Query<PagingModel>,
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_my_ipo_subscriptions_query(paging, user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<IPOResultModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(results) => Ok(HttpResponse::Ok().json(results)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_ipo_subscriptions_query(paging: PagingModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<IPOResultModel>, EngineError> {
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = diesel::sql_query(include_str!("myiposubscriptions.sql"))
+                    .bind::<sql_types::BigInt, _>(user.id)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.offset.unwrap_or(0)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.limit.unwrap_or(10)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get my IPO subscriptions SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.load::<IPOResultModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
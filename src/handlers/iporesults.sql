@@ -0,0 +1,23 @@
+SELECT
+	ipo_subscriptions.stock_id AS stock_id,
+	stocks.name AS stock_name,
+	ipo_subscriptions.user_id AS user_id,
+	users.name AS user_name,
+	ipo_subscriptions.amount AS amount,
+	ipo_subscriptions.price AS price,
+	new_stocks.offer_price AS offer_price,
+	ipo_subscriptions.allocated AS allocated,
+	ipo_subscriptions.frozen AS frozen,
+	ipo_subscriptions.refund AS refund,
+	new_stocks.allocation AS allocation,
+	CASE WHEN new_stocks.allocated_at IS NOT NULL THEN new_stocks.lottery_seed END AS lottery_seed,
+	ipo_subscriptions.created_at AS created_at,
+	ipo_subscriptions.allocated_at AS allocated_at
+FROM ipo_subscriptions
+INNER JOIN stocks ON stocks.id = ipo_subscriptions.stock_id
+INNER JOIN new_stocks ON new_stocks.id = ipo_subscriptions.stock_id
+INNER JOIN users ON users.id = ipo_subscriptions.user_id
+WHERE ipo_subscriptions.stock_id = $1
+ORDER BY
+	ipo_subscriptions.id ASC
+LIMIT $3 OFFSET $2;
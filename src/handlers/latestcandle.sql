@@ -0,0 +1,13 @@
+SELECT
+    bucket,
+    open,
+    high,
+    low,
+    close,
+    volume,
+    turnover
+FROM candles
+WHERE stock_id = $1
+    AND period = $2
+ORDER BY bucket DESC
+LIMIT 1;
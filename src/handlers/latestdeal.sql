@@ -1,26 +1,66 @@
 SELECT
-    price
-FROM (
-	SELECT
-		query_stock_id,
-		t1.*
-	FROM
-	unnest( $1 ) WITH ORDINALITY AS query(query_stock_id, ordinality)
-	LEFT JOIN
-	(
-		SELECT
-			DISTINCT ON (stock_id)
-			deals.stock_id AS stock_id,
-			deals.price AS price,
-			MAX(deals.created_at)
-				OVER (
-					PARTITION BY deals.stock_id
-				)
-			AS ts
-		FROM deals
-		WHERE deals.stock_id = ANY( $1 )
-			AND deals.sell_user_id IS NOT NULL
-	) AS t1
-	ON t1.stock_id = query_stock_id
-	ORDER BY ordinality
-) AS t;
\ No newline at end of file
+    last_deal.price AS price,
+    session.open AS open,
+    session.high AS high,
+    session.low AS low,
+    prev_session.close AS prev_close,
+    last_deal.price - prev_session.close AS change,
+    ROUND((last_deal.price - prev_session.close) * 100.0 / NULLIF(prev_session.close, 0), 2)::DOUBLE PRECISION AS change_percent,
+    COALESCE(session.volume, 0) AS volume,
+    COALESCE(session.turnover, 0) AS turnover,
+    ROUND(session.turnover::NUMERIC / NULLIF(session.volume, 0))::INTEGER AS vwap,
+    best_ask.price AS best_ask_price,
+    best_ask.amount AS best_ask_amount,
+    best_bid.price AS best_bid_price,
+    best_bid.amount AS best_bid_amount
+FROM
+    unnest( $1 ) WITH ORDINALITY AS query(query_stock_id, ordinality)
+LEFT JOIN LATERAL (
+    SELECT
+        ROUND(deals.price * split_adjustment(deals.stock_id, deals.created_at))::INTEGER AS price
+    FROM deals
+    WHERE deals.stock_id = query_stock_id
+        AND deals.sell_user_id IS NOT NULL
+    ORDER BY deals.created_at DESC, deals.id DESC
+    LIMIT 1
+) AS last_deal ON TRUE
+LEFT JOIN candles AS session
+ON
+    session.stock_id = query_stock_id
+        AND
+    session.period = '1d'
+        AND
+    session.bucket = $2
+LEFT JOIN LATERAL (
+    SELECT
+        candles.close
+    FROM candles
+    WHERE candles.stock_id = query_stock_id
+        AND candles.period = '1d'
+        AND candles.bucket < $2
+    ORDER BY candles.bucket DESC
+    LIMIT 1
+) AS prev_session ON TRUE
+LEFT JOIN LATERAL (
+    SELECT
+        user_ask_orders.price,
+        SUM(user_ask_orders.unfulfilled)::BIGINT AS amount
+    FROM user_ask_orders
+    WHERE user_ask_orders.stock_id = query_stock_id
+        AND user_ask_orders.unfulfilled != 0
+    GROUP BY user_ask_orders.price
+    ORDER BY user_ask_orders.price DESC
+    LIMIT 1
+) AS best_ask ON TRUE
+LEFT JOIN LATERAL (
+    SELECT
+        user_bid_orders.price,
+        SUM(user_bid_orders.unfulfilled)::BIGINT AS amount
+    FROM user_bid_orders
+    WHERE user_bid_orders.stock_id = query_stock_id
+        AND user_bid_orders.unfulfilled != 0
+    GROUP BY user_bid_orders.price
+    ORDER BY user_bid_orders.price ASC
+    LIMIT 1
+) AS best_bid ON TRUE
+ORDER BY ordinality;
\ No newline at end of file
@@ -0,0 +1,550 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+use crate::models::{BidOrder, MarginLoan};
+use crate::money::{Money, Price, DEFAULT_CURRENCY};
+use crate::margin::MarginSettings;
+use crate::fees::FeeSchedule;
+use crate::settlement::SettlementSchedule;
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::orders::{OrderResult, UserStockRel, BidOrderModel, EngineOrder, match_bid};
+use super::settlement::settle_due;
+use super::shorts::accrue_borrow_fees;
+use super::wallets::{add_balance, check_balance, check_currency};
+use super::notify::OrderUpdate;
+use super::history::{OrderEventKind, record_bid};
+
+use crate::schema::*;
+use diesel::sql_types;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/margin")
+        .service(
+            web::resource("/my/")
+                .route(web::get().to_async(get_my_margin))     // 查询自己各币种的融资账户
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/borrow")
+                .route(web::post().to_async(borrow))      // 以持仓为担保借入现金
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/repay")
+                .route(web::post().to_async(repay))      // 用现金偿还借款
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+// 某一币种下的融资账户，股票按最新成交价计算市值
+#[derive(QueryableByName, Debug)]
+pub struct MarginAccount {
+    #[sql_type = "sql_types::BigInt"]
+    pub cash: Money,            // 钱包余额、尚未交收的现金与卖空担保
+    #[sql_type = "sql_types::BigInt"]
+    pub frozen: Money,          // 买入委托冻结的现金
+    #[sql_type = "sql_types::BigInt"]
+    pub long_value: Money,      // 持仓市值，包括卖出委托冻结和尚未交收的股票
+    #[sql_type = "sql_types::BigInt"]
+    pub short_value: Money,     // 卖空仓位的市值
+    #[sql_type = "sql_types::BigInt"]
+    pub debt: Money             // 尚未归还的借款
+}
+
+impl MarginAccount {
+    // 净资产
+    pub fn equity(&self) -> Money {
+        self.cash + self.frozen + self.long_value - self.short_value - self.debt
+    }
+}
+
+pub fn margin_account(user_id: i64, currency: &str, conn: &PgConnection) -> Result<MarginAccount, EngineError> {
+    let query = diesel::sql_query(include_str!("marginaccount.sql"))
+                    .bind::<sql_types::BigInt, _>(user_id)
+                    .bind::<sql_types::Varchar, _>(currency);
+
+    debug!("Margin account SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_result::<MarginAccount>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+// 转出现金或股票之后检查该币种的融资账户：借款不能超过按剩余持仓计算的额度，净资产也不能低于维持担保比例，
+// 否则担保被转走后借款无法再通过强制平仓收回。action 用于错误提示，须在同一事务中、转出之后调用
+pub fn check_margin(user_id: i64, currency: &str, margin: &MarginSettings, action: &str, conn: &PgConnection) -> Result<(), EngineError> {
+    let account = margin_account(user_id, currency, conn)?;
+    if account.debt <= Money::zero() {
+        return Ok(());
+    }
+
+    let max_loan = margin.max_loan(account.long_value + account.frozen);
+    if account.debt > max_loan {
+        return Err(EngineError::BadRequest(format!("{}后 {} 融资借款 {} {} 将超过担保额度 {} {}，请先还款。", action, currency, account.debt, currency, max_loan, currency)));
+    }
+    if margin.below_maintenance(account.equity(), account.long_value) {
+        return Err(EngineError::BadRequest(format!("{}后 {} 融资账户的净资产将低于维持担保比例，请先还款。", action, currency)));
+    }
+
+    Ok(())
+}
+
+// 借入现金记入钱包，extra_collateral 为即将买入、尚未冻结的股票市值
+pub fn borrow_cash(user_id: i64, currency: &str, amount: Money, extra_collateral: Money, margin: &MarginSettings, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::margin_loans::dsl as mrgdsl;
+
+    let account = margin_account(user_id, currency, conn)?;
+    let max_loan = margin.max_loan(account.long_value + account.frozen + extra_collateral);
+    let lack = account.debt + amount - max_loan;
+
+    if lack > Money::zero() {
+        let err_msg = format!("{} 融资额度不足，最多可借 {} {}，已借 {} {}，还差 {} {}。", currency, max_loan, currency, account.debt, currency, lack, currency);
+        return Err(EngineError::Insufficient(
+            OrderResult {
+                succeed: false,
+                message: Some(err_msg.clone()),
+                error: Some(err_msg),
+                deal_amount: None,
+                lack: Some(lack.0)
+            }
+        ));
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let query = diesel::insert_into(mrgdsl::margin_loans)
+                    .values(
+                        MarginLoan {
+                            user_id,
+                            currency: currency.to_owned(),
+                            principal: amount,
+                            updated_at: now
+                        }
+                    )
+                    .on_conflict((mrgdsl::user_id, mrgdsl::currency))
+                    .do_update()
+                    .set((
+                        mrgdsl::principal.eq(mrgdsl::principal + amount),
+                        mrgdsl::updated_at.eq(now)
+                    ));
+
+    debug!("Borrow cash SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库更新融资借款错误：{}", db_err))
+        })?;
+
+    add_balance(user_id, currency, amount, conn)?;
+
+    Ok(())
+}
+
+// 减少最多 max_amount 的借款，返回实际偿还的金额；现金由调用者负责扣除
+pub fn repay_margin(user_id: i64, currency: &str, max_amount: Money, conn: &PgConnection) -> Result<Money, EngineError> {
+    use crate::schema::margin_loans::dsl as mrgdsl;
+
+    let query = mrgdsl::margin_loans
+                    .find((user_id, currency))
+                    .for_update();
+
+    debug!("Repay margin SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let loan = query.get_result::<MarginLoan>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    let mut loan = match loan {
+        Some(loan) => loan,
+        None => return Ok(Money::zero())
+    };
+
+    let repaid = std::cmp::max(std::cmp::min(loan.principal, max_amount), Money::zero());
+    if repaid == Money::zero() {
+        return Ok(repaid);
+    }
+
+    loan.principal -= repaid;
+    loan.updated_at = chrono::Utc::now().naive_utc();
+    loan.save_changes::<MarginLoan>(conn).map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库更新融资借款错误：{}", db_err))
+        })?;
+
+    Ok(repaid)
+}
+
+#[derive(QueryableByName, Debug)]
+struct MarginDebtor {
+    #[sql_type = "sql_types::BigInt"]
+    user_id: i64
+}
+
+// 成交改变了 stock_id 的最新成交价后，检查该币种下持有这只股票（含委托、待交收、借入）的借款用户，
+// 净资产低于维持担保比例的，自动提交卖出委托强制平仓，返回强制平仓委托的撮合结果
+pub fn enforce_maintenance(currency: &str, stock_id: i64, margin: &MarginSettings, fees: &FeeSchedule, settlement: &SettlementSchedule, conn: &PgConnection) -> Result<Vec<EngineOrder>, EngineError> {
+    // 强制平仓的成交又会改变其他股票的价格，再检查持有这些股票的用户，直到没有新的成交为止；每个用户只平仓一次，避免循环
+    let mut liquidated = HashSet::new();
+    let mut orders = Vec::new();
+    let mut changed = vec![stock_id];
+    while !changed.is_empty() {
+        let query = diesel::sql_query(include_str!("margindebtors.sql"))
+                        .bind::<sql_types::Varchar, _>(currency)
+                        .bind::<sql_types::Array<sql_types::BigInt>, _>(&changed);
+
+        debug!("Margin debtors SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let debtors = query.load::<MarginDebtor>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let mut traded = HashSet::new();
+        for debtor in debtors {
+            if liquidated.contains(&debtor.user_id) {
+                continue;
+            }
+            let placed = liquidate(debtor.user_id, currency, margin, fees, settlement, conn)?;
+            if !placed.is_empty() {
+                liquidated.insert(debtor.user_id);
+            }
+            for order in placed {
+                if !order.matched.deals.is_empty() {
+                    traded.insert(order.stock_id);
+                }
+                orders.push(order);
+            }
+        }
+
+        changed = traded.into_iter().collect();
+    }
+
+    Ok(orders)
+}
+
+#[derive(QueryableByName, Debug)]
+struct LiquidationPosition {
+    #[sql_type = "sql_types::BigInt"]
+    stock_id: i64,
+    #[sql_type = "sql_types::BigInt"]
+    hold: i64,
+    #[sql_type = "sql_types::Integer"]
+    price: Price        // 当前最高的买入委托价，没有时为最新成交价
+}
+
+// 维持担保比例不足时，按市值从大到小卖出持仓，返回提交的强制平仓委托的撮合结果
+fn liquidate(user_id: i64, currency: &str, margin: &MarginSettings, fees: &FeeSchedule, settlement: &SettlementSchedule, conn: &PgConnection) -> Result<Vec<EngineOrder>, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::user_bid_orders::dsl as biddsl;
+
+    // 已有未完成的强制平仓委托时，等待其成交
+    let query = biddsl::user_bid_orders
+                    .inner_join(stkdsl::stocks)
+                    .filter(
+                        biddsl::user_id.eq(user_id).and(
+                            biddsl::liquidation.eq(true)
+                        ).and(
+                            biddsl::unfulfilled.gt(0)
+                        ).and(
+                            stkdsl::currency.eq(currency)
+                        )
+                    )
+                    .count();
+
+    debug!("Open liquidation orders SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let open_orders = query.get_result::<i64>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    if open_orders > 0 {
+        return Ok(Vec::new());
+    }
+
+    let account = margin_account(user_id, currency, conn)?;
+    if !margin.below_maintenance(account.equity(), account.long_value) {
+        return Ok(Vec::new());
+    }
+
+    let mut remaining = margin.liquidation_value(account.equity(), account.long_value);
+    if remaining <= Money::zero() {
+        return Ok(Vec::new());
+    }
+
+    warn!("User {} is below maintenance margin in {}, liquidating {} {}", user_id, currency, remaining, currency);
+
+    let query = diesel::sql_query(include_str!("marginpositions.sql"))
+                    .bind::<sql_types::BigInt, _>(user_id)
+                    .bind::<sql_types::Varchar, _>(currency);
+
+    debug!("Liquidation positions SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let positions = query.load::<LiquidationPosition>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    let mut placed = Vec::new();
+    for position in positions {
+        if remaining <= Money::zero() {
+            break;
+        }
+        if position.price <= Price(0) {
+            continue;
+        }
+
+        let volume = std::cmp::min(position.hold, remaining.ratio_ceil(1, position.price.0 as i64).0);
+        remaining -= position.price.value(volume);
+
+        // 冻结要卖出的股票
+        let query = diesel::update(reldsl::user_hold_stock.find(
+                        (user_id, position.stock_id)
+                    ))
+                    .set((
+                        reldsl::hold.eq(reldsl::hold - volume),
+                        reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
+                    ));
+
+        debug!("Liquidation freeze stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        query.get_result::<UserStockRel>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库更新股票持有量错误：{}", db_err))
+            })?;
+
+        let query = diesel::insert_into(biddsl::user_bid_orders)
+                        .values(
+                            BidOrderModel {
+                                user_id,
+                                stock_id: position.stock_id,
+                                price: position.price,
+                                volume,
+                                unfulfilled: volume,
+                                created_at: chrono::Utc::now().naive_utc(),
+                                updated_at: chrono::Utc::now().naive_utc(),
+                                liquidation: true
+                            }
+                        );
+
+        debug!("Liquidation order SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let mut new_bid = query.get_result::<BidOrder>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库插入委托错误：{}", db_err))
+            })?;
+        record_bid(OrderEventKind::Inserted, &new_bid, conn)?;
+
+        // 与普通委托一样撮合，未成交的部分留在委托簿中
+        let accepted = OrderUpdate::from_bid(&new_bid);
+        let matched = match_bid(&mut new_bid, currency, fees, settlement, conn)?;
+        placed.push(EngineOrder { stock_id: position.stock_id, accepted, taker: OrderUpdate::from_bid(&new_bid), matched });
+    }
+
+    Ok(placed)
+}
+
+//////////////////
+#[derive(Serialize)]
+pub struct MarginAccountModel {
+    pub currency: String,
+    pub cash: Money,
+    pub frozen: Money,
+    pub long_value: Money,
+    pub short_value: Money,
+    pub debt: Money,
+    pub equity: Money,
+    pub max_loan: Money,            // 按当前持仓最多可借的总额
+    pub maintenance_equity: Money,  // 低于此净资产将被强制平仓
+}
+
+pub fn get_my_margin(
+    user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    margin: web::Data<MarginSettings>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            get_my_margin_query(user, pool, margin)
+        }
+    ).then(
+        move |res: Result<Vec<MarginAccountModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(accounts) => Ok(HttpResponse::Ok().json(accounts)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_margin_query(user: RememberUserModel, pool: web::Data<Pool>, margin: web::Data<MarginSettings>) -> Result<Vec<MarginAccountModel>, EngineError> {
+    use crate::schema::currencies::dsl as curdsl;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = curdsl::currencies
+                    .order(curdsl::code.asc())
+                    .select(curdsl::code);
+
+    debug!("Get my margin currencies SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let currencies = query.get_results::<String>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    currencies.into_iter()
+        .map(|currency| {
+            let account = margin_account(user.id, &currency, conn)?;
+            Ok(MarginAccountModel {
+                max_loan: margin.max_loan(account.long_value + account.frozen),
+                maintenance_equity: account.long_value.ratio_ceil(margin.maintenance_rate, crate::fees::RATE_UNIT),
+                equity: account.equity(),
+                currency,
+                cash: account.cash,
+                frozen: account.frozen,
+                long_value: account.long_value,
+                short_value: account.short_value,
+                debt: account.debt
+            })
+        })
+        .collect()
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct BorrowModel {
+    pub currency: Option<String>,   // 默认为人民币
+    pub amount: u64,
+}
+
+pub fn borrow(
+    borrow_model: web::Json<BorrowModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    margin: web::Data<MarginSettings>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            borrow_query(borrow_model.into_inner(), curr_user, pool, margin)
+        }
+    ).then(
+        move |res: Result<(), BlockingError<EngineError>>|
+            match res {
+                Ok(_) => Ok(HttpResponse::Ok().finish()),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn borrow_query(borrow_model: BorrowModel, user: RememberUserModel, pool: web::Data<Pool>, margin: web::Data<MarginSettings>) -> Result<(), EngineError> {
+    let amount = Money(i64::try_from(borrow_model.amount).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+    let currency = borrow_model.currency.unwrap_or_else(|| DEFAULT_CURRENCY.to_owned());
+
+    if amount <= Money::zero() {
+        return Err(EngineError::BadRequest(format!("借款金额必须大于 0。")));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    conn.transaction(|| {
+        // 保证原子性
+        check_currency(&currency, conn)?;
+        settle_due(user.id, conn)?;
+        accrue_borrow_fees(user.id, conn)?;
+
+        borrow_cash(user.id, &currency, amount, Money::zero(), &margin, conn)
+    })
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct RepayModel {
+    pub currency: Option<String>,   // 默认为人民币
+    pub amount: Option<u64>,        // 默认全部偿还
+}
+
+pub fn repay(
+    repay_model: web::Json<RepayModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            repay_query(repay_model.into_inner(), curr_user, pool)
+        }
+    ).then(
+        move |res: Result<(), BlockingError<EngineError>>|
+            match res {
+                Ok(_) => Ok(HttpResponse::Ok().finish()),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn repay_query(repay_model: RepayModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<(), EngineError> {
+    let amount = match repay_model.amount {
+        Some(amount) => Money(i64::try_from(amount).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?),
+        None => Money(std::i64::MAX)
+    };
+    let currency = repay_model.currency.unwrap_or_else(|| DEFAULT_CURRENCY.to_owned());
+
+    if amount <= Money::zero() {
+        return Err(EngineError::BadRequest(format!("还款金额必须大于 0。")));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    conn.transaction(|| {
+        // 保证原子性
+        check_currency(&currency, conn)?;
+        settle_due(user.id, conn)?;
+
+        let repaid = repay_margin(user.id, &currency, amount, conn)?;
+        if repaid == Money::zero() {
+            return Err(EngineError::BadRequest(format!("你没有需要偿还的 {} 借款。", currency)));
+        }
+
+        let balance_after = add_balance(user.id, &currency, -repaid, conn)?;
+        check_balance(balance_after, &currency, "偿还借款")
+    })
+}
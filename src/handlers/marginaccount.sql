@@ -0,0 +1,44 @@
+SELECT
+	(
+		COALESCE((SELECT user_wallets.balance FROM user_wallets WHERE user_wallets.user_id = $1 AND user_wallets.currency = $2), 0)
+		+ COALESCE((SELECT SUM(pending_settlements.amount) FROM pending_settlements WHERE pending_settlements.user_id = $1 AND pending_settlements.stock_id IS NULL AND pending_settlements.currency = $2), 0)
+		+ COALESCE((SELECT SUM(stock_loans.collateral) FROM stock_loans WHERE stock_loans.borrower_id = $1 AND stock_loans.closed_at IS NULL AND stock_loans.currency = $2), 0)
+	)::BIGINT AS cash,
+	COALESCE((
+		SELECT
+			SUM(user_ask_orders.price::BIGINT * user_ask_orders.unfulfilled + user_ask_orders.fee_frozen)
+		FROM user_ask_orders
+		INNER JOIN stocks ON stocks.id = user_ask_orders.stock_id
+		WHERE user_ask_orders.user_id = $1 AND stocks.currency = $2
+	), 0)::BIGINT AS frozen,
+	COALESCE(SUM(GREATEST(positions.amount, 0) * positions.mark), 0)::BIGINT AS long_value,
+	COALESCE(SUM(GREATEST(-positions.amount, 0) * positions.mark), 0)::BIGINT AS short_value,
+	COALESCE((SELECT margin_loans.principal FROM margin_loans WHERE margin_loans.user_id = $1 AND margin_loans.currency = $2), 0)::BIGINT AS debt
+FROM (
+	SELECT
+		t.stock_id AS stock_id,
+		SUM(t.amount) AS amount,
+		COALESCE(
+			(
+				SELECT ROUND(deals.price * split_adjustment(deals.stock_id, deals.created_at))::INTEGER
+				FROM deals
+				WHERE deals.stock_id = t.stock_id AND deals.sell_user_id IS NOT NULL
+				ORDER BY deals.created_at DESC, deals.id DESC
+				LIMIT 1
+			),
+			(SELECT new_stocks.offer_price FROM new_stocks WHERE new_stocks.id = t.stock_id),
+			0
+		)::BIGINT AS mark
+	FROM (
+		SELECT stock_id, hold AS amount FROM user_hold_stock WHERE user_id = $1
+		UNION ALL
+		SELECT stock_id, unfulfilled AS amount FROM user_bid_orders WHERE user_id = $1
+		UNION ALL
+		SELECT stock_id, amount FROM pending_settlements WHERE user_id = $1 AND stock_id IS NOT NULL
+		UNION ALL
+		SELECT stock_id, -amount AS amount FROM stock_loans WHERE borrower_id = $1 AND closed_at IS NULL
+	) AS t
+	INNER JOIN stocks ON stocks.id = t.stock_id
+	WHERE stocks.currency = $2
+	GROUP BY t.stock_id
+) AS positions;
@@ -0,0 +1,9 @@
+SELECT margin_loans.user_id AS user_id
+FROM margin_loans
+WHERE margin_loans.currency = $1 AND margin_loans.principal > 0 AND (
+	EXISTS (SELECT 1 FROM user_hold_stock WHERE user_hold_stock.user_id = margin_loans.user_id AND user_hold_stock.stock_id = ANY($2) AND user_hold_stock.hold <> 0)
+	OR EXISTS (SELECT 1 FROM user_bid_orders WHERE user_bid_orders.user_id = margin_loans.user_id AND user_bid_orders.stock_id = ANY($2) AND user_bid_orders.unfulfilled > 0)
+	OR EXISTS (SELECT 1 FROM pending_settlements WHERE pending_settlements.user_id = margin_loans.user_id AND pending_settlements.stock_id = ANY($2))
+	OR EXISTS (SELECT 1 FROM stock_loans WHERE stock_loans.borrower_id = margin_loans.user_id AND stock_loans.stock_id = ANY($2) AND stock_loans.closed_at IS NULL)
+)
+ORDER BY margin_loans.user_id ASC;
@@ -0,0 +1,30 @@
+SELECT
+	t.stock_id AS stock_id,
+	t.hold AS hold,
+	t.price AS price
+FROM (
+	SELECT
+		user_hold_stock.stock_id AS stock_id,
+		user_hold_stock.hold AS hold,
+		COALESCE(
+			(
+				SELECT MAX(user_ask_orders.price)
+				FROM user_ask_orders
+				WHERE user_ask_orders.stock_id = user_hold_stock.stock_id AND user_ask_orders.unfulfilled > 0
+			),
+			(
+				SELECT ROUND(deals.price * split_adjustment(deals.stock_id, deals.created_at))::INTEGER
+				FROM deals
+				WHERE deals.stock_id = user_hold_stock.stock_id AND deals.sell_user_id IS NOT NULL
+				ORDER BY deals.created_at DESC, deals.id DESC
+				LIMIT 1
+			),
+			(SELECT new_stocks.offer_price FROM new_stocks WHERE new_stocks.id = user_hold_stock.stock_id)
+		) AS price
+	FROM user_hold_stock
+	INNER JOIN stocks ON stocks.id = user_hold_stock.stock_id
+	WHERE user_hold_stock.user_id = $1 AND user_hold_stock.hold > 0
+		AND stocks.currency = $2 AND stocks.into_market = TRUE
+) AS t
+WHERE t.price IS NOT NULL
+ORDER BY t.hold * t.price::BIGINT DESC;
@@ -0,0 +1,528 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::web::{Bytes, BytesMut};
+use actix_http::ws::{self, CloseCode, Message};
+use actix_web::error::BlockingError;
+use crate::money::{Money, Price};
+use crate::candles::{Candle, Interval};
+use crate::websocket::{self, MessageReader};
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use futures::{Async, Future, Poll, Stream};
+use futures::sync::mpsc;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::overview;
+use super::orders::NewDeal;
+use super::quotation::{OrderByPriceModel, CandleRowModel, DEFAULT_DEPTH};
+
+use diesel::sql_types;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/market")
+        .service(
+            web::resource("/stream")
+                .route(web::get().to(market_stream))      // WebSocket 行情推送
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/gainers")
+                .route(web::get().to_async(overview::get_gainers))      // 当天涨幅榜
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/losers")
+                .route(web::get().to_async(overview::get_losers))      // 当天跌幅榜
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/active")
+                .route(web::get().to_async(overview::get_most_active))      // 当天成交量榜
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/index")
+                .route(web::get().to_async(overview::get_index))      // 综合指数的最新点位和涨跌
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/index/candles")
+                .route(web::get().to_async(overview::get_index_candles))      // 综合指数的 K 线
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+// 行情推送的频道，每只股票的每个频道各自编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Trades,     // 逐笔成交
+    Depth,      // 买卖五档
+    Candles(Interval)   // 最新一根 K 线
+}
+
+impl Channel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Trades => "trades",
+            Channel::Depth => "depth",
+            Channel::Candles(_) => "candles",
+        }
+    }
+
+    pub fn from_names(channel: &str, interval: Option<&str>) -> Result<Channel, String> {
+        match (channel, interval) {
+            ("trades", _) => Ok(Channel::Trades),
+            ("depth", _) => Ok(Channel::Depth),
+            ("candles", Some(interval)) => Interval::from_name(interval)
+                .map(Channel::Candles)
+                .ok_or_else(|| format!("不支持的 K 线周期 {}，可选 1m、5m、15m、1h、1d、1w。", interval)),
+            ("candles", None) => Err(format!("订阅 K 线必须指定周期 interval。")),
+            (channel, _) => Err(format!("没有 {} 频道，可选 trades、depth、candles。", channel))
+        }
+    }
+
+    fn interval(&self) -> Option<&'static str> {
+        match self {
+            Channel::Candles(interval) => Some(interval.as_str()),
+            _ => None
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FeedMessage<'a> {
+    channel: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval: Option<&'static str>,
+    stock_id: i64,
+    seq: u64,           // 该频道的序号，增量消息逐一加一；快照的序号为订阅时的最新序号
+    snapshot: bool,
+    data: &'a serde_json::Value
+}
+
+#[derive(Serialize)]
+pub struct TradeModel {
+    pub price: Price,
+    pub amount: i64,
+    pub created_at: chrono::NaiveDateTime
+}
+
+#[derive(Serialize)]
+pub struct DepthModel {
+    pub ask_prices: Vec<OrderByPriceModel>,
+    pub bid_prices: Vec<OrderByPriceModel>
+}
+
+struct FeedSession {
+    sender: mpsc::UnboundedSender<Bytes>,
+    subscriptions: HashSet<(i64, Channel)>
+}
+
+#[derive(Default)]
+struct FeedState {
+    next_session_id: u64,
+    sessions: HashMap<u64, FeedSession>,
+    sequences: HashMap<(i64, Channel), u64>
+}
+
+// 行情推送的订阅关系和各频道的序号，作为应用数据在所有工作线程间共享
+#[derive(Clone, Default)]
+pub struct MarketFeed {
+    state: Arc<Mutex<FeedState>>
+}
+
+impl MarketFeed {
+    fn state(&self) -> MutexGuard<'_, FeedState> {
+        // 持锁的线程崩溃不影响订阅关系的一致性，继续使用
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn register(&self, sender: mpsc::UnboundedSender<Bytes>) -> u64 {
+        let mut state = self.state();
+        state.next_session_id += 1;
+        let session_id = state.next_session_id;
+        state.sessions.insert(session_id, FeedSession {
+            sender,
+            subscriptions: HashSet::new()
+        });
+        session_id
+    }
+
+    fn unregister(&self, session_id: u64) {
+        self.state().sessions.remove(&session_id);
+    }
+
+    // 返回该频道当前的序号
+    fn subscribe(&self, session_id: u64, stock_id: i64, channel: Channel) -> u64 {
+        let mut state = self.state();
+        if let Some(session) = state.sessions.get_mut(&session_id) {
+            session.subscriptions.insert((stock_id, channel));
+        }
+        state.sequences.get(&(stock_id, channel)).cloned().unwrap_or(0)
+    }
+
+    fn unsubscribe(&self, session_id: u64, stock_id: i64, channel: Channel) {
+        if let Some(session) = self.state().sessions.get_mut(&session_id) {
+            session.subscriptions.remove(&(stock_id, channel));
+        }
+    }
+
+    fn subscribed_channels(&self, stock_id: i64) -> HashSet<Channel> {
+        self.state().sessions.values()
+            .flat_map(|session| session.subscriptions.iter())
+            .filter(|(subscribed_stock, _)| *subscribed_stock == stock_id)
+            .map(|(_, channel)| *channel)
+            .collect()
+    }
+
+    fn publish(&self, stock_id: i64, channel: Channel, data: &serde_json::Value) {
+        let mut state = self.state();
+        let seq = {
+            let seq = state.sequences.entry((stock_id, channel)).or_insert(0);
+            *seq += 1;
+            *seq
+        };
+        let frame = feed_frame(stock_id, channel, seq, false, data);
+        // 连接已断开的会话在发送失败时移除
+        state.sessions.retain(|_, session| {
+            !session.subscriptions.contains(&(stock_id, channel)) || session.sender.unbounded_send(frame.clone()).is_ok()
+        });
+    }
+
+    // 委托提交并撮合后推送新的成交、盘口和 K 线，只查询有人订阅的频道
+    pub fn publish_deals(&self, stock_id: i64, deals: &[NewDeal], conn: &PgConnection) -> Result<(), EngineError> {
+        let channels = self.subscribed_channels(stock_id);
+
+        if !deals.is_empty() && channels.contains(&Channel::Trades) {
+            let trades: Vec<TradeModel> = deals.iter()
+                .map(|deal| TradeModel {
+                    price: deal.price,
+                    amount: deal.amount,
+                    created_at: deal.created_at
+                })
+                .collect();
+            self.publish(stock_id, Channel::Trades, &to_json(&trades)?);
+        }
+
+        if channels.contains(&Channel::Depth) {
+            self.publish(stock_id, Channel::Depth, &to_json(&load_depth(stock_id, conn)?)?);
+        }
+
+        if !deals.is_empty() {
+            for channel in channels {
+                if let Channel::Candles(interval) = channel {
+                    if let Some(candle) = load_latest_candle(stock_id, interval, conn)? {
+                        self.publish(stock_id, channel, &to_json(&candle)?);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // 撤单后推送新的盘口
+    pub fn publish_book(&self, stock_id: i64, conn: &PgConnection) -> Result<(), EngineError> {
+        self.publish_deals(stock_id, &[], conn)
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<serde_json::Value, EngineError> {
+    serde_json::to_value(value).map_err(|json_err| {
+        EngineError::InternalError(format!("内部 JSON 转换错误：{}", json_err))
+    })
+}
+
+fn feed_frame(stock_id: i64, channel: Channel, seq: u64, snapshot: bool, data: &serde_json::Value) -> Bytes {
+    let message = FeedMessage {
+        channel: channel.name(),
+        interval: channel.interval(),
+        stock_id,
+        seq,
+        snapshot,
+        data
+    };
+    websocket::encode(Message::Text(serde_json::to_string(&message).unwrap_or_default()))
+}
+
+fn error_frame(message: &str) -> Bytes {
+    websocket::encode(Message::Text(serde_json::json!({ "error": message }).to_string()))
+}
+
+pub fn load_depth(stock_id: i64, conn: &PgConnection) -> Result<DepthModel, EngineError> {
+    let query = diesel::sql_query(include_str!("askquote.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(Some(DEFAULT_DEPTH as i64));
+
+    debug!("Get ask quote SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let ask_prices = query.load::<OrderByPriceModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    let query = diesel::sql_query(include_str!("bidquote.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(Some(DEFAULT_DEPTH as i64));
+
+    debug!("Get bid quote SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let bid_prices = query.load::<OrderByPriceModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    Ok(DepthModel {
+        ask_prices,
+        bid_prices
+    })
+}
+
+pub fn load_latest_candle(stock_id: i64, interval: Interval, conn: &PgConnection) -> Result<Option<Candle>, EngineError> {
+    let query = diesel::sql_query(include_str!("latestcandle.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Varchar, _>(interval.as_str());
+
+    debug!("Get latest candle SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let row = query.get_result::<CandleRowModel>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    Ok(row.map(|row| row.into_candle(interval).1))
+}
+
+fn load_recent_trades(stock_id: i64, conn: &PgConnection) -> Result<Vec<TradeModel>, EngineError> {
+    use crate::schema::deals::dsl as dldsl;
+
+    let query = dldsl::deals.filter(
+                    dldsl::stock_id.eq(stock_id).and(
+                        dldsl::sell_user_id.is_not_null()
+                    )
+                )
+                .order_by((dldsl::created_at.desc(), dldsl::id.desc()))
+                .select((dldsl::price, dldsl::amount, dldsl::created_at))
+                .limit(5);
+
+    debug!("Get recent trades SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let trades = query.get_results::<(Price, i64, chrono::NaiveDateTime)>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    Ok(trades.into_iter()
+        .rev()
+        .map(|(price, amount, created_at)| TradeModel {
+            price,
+            amount,
+            created_at
+        })
+        .collect())
+}
+
+// 订阅时发送的快照：最近 5 笔成交、当前盘口或最新一根 K 线
+fn load_snapshot(stock_id: i64, channel: Channel, pool: &Pool) -> Result<serde_json::Value, EngineError> {
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    match channel {
+        Channel::Trades => to_json(&load_recent_trades(stock_id, conn)?),
+        Channel::Depth => to_json(&load_depth(stock_id, conn)?),
+        Channel::Candles(interval) => to_json(&load_latest_candle(stock_id, interval, conn)?)
+    }
+}
+
+//////////////////
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum StreamCommand {
+    Subscribe {
+        stock_id: u64,
+        channel: String,
+        interval: Option<String>
+    },
+    Unsubscribe {
+        stock_id: u64,
+        channel: String,
+        interval: Option<String>
+    }
+}
+
+// 一个 WebSocket 连接：解析客户端的订阅命令，并把订阅频道的消息写回客户端
+struct MarketSession {
+    session_id: u64,
+    feed: MarketFeed,
+    pool: Pool,
+    payload: web::Payload,
+    buffer: BytesMut,
+    reader: MessageReader,
+    sender: mpsc::UnboundedSender<Bytes>,
+    receiver: mpsc::UnboundedReceiver<Bytes>,
+    snapshots: Vec<Box<dyn Future<Item = Bytes, Error = ()>>>,
+    closing: bool
+}
+
+impl MarketSession {
+    fn reply(&self, frame: Bytes) {
+        // 接收端属于本会话，不会先于发送端关闭
+        let _ = self.sender.unbounded_send(frame);
+    }
+
+    fn close(&mut self, code: CloseCode) {
+        if !self.closing {
+            self.reply(websocket::encode(Message::Close(Some(code.into()))));
+            self.closing = true;
+            self.feed.unregister(self.session_id);
+        }
+    }
+
+    fn handle_text(&mut self, text: &[u8]) {
+        let command = match serde_json::from_slice::<StreamCommand>(text) {
+            Ok(command) => command,
+            Err(json_err) => return self.reply(error_frame(&format!("无法解析订阅命令：{}", json_err)))
+        };
+
+        let (subscribe, stock_id, channel, interval) = match command {
+            StreamCommand::Subscribe { stock_id, channel, interval } => (true, stock_id, channel, interval),
+            StreamCommand::Unsubscribe { stock_id, channel, interval } => (false, stock_id, channel, interval)
+        };
+        let stock_id = match i64::try_from(stock_id) {
+            Ok(stock_id) => stock_id,
+            Err(try_err) => return self.reply(error_frame(&format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))
+        };
+        let channel = match Channel::from_names(&channel, interval.as_ref().map(String::as_str)) {
+            Ok(channel) => channel,
+            Err(err) => return self.reply(error_frame(&err))
+        };
+
+        if !subscribe {
+            return self.feed.unsubscribe(self.session_id, stock_id, channel);
+        }
+
+        // 先登记订阅再查询快照，快照之后的增量消息序号必然大于快照的序号；重复订阅即重新获取快照
+        let seq = self.feed.subscribe(self.session_id, stock_id, channel);
+        let pool = self.pool.clone();
+        self.snapshots.push(Box::new(
+            web::block(move || load_snapshot(stock_id, channel, &pool))
+                .then(move |res: Result<serde_json::Value, BlockingError<EngineError>>| Ok(match res {
+                    Ok(data) => feed_frame(stock_id, channel, seq, true, &data),
+                    Err(BlockingError::Error(eng_err)) => error_frame(&format!("{}", eng_err)),
+                    Err(BlockingError::Canceled) => error_frame("不明原因，内部请求被中断。服务端遇到错误。")
+                }))
+        ));
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        match message {
+            Message::Text(text) => self.handle_text(text.as_bytes()),
+            Message::Binary(_) => self.reply(error_frame("订阅命令必须是 JSON 文本。")),
+            Message::Ping(text) => self.reply(websocket::encode(Message::Pong(text))),
+            Message::Pong(_) | Message::Nop => (),
+            Message::Close(_) => self.close(CloseCode::Normal)
+        }
+    }
+}
+
+impl Stream for MarketSession {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        // 读取客户端发来的帧
+        while !self.closing {
+            match self.payload.poll() {
+                Ok(Async::Ready(Some(chunk))) => {
+                    self.buffer.extend_from_slice(&chunk);
+                    loop {
+                        match self.reader.read(&mut self.buffer) {
+                            Ok(Some(message)) => self.handle_message(message),
+                            Ok(None) => break,
+                            Err(err) => {
+                                debug!("WebSocket protocol error: {}", err);
+                                self.reply(error_frame(&err));
+                                self.close(CloseCode::Protocol);
+                                break;
+                            }
+                        }
+                    }
+                },
+                Ok(Async::Ready(None)) | Err(_) => {
+                    self.closing = true;
+                    self.feed.unregister(self.session_id);
+                },
+                Ok(Async::NotReady) => break
+            }
+        }
+
+        // 已查询完的快照依次发出
+        let snapshots = std::mem::replace(&mut self.snapshots, Vec::new());
+        for mut snapshot in snapshots {
+            match snapshot.poll() {
+                Ok(Async::Ready(frame)) => self.reply(frame),
+                Ok(Async::NotReady) => self.snapshots.push(snapshot),
+                Err(_) => ()
+            }
+        }
+
+        match self.receiver.poll() {
+            Ok(Async::Ready(Some(frame))) => Ok(Async::Ready(Some(frame))),
+            Ok(Async::NotReady) if !self.closing => Ok(Async::NotReady),
+            _ => Ok(Async::Ready(None))
+        }
+    }
+}
+
+impl Drop for MarketSession {
+    fn drop(&mut self) {
+        self.feed.unregister(self.session_id);
+    }
+}
+
+// 客户端连接后发送 {"op": "subscribe", "stock_id": 1, "channel": "trades"} 订阅，
+// channel 可选 trades、depth、candles（须同时指定 interval），unsubscribe 取消订阅
+pub fn market_stream(
+    req: HttpRequest,
+    payload: web::Payload,
+    _: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    feed: web::Data<MarketFeed>
+) -> Result<HttpResponse, EngineError> {
+    // 握手由 actix-http 校验请求头并生成响应
+    let mut response = ws::handshake(req.head())
+        .map_err(|ws_err| EngineError::BadRequest(format!("请使用 WebSocket 连接行情推送：{}。", ws_err)))?;
+
+    let (sender, receiver) = mpsc::unbounded();
+    let feed = feed.get_ref().clone();
+    let session_id = feed.register(sender.clone());
+
+    let session = MarketSession {
+        session_id,
+        feed,
+        pool: pool.get_ref().clone(),
+        payload,
+        buffer: BytesMut::new(),
+        reader: MessageReader::default(),
+        sender,
+        receiver,
+        snapshots: Vec::new(),
+        closing: false
+    };
+
+    Ok(response.streaming(session))
+}
@@ -4,6 +4,21 @@ pub mod recharge;
 pub mod orders;
 pub mod quotation;
 pub mod favorite;
+pub mod transfer;
+pub mod settlement;
+pub mod wallets;
+pub mod shorts;
+pub mod margin;
+pub mod dividends;
+pub mod splits;
+pub mod offerings;
+pub mod ipo;
+pub mod buybacks;
+pub mod market;
+pub mod notify;
+pub mod overview;
+pub mod history;
+pub mod indicators;
 
 pub use stocks::PagingModel;
 pub use stocks::PagingOrder;
\ No newline at end of file
@@ -0,0 +1,53 @@
+SELECT
+    movers.stock_id,
+    movers.name,
+    movers.currency,
+    movers.price,
+    movers.prev_close,
+    movers.change,
+    movers.change_percent,
+    movers.volume,
+    movers.turnover
+FROM (
+    SELECT
+        stocks.id AS stock_id,
+        stocks.name AS name,
+        stocks.currency AS currency,
+        session.close AS price,
+        prev_session.close AS prev_close,
+        session.close - prev_session.close AS change,
+        ROUND((session.close - prev_session.close) * 100.0 / NULLIF(prev_session.close, 0), 2)::DOUBLE PRECISION AS change_percent,
+        session.volume AS volume,
+        session.turnover AS turnover
+    FROM stocks
+    INNER JOIN candles AS session
+    ON
+        session.stock_id = stocks.id
+            AND
+        session.period = '1d'
+            AND
+        session.bucket = $1
+    LEFT JOIN LATERAL (
+        SELECT
+            candles.close
+        FROM candles
+        WHERE candles.stock_id = stocks.id
+            AND candles.period = '1d'
+            AND candles.bucket < $1
+        ORDER BY candles.bucket DESC
+        LIMIT 1
+    ) AS prev_session ON TRUE
+    WHERE stocks.into_market
+) AS movers
+WHERE
+    ($2 = 'gainers' AND movers.change_percent > 0)
+        OR
+    ($2 = 'losers' AND movers.change_percent < 0)
+        OR
+    $2 = 'active'
+ORDER BY
+    CASE WHEN $2 = 'gainers' THEN movers.change_percent END DESC,
+    CASE WHEN $2 = 'losers' THEN movers.change_percent END ASC,
+    CASE WHEN $2 = 'active' THEN movers.volume END DESC,
+    movers.stock_id ASC
+LIMIT $3;
\ No newline at end of file
@@ -8,7 +8,10 @@ SELECT
 	sell_users.name AS sell_user_name,
 	price,
 	amount,
-	deals.created_at AS created_at
+	deals.created_at AS created_at,
+	buyer_commission,
+	seller_commission,
+	stamp_duty
 FROM
 	deals
 		INNER JOIN
@@ -0,0 +1,18 @@
+SELECT
+	dividends.id AS dividend_id,
+	dividends.stock_id AS stock_id,
+	stocks.name AS stock_name,
+	dividends.currency AS currency,
+	dividends.amount_per_share AS amount_per_share,
+	dividend_entitlements.shares AS shares,
+	dividend_entitlements.amount AS amount,
+	dividends.record_date AS record_date,
+	dividends.pay_date AS pay_date,
+	dividends.paid_at AS paid_at
+FROM dividend_entitlements
+INNER JOIN dividends ON dividends.id = dividend_entitlements.dividend_id
+INNER JOIN stocks ON stocks.id = dividends.stock_id
+WHERE dividend_entitlements.user_id = $1
+ORDER BY
+	dividends.record_date DESC, dividends.id DESC
+LIMIT $3 OFFSET $2;
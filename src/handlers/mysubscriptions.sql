@@ -0,0 +1,18 @@
+SELECT
+	offering_subscriptions.id AS id,
+	offering_subscriptions.offering_id AS offering_id,
+	offerings.stock_id AS stock_id,
+	stocks.name AS stock_name,
+	offerings.currency AS currency,
+	offerings.price AS price,
+	offering_subscriptions.amount AS amount,
+	offering_subscriptions.cost AS cost,
+	offering_subscriptions.commission AS commission,
+	offering_subscriptions.created_at AS created_at
+FROM offering_subscriptions
+INNER JOIN offerings ON offerings.id = offering_subscriptions.offering_id
+INNER JOIN stocks ON stocks.id = offerings.stock_id
+WHERE offering_subscriptions.user_id = $1
+ORDER BY
+	offering_subscriptions.created_at DESC, offering_subscriptions.id DESC
+LIMIT $3 OFFSET $2;
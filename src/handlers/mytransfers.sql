@@ -0,0 +1,27 @@
+SELECT
+	transfers.id AS id,
+	from_users.id AS from_user_id,
+	from_users.name AS from_user_name,
+	to_users.id AS to_user_id,
+	to_users.name AS to_user_name,
+	stocks.id AS stock_id,
+	stocks.name AS stock_name,
+	transfers.currency AS currency,
+	amount,
+	memo,
+	transfers.created_at AS created_at
+FROM
+	transfers
+		INNER JOIN
+	users AS from_users ON transfers.from_user_id = from_users.id
+		INNER JOIN
+	users AS to_users ON transfers.to_user_id = to_users.id
+		LEFT JOIN
+	stocks ON transfers.stock_id = stocks.id
+WHERE
+	from_users.id = $1
+		OR
+	to_users.id = $1
+ORDER BY
+	transfers.created_at DESC
+LIMIT $3 OFFSET $2;
@@ -0,0 +1,223 @@
+use actix_web::{
+    web, HttpResponse
+};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use crate::models::{AskOrder, BidOrder};
+use crate::money::Price;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use futures::Stream;
+use futures::sync::mpsc;
+use crate::errors::EngineError;
+
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::orders::Matched;
+use super::wallets::user_wallets;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/notify")
+        .service(
+            web::resource("/stream")
+                .route(web::get().to(notify_stream))      // 当前用户的委托和账户变化推送（Server-Sent Events）
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderStatus {
+    Accepted,           // 委托已进入委托簿
+    PartiallyFilled,
+    Filled,
+    Cancelled,          // 用户撤单
+    Expired             // 由撮合引擎撤销，例如合股后不足一股的部分
+}
+
+impl OrderStatus {
+    pub fn event(&self) -> &'static str {
+        match self {
+            OrderStatus::Accepted => "order_accepted",
+            OrderStatus::PartiallyFilled => "order_partially_filled",
+            OrderStatus::Filled => "order_filled",
+            OrderStatus::Cancelled => "order_cancelled",
+            OrderStatus::Expired => "order_expired",
+        }
+    }
+}
+
+// 推送给委托人的委托状态，side 为 ask（买入）或 bid（卖出）
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderUpdate {
+    pub id: i64,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub stock_id: i64,
+    pub side: &'static str,
+    pub price: Price,
+    pub volume: i64,
+    pub unfulfilled: i64,
+    pub updated_at: chrono::NaiveDateTime
+}
+
+impl OrderUpdate {
+    pub fn from_ask(ask: &AskOrder) -> OrderUpdate {
+        OrderUpdate {
+            id: ask.id,
+            user_id: ask.user_id,
+            stock_id: ask.stock_id,
+            side: "ask",
+            price: ask.price,
+            volume: ask.volume,
+            unfulfilled: ask.unfulfilled,
+            updated_at: ask.updated_at
+        }
+    }
+
+    pub fn from_bid(bid: &BidOrder) -> OrderUpdate {
+        OrderUpdate {
+            id: bid.id,
+            user_id: bid.user_id,
+            stock_id: bid.stock_id,
+            side: "bid",
+            price: bid.price,
+            volume: bid.volume,
+            unfulfilled: bid.unfulfilled,
+            updated_at: bid.updated_at
+        }
+    }
+
+    // 有成交之后的状态
+    pub fn fill_status(&self) -> OrderStatus {
+        if self.unfulfilled == 0 {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct HoldingModel {
+    pub stock_id: i64,
+    pub hold: i64
+}
+
+// 每个用户可以同时打开多个连接，连接断开后在下一次推送或有新连接时移除
+#[derive(Clone, Default)]
+pub struct UserFeed {
+    listeners: Arc<Mutex<HashMap<i64, Vec<mpsc::UnboundedSender<Bytes>>>>>
+}
+
+impl UserFeed {
+    fn listeners(&self) -> MutexGuard<'_, HashMap<i64, Vec<mpsc::UnboundedSender<Bytes>>>> {
+        // 持锁的线程崩溃不影响连接表的一致性，继续使用
+        self.listeners.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn register(&self, user_id: i64) -> mpsc::UnboundedReceiver<Bytes> {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut listeners = self.listeners();
+        for senders in listeners.values_mut() {
+            senders.retain(|sender| !sender.is_closed());
+        }
+        listeners.retain(|_, senders| !senders.is_empty());
+        listeners.entry(user_id).or_insert_with(Vec::new).push(sender);
+        receiver
+    }
+
+    fn is_listening(&self, user_id: i64) -> bool {
+        self.listeners().contains_key(&user_id)
+    }
+
+    fn send<T: serde::Serialize>(&self, user_id: i64, event: &str, data: &T) {
+        let mut listeners = self.listeners();
+        let closed = match listeners.get_mut(&user_id) {
+            Some(senders) => {
+                let message = Bytes::from(event_message(event, data));
+                senders.retain(|sender| sender.unbounded_send(message.clone()).is_ok());
+                senders.is_empty()
+            },
+            None => false
+        };
+        if closed {
+            listeners.remove(&user_id);
+        }
+    }
+
+    pub fn publish_order(&self, status: OrderStatus, order: &OrderUpdate) {
+        self.send(order.user_id, status.event(), order);
+    }
+
+    // 推送用户各币种的余额，stock_id 不为空时同时推送该股票的持有量
+    pub fn publish_account(&self, user_id: i64, stock_id: Option<i64>, conn: &PgConnection) -> Result<(), EngineError> {
+        use crate::schema::user_hold_stock::dsl as reldsl;
+
+        if !self.is_listening(user_id) {
+            return Ok(());
+        }
+
+        self.send(user_id, "balance", &user_wallets(user_id, conn)?);
+
+        if let Some(stock_id) = stock_id {
+            let query = reldsl::user_hold_stock
+                            .find((user_id, stock_id))
+                            .select(reldsl::hold);
+
+            debug!("Notify holding SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+            let hold = query.get_result::<i64>(conn)
+                .optional()
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+                })?
+                .unwrap_or(0);
+            self.send(user_id, "holding", &HoldingModel { stock_id, hold });
+        }
+
+        Ok(())
+    }
+
+    // 新委托提交后推送：委托已受理，有成交时推送双方委托的新状态，以及所有相关用户的余额和持有量
+    pub fn publish_matched(&self, accepted: &OrderUpdate, taker: &OrderUpdate, matched: &Matched, conn: &PgConnection) -> Result<(), EngineError> {
+        self.publish_order(OrderStatus::Accepted, accepted);
+
+        let mut users = HashSet::new();
+        users.insert(taker.user_id);
+        if !matched.deals.is_empty() {
+            self.publish_order(taker.fill_status(), taker);
+        }
+        for maker in &matched.makers {
+            self.publish_order(maker.fill_status(), maker);
+            users.insert(maker.user_id);
+        }
+
+        for user_id in users {
+            self.publish_account(user_id, Some(taker.stock_id), conn)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Server-Sent Events 的一条消息，data 为一行 JSON
+fn event_message<T: serde::Serialize>(event: &str, data: &T) -> String {
+    format!("event: {}\ndata: {}\n\n", event, serde_json::to_string(data).unwrap_or_default())
+}
+
+pub fn notify_stream(
+    user: RememberUserModel,
+    feed: web::Data<UserFeed>
+) -> HttpResponse {
+    let receiver = feed.register(user.id);
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .streaming(receiver.map_err(|_| EngineError::InternalError(format!("推送通道意外关闭。"))))
+}
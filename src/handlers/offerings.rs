@@ -0,0 +1,578 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+use crate::models::Offering;
+use crate::money::{Money, Price, price_tick};
+use crate::fees::{FeeSchedule, Liquidity};
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::wallets::add_balance;
+use super::orders::{OrderResult, NewDeal, UserStockRel, credit_house};
+use super::dividends::record_dividends;
+use super::notify::UserFeed;
+use super::PagingModel;
+
+use crate::schema::*;
+use diesel::sql_types;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/offerings")
+        .service(
+            web::resource("/")  // Scope 会自动加尾 /，所以 /offerings 无法匹配
+                .route(web::post().to_async(create_offering))       // 发行人发起增发
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/my/")
+                .route(web::get().to_async(get_my_subscriptions))     // 查询自己的增发认购记录
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/stock/{id}")
+                .route(web::get().to_async(get_stock_offerings))     // 查询某只股票的历次增发
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/{id}/subscribe")
+                .route(web::post().to_async(subscribe_offering))     // 认购增发的新股
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct CreateOfferingModel {
+    pub stock_id: u64,
+    pub price: Price,
+    pub size: u64,
+    pub starts_at: Option<chrono::NaiveDateTime>,   // 不填则立即开始认购
+    pub ends_at: chrono::NaiveDateTime,
+}
+
+pub fn create_offering(
+    create: web::Json<CreateOfferingModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            create_offering_query(create.into_inner(), curr_user, pool)
+        }
+    ).then(
+        move |res: Result<Offering, BlockingError<EngineError>>|
+            match res {
+                Ok(offering) => Ok(HttpResponse::Ok().json(offering)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn create_offering_query(create: CreateOfferingModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Offering, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+    use crate::schema::offerings::dsl as offdsl;
+
+    let stock_id = i64::try_from(create.stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+    let size = i64::try_from(create.size).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    if create.price <= Price(0) || size <= 0 {
+        return Err(EngineError::BadRequest(format!("增发价格和增发量必须为正数！")));
+    }
+    create.price.checked_value(size)
+        .ok_or_else(|| EngineError::BadRequest(format!("增发总金额过大！")))?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let starts_at = create.starts_at.unwrap_or(now);
+    if create.ends_at <= now || create.ends_at <= starts_at {
+        return Err(EngineError::BadRequest(format!("认购结束时间必须晚于当前时间和认购开始时间！")));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    conn.transaction(|| {
+        // 保证原子性
+        // 只有已上市股票的发行人可以增发
+        let query = stkdsl::stocks
+                        .inner_join(newdsl::new_stocks)
+                        .filter(
+                            stkdsl::id.eq(stock_id).and(
+                                stkdsl::into_market.eq(true)
+                            )
+                        )
+                        .select((newdsl::issuer_id, stkdsl::currency, stkdsl::price_decimals));
+
+        debug!("Create offering stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let (issuer_id, currency, price_decimals) = query.get_result::<(i64, String, i16)>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::BadRequest(format!("该股票还未上市，不能增发！")))?;
+
+        if issuer_id != user.id {
+            return Err(EngineError::Unauthorized(format!("只有该股票的发行人可以增发。")));
+        }
+
+        if !create.price.is_on_tick(price_decimals) {
+            return Err(EngineError::BadRequest(format!("增发价格 {} 元不符合该股票的最小变动单位 {} 元！", create.price, Price(price_tick(price_decimals)))));
+        }
+
+        let query = diesel::insert_into(offdsl::offerings)
+                        .values((
+                            offdsl::stock_id.eq(stock_id),
+                            offdsl::issuer_id.eq(issuer_id),
+                            offdsl::price.eq(create.price),
+                            offdsl::size.eq(size),
+                            offdsl::remaining.eq(size),
+                            offdsl::currency.eq(currency),
+                            offdsl::starts_at.eq(starts_at),
+                            offdsl::ends_at.eq(create.ends_at),
+                            offdsl::created_at.eq(now)
+                        ));
+
+        debug!("Create offering SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        query.get_result::<Offering>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库插入增发错误：{}", db_err))
+            })
+    })
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct SubscribeOfferingModel {
+    pub amount: u64
+}
+
+pub fn subscribe_offering(
+    offering_id: web::Path<u64>,
+    subscribe: web::Json<SubscribeOfferingModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    fees: web::Data<FeeSchedule>,
+    notify: web::Data<UserFeed>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            subscribe_offering_query(offering_id.into_inner(), subscribe.into_inner(), curr_user, pool, fees, notify)
+        }
+    ).then(
+        move |res: Result<i64, BlockingError<EngineError>>|
+            match res {
+                Ok(deal_num) => Ok(HttpResponse::Ok().json(
+                    OrderResult {
+                        succeed: true,
+                        message: None,
+                        error: None,
+                        deal_amount: Some(deal_num),
+                        lack: None
+                    }
+                )),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+// 认购期内先到先得，认购款直接记入发行人的钱包，新股直接记入认购人的持有量
+fn subscribe_offering_query(offering_id: u64, subscribe: SubscribeOfferingModel, user: RememberUserModel, pool: web::Data<Pool>, fees: web::Data<FeeSchedule>, notify: web::Data<UserFeed>) -> Result<i64, EngineError> {
+    use crate::schema::offerings::dsl as offdsl;
+
+    let offering_id = i64::try_from(offering_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+    let amount = i64::try_from(subscribe.amount).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    if amount <= 0 {
+        return Err(EngineError::BadRequest(format!("认购数量必须为正数！")));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    // 新股记入持有量前先做到期的股权登记
+    record_dividends(conn)?;
+
+    let (deal_num, stock_id, issuer_id) = conn.transaction::<_, EngineError, _>(|| {
+        // 保证原子性
+        let deal_num = subscribe_offering_in(offering_id, amount, user.id, &fees, conn)?;
+
+        let query = offdsl::offerings
+                        .find(offering_id)
+                        .select((offdsl::stock_id, offdsl::issuer_id));
+
+        debug!("Subscribed offering SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let (stock_id, issuer_id) = query.get_result::<(i64, i64)>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        Ok((deal_num, stock_id, issuer_id))
+    })?;
+
+    // 提交后推送认购人的余额和持有量，以及发行人的余额，推送失败不影响认购
+    if let Err(err) = notify.publish_account(user.id, Some(stock_id), conn) {
+        error!("Publishing user events failed: {}", err);
+    }
+    if let Err(err) = notify.publish_account(issuer_id, None, conn) {
+        error!("Publishing user events failed: {}", err);
+    }
+
+    Ok(deal_num)
+}
+
+// 认购增发，须在事务中调用
+fn subscribe_offering_in(offering_id: i64, amount: i64, user_id: i64, fees: &FeeSchedule, conn: &PgConnection) -> Result<i64, EngineError> {
+    use crate::schema::offerings::dsl as offdsl;
+    use crate::schema::offering_subscriptions::dsl as subdsl;
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::deals::dsl as dldsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+
+    let query = offdsl::offerings
+                    .find(offering_id)
+                    .for_update();
+
+    debug!("Subscribe offering SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let mut offering = query.get_result::<Offering>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?
+        .ok_or_else(|| EngineError::NotFound(format!("没有该增发！")))?;
+
+    let now = chrono::Utc::now().naive_utc();
+    if now < offering.starts_at {
+        return Err(EngineError::BadRequest(format!("该增发的认购将于 {} 开始！", offering.starts_at)));
+    }
+    if now >= offering.ends_at || offering.remaining <= 0 {
+        return Err(EngineError::BadRequest(format!("该增发的认购已经结束！")));
+    }
+    if offering.issuer_id == user_id {
+        return Err(EngineError::BadRequest(format!("发行人不能认购自己的增发！")));
+    }
+
+    let effective_amount = std::cmp::min(offering.remaining, amount);
+
+    offering.remaining -= effective_amount;
+    offering.save_changes::<Offering>(conn).map_err(|db_err| {
+        debug!("Database query error: {}", db_err);
+        EngineError::InternalError(format!("数据库重设增发余量错误：{}", db_err))
+    })?;
+
+    // 检查钱，扣钱（认购增发按吃单费率收取佣金）
+    let cost = offering.price.checked_value(effective_amount)
+        .ok_or_else(|| EngineError::BadRequest(format!("认购金额过大！")))?;
+    let commission = fees.commission(cost, Liquidity::Taker);
+
+    let balance_after = add_balance(user_id, &offering.currency, -(cost + commission), conn)?;
+
+    if balance_after < Money::zero() {
+        let err_msg = format!("{} 账户余额不足（含佣金 {} {}），你还需要 {} {}来认购剩余数量的增发股票。", offering.currency, commission, offering.currency, -balance_after, offering.currency);
+        return Err(EngineError::Insufficient(
+            OrderResult {
+                succeed: false,
+                message: Some(err_msg.clone()),
+                error: Some(err_msg),
+                deal_amount: None,
+                lack: Some((-balance_after).0)
+            }
+        ));
+    }
+
+    credit_house(commission, &offering.currency, &fees, conn)?;
+    add_balance(offering.issuer_id, &offering.currency, cost, conn)?;
+
+    // 加交易、加认购记录、加股票
+    let deal = NewDeal {
+        buy_user_id: user_id,
+        sell_user_id: None,
+        stock_id: offering.stock_id,
+        price: offering.price,
+        amount: effective_amount,
+        created_at: now,
+        buyer_commission: commission,
+        seller_commission: Money::zero(),
+        stamp_duty: Money::zero()
+    };
+
+    diesel::insert_into(dldsl::deals).values(&deal)
+        .execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库插入交易错误：{}", db_err))
+        })?;
+
+    let query = diesel::insert_into(subdsl::offering_subscriptions)
+                    .values((
+                        subdsl::offering_id.eq(offering.id),
+                        subdsl::user_id.eq(user_id),
+                        subdsl::amount.eq(effective_amount),
+                        subdsl::cost.eq(cost),
+                        subdsl::commission.eq(commission),
+                        subdsl::created_at.eq(now)
+                    ));
+
+    debug!("Insert offering subscription SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库插入认购记录错误：{}", db_err))
+        })?;
+
+    diesel::insert_into(reldsl::user_hold_stock)
+        .values(
+            UserStockRel {
+                user_id: deal.buy_user_id,
+                stock_id: deal.stock_id,
+                hold: deal.amount,
+                updated_at: now
+            }
+        )
+        .on_conflict((reldsl::user_id, reldsl::stock_id))
+        .do_update()
+        .set((
+            reldsl::hold.eq(reldsl::hold + deal.amount),
+            reldsl::updated_at.eq(now)
+        ))
+        .execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库重设买家股票数量错误：{}", db_err))
+        })?;
+
+    // 增发的股票计入流通股数
+    let query = diesel::update(newdsl::new_stocks.find(offering.stock_id))
+                    .set(newdsl::offer_circ.eq(newdsl::offer_circ + effective_amount));
+
+    debug!("Subscribe offering circulation SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库重设流通股数错误：{}", db_err))
+        })?;
+
+    Ok(effective_amount)
+}
+
+//////////////////
+pub fn get_stock_offerings(
+    stock_id: web::Path<u64>,
+    paging: web::Query<PagingModel>,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_stock_offerings_query(stock_id.into_inner(), paging, pool)
+        }
+    ).then(
+        move |res: Result<Vec<Offering>, BlockingError<EngineError>>|
+            match res {
+                Ok(offerings) => Ok(HttpResponse::Ok().json(offerings)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_stock_offerings_query(stock_id: u64, paging: PagingModel, pool: web::Data<Pool>) -> Result<Vec<Offering>, EngineError> {
+    use crate::schema::offerings::dsl as offdsl;
+
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = offdsl::offerings
+                    .filter(offdsl::stock_id.eq(stock_id))
+                    .order((offdsl::created_at.desc(), offdsl::id.desc()))
+                    .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get stock offerings SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<Offering>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+//////////////////
+#[derive(QueryableByName, Serialize)]
+pub struct SubscriptionModel {
+    #[sql_type = "sql_types::BigInt"]
+    pub id: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub offering_id: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub stock_id: i64,
+    #[sql_type = "sql_types::Varchar"]
+    pub stock_name: String,
+    #[sql_type = "sql_types::Varchar"]
+    pub currency: String,
+    #[sql_type = "sql_types::Int4"]
+    pub price: Price,
+    #[sql_type = "sql_types::BigInt"]
+    pub amount: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub cost: Money,
+    #[sql_type = "sql_types::BigInt"]
+    pub commission: Money,
+    #[sql_type = "sql_types::Timestamp"]
+    pub created_at: chrono::NaiveDateTime
+}
+
+pub fn get_my_subscriptions(
+    paging: web::Query<PagingModel>,
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_my_subscriptions_query(paging, user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<SubscriptionModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(subscriptions) => Ok(HttpResponse::Ok().json(subscriptions)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_subscriptions_query(paging: PagingModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<SubscriptionModel>, EngineError> {
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = diesel::sql_query(include_str!("mysubscriptions.sql"))
+                    .bind::<sql_types::BigInt, _>(user.id)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.offset.unwrap_or(0)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.limit.unwrap_or(10)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get my subscriptions SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.load::<SubscriptionModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+#[test]
+fn test_subscribe_offering_circulation() {
+    use crate::schema::users::dsl as usrdsl;
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+    use crate::schema::offerings::dsl as offdsl;
+    let conn = crate::test_get_data_connection();
+
+    // 在测试事务中进行，结束后回滚
+    conn.test_transaction::<_, EngineError, _>(|| {
+        let now = chrono::Utc::now().naive_utc();
+        let user_ids = diesel::insert_into(usrdsl::users)
+            .values(&vec![
+                (usrdsl::name.eq("增发测试发行人"), usrdsl::password_hashed.eq(""), usrdsl::created_at.eq(now)),
+                (usrdsl::name.eq("增发测试认购人"), usrdsl::password_hashed.eq(""), usrdsl::created_at.eq(now))
+            ])
+            .returning(usrdsl::id)
+            .get_results::<i64>(&conn)?;
+        let (issuer_id, user_id) = (user_ids[0], user_ids[1]);
+
+        let stock_id = diesel::insert_into(stkdsl::stocks)
+            .values((
+                stkdsl::name.eq("增发测试股票"),
+                stkdsl::into_market.eq(true),
+                stkdsl::into_market_at.eq(now),
+                stkdsl::price_decimals.eq(2i16),
+                stkdsl::currency.eq("CNY")
+            ))
+            .returning(stkdsl::id)
+            .get_result::<i64>(&conn)?;
+
+        diesel::insert_into(newdsl::new_stocks)
+            .values((
+                newdsl::id.eq(stock_id),
+                newdsl::issuer_id.eq(issuer_id),
+                newdsl::offer_circ.eq(1000i64),
+                newdsl::offer_price.eq(Price(100)),
+                newdsl::offer_unfulfilled.eq(0i64),
+                newdsl::created_at.eq(now)
+            ))
+            .execute(&conn)?;
+
+        let offering_id = diesel::insert_into(offdsl::offerings)
+            .values((
+                offdsl::stock_id.eq(stock_id),
+                offdsl::issuer_id.eq(issuer_id),
+                offdsl::price.eq(Price(100)),
+                offdsl::size.eq(300i64),
+                offdsl::remaining.eq(300i64),
+                offdsl::currency.eq("CNY"),
+                offdsl::starts_at.eq(now - chrono::Duration::hours(1)),
+                offdsl::ends_at.eq(now + chrono::Duration::hours(1)),
+                offdsl::created_at.eq(now)
+            ))
+            .returning(offdsl::id)
+            .get_result::<i64>(&conn)?;
+
+        add_balance(user_id, "CNY", Money(100_000), &conn)?;
+
+        let fees = FeeSchedule {
+            maker_rate: 0,
+            taker_rate: 0,
+            min_commission: Money::zero(),
+            stamp_duty_rate: 0,
+            house_user_id: issuer_id
+        };
+
+        // 认购超过余量时只认购余量，流通股数按实际认购数量增加
+        assert_eq!(subscribe_offering_in(offering_id, 200, user_id, &fees, &conn)?, 200);
+        assert_eq!(subscribe_offering_in(offering_id, 200, user_id, &fees, &conn)?, 100);
+
+        let offer_circ = newdsl::new_stocks
+            .find(stock_id)
+            .select(newdsl::offer_circ)
+            .get_result::<i64>(&conn)?;
+        assert_eq!(offer_circ, 1300);
+
+        Ok(())
+    });
+}
@@ -15,6 +15,11 @@ use std::convert::TryInto;
 
 use futures::Future;
 use crate::errors::EngineError;
+use crate::fees::{FeeSchedule, Liquidity};
+use crate::money::{Money, Price, price_tick};
+use crate::settlement::SettlementSchedule;
+use crate::shorting::ShortSelling;
+use crate::margin::MarginSettings;
 
 use crate::common::Pool;
 use diesel::PgConnection;
@@ -23,6 +28,17 @@ use diesel::prelude::*;
 use std::str::FromStr;
 
 use super::users::{RememberUserModel};
+use super::settlement::{settle_due, add_pending, pending_stock, NewPendingSettlement};
+use super::wallets::{add_balance, stock_currency};
+use super::shorts::{borrow_shares, accrue_borrow_fees, cover_loans};
+use super::margin::{borrow_cash, repay_margin, enforce_maintenance};
+use super::dividends::record_dividends;
+use super::ipo::check_lockup;
+use super::buybacks::retire_shares;
+use super::quotation::update_candles;
+use super::market::MarketFeed;
+use super::notify::{UserFeed, OrderUpdate, OrderStatus};
+use super::history::{OrderEventKind, record_ask, record_bid, save_ask, save_bid};
 use super::PagingModel;
 
 use crate::schema::*;
@@ -35,6 +51,11 @@ pub fn make_scope() -> actix_web::Scope {
                 .route(web::post().to_async(new_order))   // 创建委托
                 .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
         )
+        .service(
+            web::resource("/preview")
+                .route(web::post().to_async(preview_order))   // 预览委托的成交、费用和冻结资金，不实际提交
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
         .service(
             web::resource("/my/asks/")
                 .route(web::get().to_async(get_my_asks))     // 查询自己的买委托
@@ -57,6 +78,16 @@ pub fn make_scope() -> actix_web::Scope {
                 .route(web::delete().to_async(revoke_bid))      // 撤销委托
                 .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
         )
+        .service(
+            web::resource("/asks/{id}/events")
+                .route(web::get().to_async(super::history::get_ask_events))      // 查询自己委托的变化记录
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/bids/{id}/events")
+                .route(web::get().to_async(super::history::get_bid_events))      // 查询自己委托的变化记录
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -69,8 +100,14 @@ pub enum AskOrBid {
 pub struct OrderModel {
     pub entype: AskOrBid,
     pub stock_id: i64,
-    pub price: i32,
+    pub price: Price,
     pub volume: i64,
+    #[serde(default)]
+    pub short: bool,        // 卖空：从借券池借入股票卖出
+    #[serde(default)]
+    pub cover: bool,        // 买入平仓：成交的股票用于归还借券
+    #[serde(default)]
+    pub margin: bool,       // 融资买入：现金不足的部分以持仓为担保借入
 }
 
 #[derive(Queryable, Insertable)]
@@ -78,11 +115,14 @@ pub struct OrderModel {
 pub struct AskOrderModel {
     pub user_id: i64,
     pub stock_id: i64,
-    pub price: i32,
+    pub price: Price,
     pub volume: i64,
     pub unfulfilled: i64,
     pub created_at: chrono::NaiveDateTime,
-    pub updated_at: chrono::NaiveDateTime
+    pub updated_at: chrono::NaiveDateTime,
+    pub fee_frozen: Money,
+    pub cover: bool,
+    pub buyback_id: Option<i64>
 }
 
 trait AskOrBidOrderModel {
@@ -99,7 +139,10 @@ impl AskOrBidOrderModel for AskOrderModel {
             volume: model.volume,
             unfulfilled: model.volume,
             created_at: chrono::Utc::now().naive_utc(),
-            updated_at: chrono::Utc::now().naive_utc()
+            updated_at: chrono::Utc::now().naive_utc(),
+            fee_frozen: Money::zero(),
+            cover: model.cover,
+            buyback_id: None
         }
     }
 }
@@ -109,11 +152,12 @@ impl AskOrBidOrderModel for AskOrderModel {
 pub struct BidOrderModel {
     pub user_id: i64,
     pub stock_id: i64,
-    pub price: i32,
+    pub price: Price,
     pub volume: i64,
     pub unfulfilled: i64,
     pub created_at: chrono::NaiveDateTime,
-    pub updated_at: chrono::NaiveDateTime
+    pub updated_at: chrono::NaiveDateTime,
+    pub liquidation: bool
 }
 
 impl AskOrBidOrderModel for BidOrderModel {
@@ -125,7 +169,8 @@ impl AskOrBidOrderModel for BidOrderModel {
             volume: model.volume,
             unfulfilled: model.volume,
             created_at: chrono::Utc::now().naive_utc(),
-            updated_at: chrono::Utc::now().naive_utc()
+            updated_at: chrono::Utc::now().naive_utc(),
+            liquidation: false
         }
     }
 }
@@ -137,9 +182,38 @@ pub struct NewDeal {
     pub buy_user_id: i64,
     pub sell_user_id: Option<i64>,
     pub stock_id: i64,
-    pub price: i32,
+    pub price: Price,
     pub amount: i64,
-    pub created_at: chrono::NaiveDateTime
+    pub created_at: chrono::NaiveDateTime,
+    pub buyer_commission: Money,
+    pub seller_commission: Money,
+    pub stamp_duty: Money
+}
+
+// 撮合的结果：成交记录，以及有成交的挂单方委托成交后的状态
+pub struct Matched {
+    pub deals: Vec<NewDeal>,
+    pub makers: Vec<OrderUpdate>
+}
+
+// 撮合引擎自动提交的委托（强制平仓、回购）的撮合结果，事务提交后推送
+pub struct EngineOrder {
+    pub stock_id: i64,
+    pub accepted: OrderUpdate,
+    pub taker: OrderUpdate,
+    pub matched: Matched
+}
+
+// 提交后推送撮合引擎自动提交的委托带来的成交和盘口变化，以及相关用户的委托和账户变化，推送失败不影响委托
+pub fn publish_engine_orders(orders: &[EngineOrder], feed: &MarketFeed, notify: &UserFeed, conn: &PgConnection) {
+    for order in orders {
+        if let Err(err) = feed.publish_deals(order.stock_id, &order.matched.deals, conn) {
+            error!("Publishing market data failed: {}", err);
+        }
+        if let Err(err) = notify.publish_matched(&order.accepted, &order.taker, &order.matched, conn) {
+            error!("Publishing user events failed: {}", err);
+        }
+    }
 }
 
 #[derive(Queryable, Insertable, AsChangeset, Identifiable)]
@@ -170,12 +244,18 @@ impl std::fmt::Display for OrderResult {
 pub fn new_order(
     order: web::Json<OrderModel>,
     curr_user: RememberUserModel,
-    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    fees: web::Data<FeeSchedule>,
+    settlement: web::Data<SettlementSchedule>,
+    shorting: web::Data<ShortSelling>,
+    margin: web::Data<MarginSettings>,
+    feed: web::Data<MarketFeed>,
+    notify: web::Data<UserFeed>
 ) -> impl Future<Item = HttpResponse, Error = EngineError> {
    
     web::block(
         move || {
-            new_order_query(order.into_inner(), curr_user, pool)
+            new_order_query(order.into_inner(), curr_user, pool, fees, settlement, shorting, margin, feed, notify)
         }
     ).then(
         move |res: Result<i64, BlockingError<EngineError>>|
@@ -197,7 +277,48 @@ pub fn new_order(
     )
 }
 
-fn new_order_query(order: OrderModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<i64, EngineError> {
+fn new_order_query(order: OrderModel, user: RememberUserModel, pool: web::Data<Pool>, fees: web::Data<FeeSchedule>, settlement: web::Data<SettlementSchedule>, shorting: web::Data<ShortSelling>, margin: web::Data<MarginSettings>, feed: web::Data<MarketFeed>, notify: web::Data<UserFeed>) -> Result<i64, EngineError> {
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let (placed, liquidations) = conn.transaction::<_, EngineError, _>(|| {
+        // 保证原子性
+        let placed = place_order(&order, &user, &fees, &settlement, &shorting, &margin, conn)?;
+
+        // 成交价变化后，检查持有该股票的融资用户的维持担保比例；预览委托不检查
+        let liquidations = if placed.matched.deals.is_empty() {
+            Vec::new()
+        } else {
+            enforce_maintenance(&placed.currency, order.stock_id, &margin, &fees, &settlement, conn)?
+        };
+        Ok((placed, liquidations))
+    })?;
+
+    // 提交后推送成交和盘口的变化，以及相关用户的委托和账户变化，推送失败不影响委托
+    if let Err(err) = feed.publish_deals(order.stock_id, &placed.matched.deals, conn) {
+        error!("Publishing market data failed: {}", err);
+    }
+    if let Err(err) = notify.publish_matched(&placed.accepted, &placed.taker, &placed.matched, conn) {
+        error!("Publishing user events failed: {}", err);
+    }
+    publish_engine_orders(&liquidations, &feed, &notify, conn);
+
+    Ok(placed.matched.deals.iter().map(|deal| deal.amount).sum())
+}
+
+// 委托进入委托簿并撮合之后的结果
+struct Placed {
+    currency: String,
+    accepted: OrderUpdate,
+    taker: OrderUpdate,
+    matched: Matched,
+    frozen_cash: Money,         // 委托时冻结的现金，含预估佣金
+    frozen_shares: i64,         // 委托时冻结的持股，卖空时为 0
+    resting_frozen_cash: Money  // 撮合后仍冻结在剩余委托中的现金
+}
+
+// 检查并冻结资金或股票，插入委托单并撮合，须在事务中调用
+fn place_order(order: &OrderModel, user: &RememberUserModel, fees: &FeeSchedule, settlement: &SettlementSchedule, shorting: &ShortSelling, margin: &MarginSettings, conn: &PgConnection) -> Result<Placed, EngineError> {
     use crate::schema::stocks::dsl as stkdsl;
     use crate::schema::users::dsl as usrdsl;
     use crate::schema::user_hold_stock::dsl as reldsl;
@@ -205,338 +326,555 @@ fn new_order_query(order: OrderModel, user: RememberUserModel, pool: web::Data<P
     use crate::schema::user_ask_orders::dsl as askdsl;
     use crate::schema::user_bid_orders::dsl as biddsl;
 
-    // 取出数据库连接
-    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+    // 检查股票是否上市
+    let query_stock = stkdsl::stocks
+                        .find(order.stock_id)
+                        .filter(
+                            stkdsl::into_market.eq(true)
+                        )
+                        .select((stkdsl::price_decimals, stkdsl::currency));
 
-    conn.transaction(|| {
-        // 保证原子性
-        // 检查股票是否上市
-        let query_stock = stkdsl::stocks
-                            .find(order.stock_id)
-                            .filter(
-                                stkdsl::into_market.eq(true)
-                            )
-                            .select(stkdsl::id);
+    debug!("New order query_stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query_stock));
 
-        debug!("New order query_stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query_stock));
+    let (price_decimals, currency) = query_stock.get_result::<(i16, String)>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?
+        .ok_or_else(|| EngineError::BadRequest(format!("该股票还未上市！")))?;
 
-        query_stock.get_result::<i64>(conn)
-            .optional()
-            .map_err(|db_err| {
-                debug!("Database query error: {}", db_err);
-                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
-            })?
-            .ok_or_else(|| EngineError::BadRequest(format!("该股票还未上市！")))?;
+    // 检查价格和数量
+    if order.price <= Price(0) || order.volume <= 0 {
+        return Err(EngineError::BadRequest(format!("委托价格和数量必须为正数！")));
+    }
+    if !order.price.is_on_tick(price_decimals) {
+        return Err(EngineError::BadRequest(format!("委托价格 {} 元不符合该股票的最小变动单位 {} 元！", order.price, Price(price_tick(price_decimals)))));
+    }
+    let order_value = order.price.checked_value(order.volume)
+        .ok_or_else(|| EngineError::BadRequest(format!("委托金额过大！")))?;
+    match order.entype {
+        AskOrBid::Ask if order.short => return Err(EngineError::BadRequest(format!("买入委托不能卖空！"))),
+        AskOrBid::Bid if order.cover => return Err(EngineError::BadRequest(format!("卖出委托不能平仓！"))),
+        AskOrBid::Bid if order.margin => return Err(EngineError::BadRequest(format!("卖出委托不能融资！"))),
+        _ => ()
+    }
+    if let AskOrBid::Bid = order.entype {
+        check_lockup(order.stock_id, user.id, conn)?;
+    }
 
-        // 如果是买单，扣钱；如果是卖单，扣股票
+    // 已到股权登记时间的分红先登记，已到交收时间的股票和现金先转为可用，并计提借券费用
+    record_dividends(conn)?;
+    settle_due(user.id, conn)?;
+    accrue_borrow_fees(user.id, conn)?;
+
+    // 如果是买单，从该股票报价币种的钱包中扣钱（包括预估的佣金）；如果是卖单，扣股票
+    let fee_reserve = fees.buy_fee_reserve(order_value);
+    let (frozen_cash, frozen_shares) = match order.entype {
+        AskOrBid::Ask => (order_value + fee_reserve, 0),
+        AskOrBid::Bid if order.short => (Money::zero(), 0),
+        AskOrBid::Bid => (Money::zero(), order.volume)
+    };
+    let mut resting_frozen_cash = Money::zero();
+
+    match order.entype {
+        AskOrBid::Ask => {
+            let mut balance_after = add_balance(user.id, &currency, -(order_value + fee_reserve), conn)?;
+
+            // 融资买入：不足的部分借入，这笔委托买入的股票也计入担保
+            if order.margin && balance_after < Money::zero() {
+                borrow_cash(user.id, &currency, -balance_after, order_value, &margin, conn)?;
+                balance_after = Money::zero();
+            }
 
-        match order.entype {
-            AskOrBid::Ask => {
-                let query = diesel::update(usrdsl::users.find(user.id))
-                                .set(usrdsl::balance.eq(usrdsl::balance - order.price as i64 * order.volume));
+            if balance_after < Money::zero() {
+                let err_msg = format!("{} 账户余额不足（含预估佣金 {} {}），你还需要 {} {}来申请这笔委托。", currency, fee_reserve, currency, -balance_after, currency);
+                return Err(EngineError::Insufficient(
+                    OrderResult {
+                        succeed: false,
+                        message: Some(err_msg.clone()),
+                        error: Some(err_msg),
+                        deal_amount: None,
+                        lack: Some((-balance_after).0)
+                    }
+                ));
+            }
+        },
+        AskOrBid::Bid if order.short => {
+            // 卖空的股票从借券池借入，不扣持有的股票
+            borrow_shares(user.id, order.stock_id, order.volume, order.price, &currency, &shorting, conn)?;
+        },
+        AskOrBid::Bid => {
+            let query = diesel::update(reldsl::user_hold_stock.find(
+                            (user.id, order.stock_id)
+                        ))
+                        .set((
+                            reldsl::hold.eq(reldsl::hold - order.volume),
+                            reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
+                        ));
+
+            debug!("New freeze stock query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
 
-                debug!("New freeze balance query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+            let rel_after = query.get_result::<UserStockRel>(conn)
+                .optional()
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库更新股票持有量错误：{}", db_err))
+                })?;
 
-                let user_after = query.get_result::<User>(conn)
-                    .map_err(|db_err| {
-                        debug!("Database query error: {}", db_err);
-                        EngineError::InternalError(format!("数据库更新余额错误：{}", db_err))
-                    })?;
+            // 尚未交收的股票不能卖出，在提示中说明
+            let unsettled_hint = match pending_stock(user.id, order.stock_id, conn)? {
+                (0, _) | (_, None) => format!(""),
+                (pending, Some(settle_at)) => format!("另有 {} 股尚未交收，最早于 {} 起可卖出。", pending, settle_at)
+            };
 
-                if user_after.balance < 0 {
-                    let err_msg = format!("账户余额不足，你还需要 {} 元来申请这笔委托。", (-user_after.balance) as f32 / 100.);
-                    return Err(EngineError::Insufficient(
+            let rel_after = rel_after.ok_or_else(|| {
+                    let err_msg = format!("股票持有量不足，你当前并未持有该股票。{}", unsettled_hint);
+                    EngineError::Insufficient(
                         OrderResult {
                             succeed: false,
                             message: Some(err_msg.clone()),
                             error: Some(err_msg),
                             deal_amount: None,
-                            lack: Some(-user_after.balance)
+                            lack: Some(-order.volume)
                         }
-                    ));
-                }
-            },
-            AskOrBid::Bid => {
-                let query = diesel::update(reldsl::user_hold_stock.find(
-                                (user.id, order.stock_id)
-                            ))
-                            .set((
-                                reldsl::hold.eq(reldsl::hold - order.volume),
-                                reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
-                            ));
-
-                debug!("New freeze stock query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
-
-                let rel_after = query.get_result::<UserStockRel>(conn)
-                    .optional()
-                    .map_err(|db_err| {
-                        debug!("Database query error: {}", db_err);
-                        EngineError::InternalError(format!("数据库更新股票持有量错误：{}", db_err))
-                    })?
-                    .ok_or_else(|| {
-                        let err_msg = format!("股票持有量不足，你当前并未持有该股票。");
-                        EngineError::Insufficient(
-                            OrderResult {
-                                succeed: false,
-                                message: Some(err_msg.clone()),
-                                error: Some(err_msg),
-                                deal_amount: None,
-                                lack: Some(-order.volume)
-                            }
-                        )
-                    })?;
+                    )
+                })?;
 
-                if rel_after.hold < 0 {
-                    let err_msg = format!("股票持有量不足，你还需要 {} 股来申请这笔委托。", -rel_after.hold);
-                    return Err(EngineError::Insufficient(
-                        OrderResult {
-                            succeed: false,
-                            message: Some(err_msg.clone()),
-                            error: Some(err_msg),
-                            deal_amount: None,
-                            lack: Some(-rel_after.hold)
-                        }
-                    ));
-                }
+            if rel_after.hold < 0 {
+                let err_msg = format!("股票持有量不足，你还需要 {} 股来申请这笔委托。{}", -rel_after.hold, unsettled_hint);
+                return Err(EngineError::Insufficient(
+                    OrderResult {
+                        succeed: false,
+                        message: Some(err_msg.clone()),
+                        error: Some(err_msg),
+                        deal_amount: None,
+                        lack: Some(-rel_after.hold)
+                    }
+                ));
             }
         }
+    }
 
-        // 创建委托单
-        let mut new_ask: Option<AskOrder> = None;
-        let mut new_bid: Option<BidOrder> = None;
-        match order.entype {
-            AskOrBid::Ask => {
-                let query = diesel::insert_into(askdsl::user_ask_orders)
-                    .values(AskOrderModel::from_order_model_and_user(&order, &user));
+    // 创建委托单
+    let mut new_ask: Option<AskOrder> = None;
+    let mut new_bid: Option<BidOrder> = None;
+    match order.entype {
+        AskOrBid::Ask => {
+            let mut ask_model = AskOrderModel::from_order_model_and_user(&order, &user);
+            ask_model.fee_frozen = fee_reserve;
+            let query = diesel::insert_into(askdsl::user_ask_orders)
+                .values(ask_model);
 
-                debug!("New order query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+            debug!("New order query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
 
-                new_ask = Some(query.get_result::<AskOrder>(conn)
-                    .map_err(|db_err| {
-                        debug!("Database query error: {}", db_err);
-                        EngineError::InternalError(format!("数据库插入委托错误：{}", db_err))
-                    })?);
+            let ask = query.get_result::<AskOrder>(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库插入委托错误：{}", db_err))
+                })?;
+            record_ask(OrderEventKind::Inserted, &ask, conn)?;
+            new_ask = Some(ask);
+        },
+        AskOrBid::Bid => {
+            let query = diesel::insert_into(biddsl::user_bid_orders)
+                .values(BidOrderModel::from_order_model_and_user(&order, &user));
 
-                
+            debug!("New order query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
 
-            },
-            AskOrBid::Bid => {
-                let query = diesel::insert_into(biddsl::user_bid_orders)
-                    .values(BidOrderModel::from_order_model_and_user(&order, &user));
+            let bid = query.get_result::<BidOrder>(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库插入委托错误：{}", db_err))
+                })?;
+            record_bid(OrderEventKind::Inserted, &bid, conn)?;
+            new_bid = Some(bid);
+        }
+    }
 
-                debug!("New order query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+    // 第三步：撮合
+    let (accepted, taker, matched) = match order.entype {
+        AskOrBid::Ask => {
+            let mut new_ask = new_ask.ok_or_else(|| EngineError::InternalError(format!("服务端逻辑错误。")))?;
+            let accepted = OrderUpdate::from_ask(&new_ask);
+            let matched = match_ask(&mut new_ask, &currency, &fees, &settlement, conn)?;
+            resting_frozen_cash = new_ask.price.value(new_ask.unfulfilled) + new_ask.fee_frozen;
+            (accepted, OrderUpdate::from_ask(&new_ask), matched)
+        },
+        AskOrBid::Bid => {
+            let mut new_bid = new_bid.ok_or_else(|| EngineError::InternalError(format!("服务端逻辑错误。")))?;
+            let accepted = OrderUpdate::from_bid(&new_bid);
+            let matched = match_bid(&mut new_bid, &currency, &fees, &settlement, conn)?;
+            (accepted, OrderUpdate::from_bid(&new_bid), matched)
+        }
+    };
+
+    Ok(Placed {
+        currency,
+        accepted,
+        taker,
+        matched,
+        frozen_cash,
+        frozen_shares,
+        resting_frozen_cash
+    })
+}
 
-                new_bid = Some(query.get_result::<BidOrder>(conn)
-                    .map_err(|db_err| {
-                        debug!("Database query error: {}", db_err);
-                        EngineError::InternalError(format!("数据库插入委托错误：{}", db_err))
-                    })?);
+//////////////////
+// 预计成交的一个价位，佣金和印花税为委托方一侧
+#[derive(Serialize)]
+pub struct PreviewLevelModel {
+    pub price: Price,
+    pub amount: i64,
+    pub value: Money,
+    pub commission: Money,
+    pub stamp_duty: Money       // 只有卖出收取
+}
+
+#[derive(Serialize)]
+pub struct OrderPreviewModel {
+    pub currency: String,
+    pub fills: Vec<PreviewLevelModel>,  // 按撮合顺序排列
+    pub deal_amount: i64,
+    pub deal_value: Money,
+    pub average_price: Option<f64>,     // 成交均价，单位同委托价
+    pub commission: Money,
+    pub stamp_duty: Money,
+    pub frozen_cash: Money,             // 委托时冻结的现金，含预估佣金
+    pub frozen_shares: i64,             // 委托时冻结的持股
+    pub unfulfilled: i64,
+    pub rests: bool,                    // 未成交的部分是否留在委托簿中
+    pub resting_frozen_cash: Money      // 留在委托簿中的部分仍冻结的现金
+}
+
+impl OrderPreviewModel {
+    fn from_placed(placed: Placed, entype: &AskOrBid) -> OrderPreviewModel {
+        let mut fills: Vec<PreviewLevelModel> = Vec::new();
+        for deal in &placed.matched.deals {
+            let (commission, stamp_duty) = match entype {
+                AskOrBid::Ask => (deal.buyer_commission, Money::zero()),
+                AskOrBid::Bid => (deal.seller_commission, deal.stamp_duty)
+            };
+            match fills.last_mut() {
+                Some(level) if level.price == deal.price => {
+                    level.amount += deal.amount;
+                    level.value += deal.price.value(deal.amount);
+                    level.commission += commission;
+                    level.stamp_duty += stamp_duty;
+                },
+                _ => fills.push(PreviewLevelModel {
+                    price: deal.price,
+                    amount: deal.amount,
+                    value: deal.price.value(deal.amount),
+                    commission,
+                    stamp_duty
+                })
             }
         }
 
-        // 第三步：撮合
-        let deal_num = match order.entype {
-            AskOrBid::Ask => {
-                let mut new_ask = new_ask.ok_or_else(|| EngineError::InternalError(format!("服务端逻辑错误。")))?;
-                let query = biddsl::user_bid_orders.filter(
-                                biddsl::stock_id.eq(order.stock_id).and(
-                                    biddsl::unfulfilled.ne(0)
-                                ).and(
-                                    biddsl::price.le(order.price)
-                                )
-                            )
-                                .order_by(
-                                    biddsl::price.asc()
-                                )
-                                .then_order_by(
-                                    biddsl::created_at.asc()   
-                                );
-                            
-
-                debug!("Ask matching query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
-
-                let mut bids = query.get_results::<BidOrder>(conn)
-                    .map_err(|db_err| {
-                        debug!("Database query error: {}", db_err);
-                        EngineError::InternalError(format!("数据库查询卖出委托错误：{}", db_err))
-                    })?;
-
-                let mut deal_num = 0;
-
-                for bid in &mut bids {
-                    let deal_amount = std::cmp::min(bid.unfulfilled, new_ask.unfulfilled);
-                    new_ask.unfulfilled -= deal_amount;
-                    new_ask.updated_at = chrono::Utc::now().naive_utc();
-                    bid.unfulfilled -= deal_amount;
-                    bid.updated_at = chrono::Utc::now().naive_utc();
-                    let deal = NewDeal {
-                        buy_user_id: new_ask.user_id,
-                        sell_user_id: Some(bid.user_id),
-                        stock_id: new_ask.stock_id,
-                        price: bid.price,
-                        amount: deal_amount,
-                        created_at: chrono::Utc::now().naive_utc()
-                    };
-                    debug!("Deal: {:?}", deal);
-                    deal_num += deal_amount;
-                    let give_seller_cash = deal_amount * (deal.price as i64);
-                    let giveback_buyer_cash = deal_amount * ((new_ask.price - deal.price) as i64);
-                    
-                    new_ask.save_changes::<AskOrder>(conn).map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库重设新买委托错误：{}", db_err))
-                        })?;
-                    bid.save_changes::<BidOrder>(conn).map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库重设旧卖委托错误：{}", db_err))
-                        })?;
-                    diesel::update(usrdsl::users.find(deal.buy_user_id))
-                        .set(usrdsl::balance.eq(usrdsl::balance + giveback_buyer_cash))
-                        .execute(conn)
-                        .map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库重设买家余额错误：{}", db_err))
-                        })?;
-                    diesel::update(usrdsl::users.find(bid.user_id))
-                        .set(usrdsl::balance.eq(usrdsl::balance + give_seller_cash))
-                        .execute(conn)
-                        .map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库重设卖家余额错误：{}", db_err))
-                        })?;
-                    diesel::insert_into(dldsl::deals).values(&deal)
-                        .execute(conn)
-                        .map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库插入交易错误：{}", db_err))
-                        })?;
-                    diesel::insert_into(reldsl::user_hold_stock)
-                        .values(
-                            UserStockRel {
-                                user_id: deal.buy_user_id,
-                                stock_id: deal.stock_id,
-                                hold: deal.amount,
-                                updated_at: chrono::Utc::now().naive_utc()
-                            }
-                        )
-                        .on_conflict((reldsl::user_id, reldsl::stock_id))
-                        .do_update()
-                        .set((
-                            reldsl::hold.eq(reldsl::hold + deal.amount),
-                            reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
-                        ))
-                        .execute(conn)
-                        .map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库重设买家股票数量错误：{}", db_err))
-                        })?;
-
-                    if new_ask.unfulfilled == 0 {
-                        break;
-                    }
+        let deal_amount: i64 = fills.iter().map(|level| level.amount).sum();
+        let deal_value: Money = fills.iter().map(|level| level.value).sum();
+        OrderPreviewModel {
+            currency: placed.currency,
+            deal_amount,
+            deal_value,
+            average_price: if deal_amount > 0 { Some(deal_value.0 as f64 / deal_amount as f64) } else { None },
+            commission: fills.iter().map(|level| level.commission).sum(),
+            stamp_duty: fills.iter().map(|level| level.stamp_duty).sum(),
+            fills,
+            frozen_cash: placed.frozen_cash,
+            frozen_shares: placed.frozen_shares,
+            unfulfilled: placed.taker.unfulfilled,
+            rests: placed.taker.unfulfilled > 0,
+            resting_frozen_cash: placed.resting_frozen_cash
+        }
+    }
+}
+
+pub fn preview_order(
+    order: web::Json<OrderModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    fees: web::Data<FeeSchedule>,
+    settlement: web::Data<SettlementSchedule>,
+    shorting: web::Data<ShortSelling>,
+    margin: web::Data<MarginSettings>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            preview_order_query(order.into_inner(), curr_user, pool, fees, settlement, shorting, margin)
+        }
+    ).then(
+        move |res: Result<OrderPreviewModel, BlockingError<EngineError>>|
+            match res {
+                Ok(m) => Ok(HttpResponse::Ok().json(m)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
                 }
+            }
+    )
+}
+
+// 与提交委托走同样的检查和撮合，但最后回滚事务，不冻结资金、不成交，也不推送
+fn preview_order_query(order: OrderModel, user: RememberUserModel, pool: web::Data<Pool>, fees: web::Data<FeeSchedule>, settlement: web::Data<SettlementSchedule>, shorting: web::Data<ShortSelling>, margin: web::Data<MarginSettings>) -> Result<OrderPreviewModel, EngineError> {
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let mut preview = None;
+    let rolled_back = conn.transaction::<(), EngineError, _>(|| {
+        let placed = place_order(&order, &user, &fees, &settlement, &shorting, &margin, conn)?;
+        preview = Some(OrderPreviewModel::from_placed(placed, &order.entype));
+        Err(EngineError::from(diesel::result::Error::RollbackTransaction))
+    });
+
+    match (preview, rolled_back) {
+        (Some(preview), _) => Ok(preview),
+        (None, Err(err)) => Err(err),
+        (None, Ok(())) => Err(EngineError::InternalError(format!("服务端逻辑错误。")))
+    }
+}
+
+// 新的买入委托与委托簿中的卖出委托撮合，按价格从低到高、时间从早到晚成交
+pub fn match_ask(new_ask: &mut AskOrder, currency: &str, fees: &FeeSchedule, settlement: &SettlementSchedule, conn: &PgConnection) -> Result<Matched, EngineError> {
+    use crate::schema::user_bid_orders::dsl as biddsl;
+
+    let query = biddsl::user_bid_orders.filter(
+                    biddsl::stock_id.eq(new_ask.stock_id).and(
+                        biddsl::unfulfilled.ne(0)
+                    ).and(
+                        biddsl::price.le(new_ask.price)
+                    )
+                )
+                    .order_by(
+                        biddsl::price.asc()
+                    )
+                    .then_order_by(
+                        biddsl::created_at.asc()   
+                    );
+                
+
+    debug!("Ask matching query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let mut bids = query.get_results::<BidOrder>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询卖出委托错误：{}", db_err))
+        })?;
+
+    let mut deals = Vec::new();
+    let mut makers = Vec::new();
 
-                deal_num
+    for bid in &mut bids {
+        let deal_amount = std::cmp::min(bid.unfulfilled, new_ask.unfulfilled);
+        deals.push(settle_deal(new_ask, bid, deal_amount, AskOrBid::Ask, currency, fees, settlement, conn)?);
+        makers.push(OrderUpdate::from_bid(bid));
+
+        if new_ask.unfulfilled == 0 {
+            break;
+        }
+    }
+
+    Ok(Matched { deals, makers })
+}
+
+// 新的卖出委托与委托簿中的买入委托撮合，按价格从高到低、时间从早到晚成交
+pub fn match_bid(new_bid: &mut BidOrder, currency: &str, fees: &FeeSchedule, settlement: &SettlementSchedule, conn: &PgConnection) -> Result<Matched, EngineError> {
+    use crate::schema::user_ask_orders::dsl as askdsl;
+
+    let query = askdsl::user_ask_orders.filter(
+                    askdsl::stock_id.eq(new_bid.stock_id).and(
+                        askdsl::unfulfilled.ne(0)
+                    ).and(
+                        askdsl::price.ge(new_bid.price)
+                    )
+                )
+                    .order_by(
+                        askdsl::price.desc()
+                    )
+                    .then_order_by(
+                        askdsl::created_at.asc()   
+                    );
+                
+
+    debug!("Ask matching query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let mut asks = query.get_results::<AskOrder>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询查找委托错误：{}", db_err))
+        })?;
+
+    let mut deals = Vec::new();
+    let mut makers = Vec::new();
+
+    for ask in &mut asks {
+        let deal_amount = std::cmp::min(ask.unfulfilled, new_bid.unfulfilled);
+        deals.push(settle_deal(ask, new_bid, deal_amount, AskOrBid::Bid, currency, fees, settlement, conn)?);
+        makers.push(OrderUpdate::from_ask(ask));
+
+        if new_bid.unfulfilled == 0 {
+            break;
+        }
+    }
+
+    Ok(Matched { deals, makers })
+}
+
+// 结算一笔撮合成交：成交价为挂单方的委托价，taker 表示新进入的委托是买单还是卖单，currency 为股票的报价币种
+fn settle_deal(ask: &mut AskOrder, bid: &mut BidOrder, deal_amount: i64, taker: AskOrBid, currency: &str, fees: &FeeSchedule, settlement: &SettlementSchedule, conn: &PgConnection) -> Result<NewDeal, EngineError> {
+    use crate::schema::users::dsl as usrdsl;
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::deals::dsl as dldsl;
+
+    let (price, buyer_liquidity) = match taker {
+        AskOrBid::Ask => (bid.price, Liquidity::Taker),
+        AskOrBid::Bid => (ask.price, Liquidity::Maker),
+    };
+
+    ask.unfulfilled -= deal_amount;
+    ask.updated_at = chrono::Utc::now().naive_utc();
+    bid.unfulfilled -= deal_amount;
+    bid.updated_at = chrono::Utc::now().naive_utc();
+
+    let deal_value = price.value(deal_amount);
+    let deal_fees = fees.deal_fees(deal_value, buyer_liquidity, ask.fee_paid, ask.fee_frozen, bid.fee_paid);
+
+    // 买方佣金从委托冻结的佣金中扣除，最低佣金按委托计算，总额不超过冻结的佣金
+    ask.fee_frozen -= deal_fees.buyer_commission;
+    ask.fee_paid += deal_fees.buyer_commission;
+    // 卖方佣金从卖出所得中扣除，最低佣金同样按委托计算
+    bid.fee_paid += deal_fees.seller_commission;
+
+    // 成交价低于买方委托价的差价，以及委托完成后剩余的冻结佣金，返还给买方
+    let mut giveback_buyer_cash = ask.price.value(deal_amount) - deal_value;
+    if ask.unfulfilled == 0 {
+        giveback_buyer_cash += ask.fee_frozen;
+        ask.fee_frozen = Money::zero();
+    }
+    let mut give_seller_cash = deal_value - deal_fees.seller_commission - deal_fees.stamp_duty;
+
+    save_ask(OrderEventKind::Filled, ask, conn)?;
+    save_bid(OrderEventKind::Filled, bid, conn)?;
+
+    add_balance(ask.user_id, currency, giveback_buyer_cash, conn)?;
+
+    let traded_at = chrono::Utc::now().naive_utc();
+
+    // 强制平仓所得先偿还融资借款，不受交收周期限制
+    if bid.liquidation {
+        give_seller_cash -= repay_margin(bid.user_id, currency, give_seller_cash, conn)?;
+    }
+
+    // 卖出所得的现金按交收周期到账
+    match settlement.cash_settle_at(traded_at) {
+        Some(settle_at) => add_pending(
+            NewPendingSettlement {
+                user_id: bid.user_id,
+                stock_id: None,
+                amount: give_seller_cash.0,
+                settle_at,
+                created_at: traded_at,
+                currency: Some(currency.to_owned())
             },
-            AskOrBid::Bid => {
-                let mut new_bid = new_bid.ok_or_else(|| EngineError::InternalError(format!("服务端逻辑错误。")))?;
-                let query = askdsl::user_ask_orders.filter(
-                                askdsl::stock_id.eq(order.stock_id).and(
-                                    askdsl::unfulfilled.ne(0)
-                                ).and(
-                                    askdsl::price.ge(order.price)
-                                )
-                            )
-                                .order_by(
-                                    askdsl::price.desc()
-                                )
-                                .then_order_by(
-                                    askdsl::created_at.asc()   
-                                );
-                            
-
-                debug!("Ask matching query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
-
-                let mut asks = query.get_results::<AskOrder>(conn)
-                    .map_err(|db_err| {
-                        debug!("Database query error: {}", db_err);
-                        EngineError::InternalError(format!("数据库查询查找委托错误：{}", db_err))
-                    })?;
-
-                let mut deal_num = 0;
-
-                for ask in &mut asks {
-                    let deal_amount = std::cmp::min(ask.unfulfilled, new_bid.unfulfilled);
-                    new_bid.unfulfilled -= deal_amount;
-                    new_bid.updated_at = chrono::Utc::now().naive_utc();
-                    ask.unfulfilled -= deal_amount;
-                    ask.updated_at = chrono::Utc::now().naive_utc();
-                    let deal = NewDeal {
-                        buy_user_id: ask.user_id,
-                        sell_user_id: Some(new_bid.user_id),
-                        stock_id: new_bid.stock_id,
-                        price: ask.price,
-                        amount: deal_amount,
-                        created_at: chrono::Utc::now().naive_utc()
-                    };
-                    debug!("Deal: {:?}", deal);
-                    deal_num += deal_amount;
-                    let give_seller_cash = deal_amount * (deal.price as i64);
-                    // let giveback_buyer_cash = 0;
-                    
-                    new_bid.save_changes::<BidOrder>(conn).map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库重设新卖委托错误：{}", db_err))
-                        })?;
-                    ask.save_changes::<AskOrder>(conn).map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库重设旧买委托错误：{}", db_err))
-                        })?;
-                    diesel::update(usrdsl::users.find(new_bid.user_id))
-                        .set(usrdsl::balance.eq(usrdsl::balance + give_seller_cash))
-                        .execute(conn)
-                        .map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库重设卖家余额错误：{}", db_err))
-                        })?;
-                    diesel::insert_into(dldsl::deals).values(&deal)
-                        .execute(conn)
-                        .map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库插入交易错误：{}", db_err))
-                        })?;
-                    diesel::insert_into(reldsl::user_hold_stock)
-                        .values(
-                            UserStockRel {
-                                user_id: deal.buy_user_id,
-                                stock_id: deal.stock_id,
-                                hold: deal.amount,
-                                updated_at: chrono::Utc::now().naive_utc()
-                            }
-                        )
-                        .on_conflict((reldsl::user_id, reldsl::stock_id))
-                        .do_update()
-                        .set((
-                            reldsl::hold.eq(reldsl::hold + deal.amount),
-                            reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
-                        ))
-                        .execute(conn)
-                        .map_err(|db_err| {
-                            debug!("Database query error: {}", db_err);
-                            EngineError::InternalError(format!("数据库重设买家股票数量错误：{}", db_err))
-                        })?;
-
-                    if new_bid.unfulfilled == 0 {
-                        break;
+            conn
+        )?,
+        None => {
+            add_balance(bid.user_id, currency, give_seller_cash, conn)?;
+        }
+    }
+
+    credit_house(deal_fees.total(), currency, fees, conn)?;
+
+    let deal = NewDeal {
+        buy_user_id: ask.user_id,
+        sell_user_id: Some(bid.user_id),
+        stock_id: ask.stock_id,
+        price,
+        amount: deal_amount,
+        created_at: traded_at,
+        buyer_commission: deal_fees.buyer_commission,
+        seller_commission: deal_fees.seller_commission,
+        stamp_duty: deal_fees.stamp_duty
+    };
+    debug!("Deal: {:?}", deal);
+
+    diesel::insert_into(dldsl::deals).values(&deal)
+        .execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库插入交易错误：{}", db_err))
+        })?;
+
+    update_candles(deal.stock_id, deal.price, deal.amount, deal.created_at, conn)?;
+
+    // 回购的股票直接注销，不计入发行人的持股
+    if let Some(buyback_id) = ask.buyback_id {
+        retire_shares(buyback_id, deal.stock_id, deal.amount, deal_value + deal_fees.buyer_commission, conn)?;
+        return Ok(deal);
+    }
+
+    // 买入平仓的委托，成交的股票先用于归还借券，不受交收周期限制
+    let covered = if ask.cover {
+        cover_loans(deal.buy_user_id, deal.stock_id, deal.amount, conn)?
+    } else {
+        0
+    };
+    let hold_amount = deal.amount - covered;
+    if hold_amount == 0 {
+        return Ok(deal);
+    }
+
+    // 买入的股票按交收周期到账，交收前不能卖出
+    match settlement.stock_settle_at(traded_at) {
+        Some(settle_at) => add_pending(
+            NewPendingSettlement {
+                user_id: deal.buy_user_id,
+                stock_id: Some(deal.stock_id),
+                amount: hold_amount,
+                settle_at,
+                created_at: traded_at,
+                currency: None
+            },
+            conn
+        )?,
+        None => {
+            diesel::insert_into(reldsl::user_hold_stock)
+                .values(
+                    UserStockRel {
+                        user_id: deal.buy_user_id,
+                        stock_id: deal.stock_id,
+                        hold: hold_amount,
+                        updated_at: chrono::Utc::now().naive_utc()
                     }
-                }
+                )
+                .on_conflict((reldsl::user_id, reldsl::stock_id))
+                .do_update()
+                .set((
+                    reldsl::hold.eq(reldsl::hold + hold_amount),
+                    reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
+                ))
+                .execute(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库重设买家股票数量错误：{}", db_err))
+                })?;
+        }
+    }
 
-                deal_num
-            }
-        };
+    Ok(deal)
+}
 
-        Ok(deal_num)
-    })
+// 手续费计入平台账户
+pub fn credit_house(amount: Money, currency: &str, fees: &FeeSchedule, conn: &PgConnection) -> Result<(), EngineError> {
+    if amount == Money::zero() {
+        return Ok(());
+    }
+
+    add_balance(fees.house_user_id, currency, amount, conn)?;
+
+    Ok(())
 }
 
 
@@ -549,7 +887,7 @@ pub struct ReturnOrderModel {
     pub user_name: String,
     pub stock_id: i64,
     pub stock_name: String,
-    pub price: i32,
+    pub price: Price,
     pub volume: i64,
     pub unfulfilled: i64,
     pub created_at: chrono::NaiveDateTime,
@@ -840,13 +1178,15 @@ fn get_bid_query(bid_id: u64, _: RememberUserModel, pool: web::Data<Pool>) -> Re
 pub fn revoke_ask(
     ask_id: web::Path<u64>,
     user: RememberUserModel,
-    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    feed: web::Data<MarketFeed>,
+    notify: web::Data<UserFeed>
 ) -> impl Future<Item = HttpResponse, Error = EngineError> {
     let ask_id = ask_id.into_inner();
    
     web::block(
         move || {
-            revoke_ask_query(ask_id, user, pool)
+            revoke_ask_query(ask_id, user, pool, feed, notify)
         }
     ).then(
         move |res: Result<(), BlockingError<EngineError>>|
@@ -860,7 +1200,7 @@ pub fn revoke_ask(
     )
 }
 
-fn revoke_ask_query(ask_id: u64, user: RememberUserModel, pool: web::Data<Pool>) -> Result<(), EngineError> {
+fn revoke_ask_query(ask_id: u64, user: RememberUserModel, pool: web::Data<Pool>, feed: web::Data<MarketFeed>, notify: web::Data<UserFeed>) -> Result<(), EngineError> {
     use crate::schema::stocks::dsl as stkdsl;
     use crate::schema::users::dsl as usrdsl;
     use crate::schema::user_hold_stock::dsl as reldsl;
@@ -875,20 +1215,17 @@ fn revoke_ask_query(ask_id: u64, user: RememberUserModel, pool: web::Data<Pool>)
 
     use diesel::pg::expression::array_comparison::AsArrayExpression;
 
-    conn.transaction(|| {
+    let cancelled = conn.transaction(|| {
         // 保证原子性
 
         // 返钱
 
-        let (ask_unful, ask_price): (i64, i32)
+        let ask: AskOrder
             = askdsl::user_ask_orders.filter(
                     askdsl::id.eq(ask_id).and(
                         askdsl::user_id.eq(user.id)
                     )
                 ).limit(1)
-                .select(
-                    (askdsl::unfulfilled, askdsl::price)
-                )
                 .get_result(conn)
                 .optional()
                 .map_err(|db_err| {
@@ -899,21 +1236,13 @@ fn revoke_ask_query(ask_id: u64, user: RememberUserModel, pool: web::Data<Pool>)
                     EngineError::NotFound(format!("未找到请求的委托。"))
                 })?;
 
-        let query = diesel::update(usrdsl::users.find(user.id))
-                        .set(usrdsl::balance.eq(usrdsl::balance + ask_unful * ask_price as i64));
-
-        debug!("New refund balance query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
-
-        let affected_rows = query.execute(conn)
-            .map_err(|db_err| {
-                debug!("Database query error: {}", db_err);
-                EngineError::InternalError(format!("数据库更新余额错误：{}", db_err))
-            })?;
+        // 回购计划挂出的委托由撮合引擎管理，只能通过终止回购计划撤销
+        if ask.buyback_id.is_some() {
+            return Err(EngineError::BadRequest(format!("该委托属于回购计划，请终止回购计划以撤销委托。")));
+        }
 
-        match affected_rows {
-            1 => Ok(()),
-            _ => Err(EngineError::InternalError(format!("数据库更新余额，影响行数非 1：{}", affected_rows)))
-        }?;
+        let currency = stock_currency(ask.stock_id, conn)?;
+        add_balance(user.id, &currency, ask.price.value(ask.unfulfilled) + ask.fee_frozen, conn)?;
 
         // 删除委托单
         let query = diesel::delete(askdsl::user_ask_orders.find(ask_id));
@@ -930,9 +1259,21 @@ fn revoke_ask_query(ask_id: u64, user: RememberUserModel, pool: web::Data<Pool>)
             1 => Ok(()),
             _ => Err(EngineError::InternalError(format!("数据库删除委托，影响行数非 1：{}", affected_rows)))
         }?;
+        record_ask(OrderEventKind::Cancelled, &ask, conn)?;
 
-        Ok(())
-    })
+        Ok(OrderUpdate::from_ask(&ask))
+    })?;
+
+    // 提交后推送盘口的变化，以及撤单和余额的变化
+    if let Err(err) = feed.publish_book(cancelled.stock_id, conn) {
+        error!("Publishing market data failed: {}", err);
+    }
+    notify.publish_order(OrderStatus::Cancelled, &cancelled);
+    if let Err(err) = notify.publish_account(user.id, None, conn) {
+        error!("Publishing user events failed: {}", err);
+    }
+
+    Ok(())
 }
 
 
@@ -940,13 +1281,15 @@ fn revoke_ask_query(ask_id: u64, user: RememberUserModel, pool: web::Data<Pool>)
 pub fn revoke_bid(
     bid_id: web::Path<u64>,
     user: RememberUserModel,
-    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    feed: web::Data<MarketFeed>,
+    notify: web::Data<UserFeed>
 ) -> impl Future<Item = HttpResponse, Error = EngineError> {
     let bid_id = bid_id.into_inner();
    
     web::block(
         move || {
-            revoke_bid_query(bid_id, user, pool)
+            revoke_bid_query(bid_id, user, pool, feed, notify)
         }
     ).then(
         move |res: Result<(), BlockingError<EngineError>>|
@@ -960,7 +1303,7 @@ pub fn revoke_bid(
     )
 }
 
-fn revoke_bid_query(bid_id: u64, user: RememberUserModel, pool: web::Data<Pool>) -> Result<(), EngineError> {
+fn revoke_bid_query(bid_id: u64, user: RememberUserModel, pool: web::Data<Pool>, feed: web::Data<MarketFeed>, notify: web::Data<UserFeed>) -> Result<(), EngineError> {
     use crate::schema::stocks::dsl as stkdsl;
     use crate::schema::users::dsl as usrdsl;
     use crate::schema::user_hold_stock::dsl as reldsl;
@@ -975,19 +1318,16 @@ fn revoke_bid_query(bid_id: u64, user: RememberUserModel, pool: web::Data<Pool>)
 
     use diesel::pg::expression::array_comparison::AsArrayExpression;
 
-    conn.transaction(|| {
+    let cancelled = conn.transaction(|| {
         // 保证原子性
 
         // 返还股票
-        let (bid_unful, stock_id): (i64, i64)
+        let bid: BidOrder
             = biddsl::user_bid_orders.filter(
                     biddsl::id.eq(bid_id).and(
                         biddsl::user_id.eq(user.id)
                     )
                 ).limit(1)
-                .select(
-                    (biddsl::unfulfilled, biddsl::stock_id)
-                )
                 .get_result(conn)
                 .optional()
                 .map_err(|db_err| {
@@ -998,9 +1338,24 @@ fn revoke_bid_query(bid_id: u64, user: RememberUserModel, pool: web::Data<Pool>)
                     EngineError::NotFound(format!("未找到请求的委托。"))
                 })?;
 
-        let query = diesel::update(reldsl::user_hold_stock.find((user.id, stock_id)))
+        if bid.liquidation {
+            return Err(EngineError::BadRequest(format!("强制平仓的委托不能撤销。")));
+        }
+
+        // 卖空的委托可能没有持股记录，未成交的借入股票撤单后计入持有，借券仍需归还
+        let query = diesel::insert_into(reldsl::user_hold_stock)
+                        .values(
+                            UserStockRel {
+                                user_id: user.id,
+                                stock_id: bid.stock_id,
+                                hold: bid.unfulfilled,
+                                updated_at: chrono::Utc::now().naive_utc()
+                            }
+                        )
+                        .on_conflict((reldsl::user_id, reldsl::stock_id))
+                        .do_update()
                         .set((
-                            reldsl::hold.eq(reldsl::hold + bid_unful),
+                            reldsl::hold.eq(reldsl::hold + bid.unfulfilled),
                             reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
                         ));
 
@@ -1032,9 +1387,21 @@ fn revoke_bid_query(bid_id: u64, user: RememberUserModel, pool: web::Data<Pool>)
             1 => Ok(()),
             _ => Err(EngineError::InternalError(format!("数据库删除委托，影响行数非 1：{}", affected_rows)))
         }?;
+        record_bid(OrderEventKind::Cancelled, &bid, conn)?;
 
-        Ok(())
-    })
+        Ok(OrderUpdate::from_bid(&bid))
+    })?;
+
+    // 提交后推送盘口的变化，以及撤单和持有量的变化
+    if let Err(err) = feed.publish_book(cancelled.stock_id, conn) {
+        error!("Publishing market data failed: {}", err);
+    }
+    notify.publish_order(OrderStatus::Cancelled, &cancelled);
+    if let Err(err) = notify.publish_account(user.id, Some(cancelled.stock_id), conn) {
+        error!("Publishing user events failed: {}", err);
+    }
+
+    Ok(())
 }
 
 
@@ -1048,12 +1415,13 @@ pub fn ipo_buy(
     stock_id: web::Path<u64>,
     ipobuy: web::Json<IPOBuyModel>,
     curr_user: RememberUserModel,
-    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    fees: web::Data<FeeSchedule>
 ) -> impl Future<Item = HttpResponse, Error = EngineError> {
    
     web::block(
         move || {
-            ipo_buy_query(stock_id.into_inner(), ipobuy.into_inner(), curr_user, pool)
+            ipo_buy_query(stock_id.into_inner(), ipobuy.into_inner(), curr_user, pool, fees)
         }
     ).then(
         move |res: Result<i64, BlockingError<EngineError>>|
@@ -1075,7 +1443,7 @@ pub fn ipo_buy(
     )
 }
 
-fn ipo_buy_query(stock_id: u64, ipobuy: IPOBuyModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<i64, EngineError> {
+fn ipo_buy_query(stock_id: u64, ipobuy: IPOBuyModel, user: RememberUserModel, pool: web::Data<Pool>, fees: web::Data<FeeSchedule>) -> Result<i64, EngineError> {
     use crate::schema::stocks::dsl as stkdsl;
     use crate::schema::users::dsl as usrdsl;
     use crate::schema::new_stocks::dsl as newdsl;
@@ -1099,11 +1467,11 @@ fn ipo_buy_query(stock_id: u64, ipobuy: IPOBuyModel, user: RememberUserModel, po
                             .filter(
                                 stkdsl::into_market.eq(false)
                             )
-                            .select(stkdsl::id);
+                            .select(stkdsl::currency);
 
         debug!("New ipobuy query_stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query_stock));
 
-        query_stock.get_result::<i64>(conn)
+        let currency = query_stock.get_result::<String>(conn)
             .optional()
             .map_err(|db_err| {
                 debug!("Database query error: {}", db_err);
@@ -1125,6 +1493,13 @@ fn ipo_buy_query(stock_id: u64, ipobuy: IPOBuyModel, user: RememberUserModel, po
             })?
             .ok_or_else(|| EngineError::InternalError(format!("该股票没有新股发行信息，请联系管理员维护！")))?;
 
+        if new_stock.subscribe_ends_at.is_some() {
+            return Err(EngineError::BadRequest(format!("该股票在认购期内接受认购，认购期结束后统一配售，不能直接买入！")));
+        }
+        if new_stock.failed_at.is_some() || new_stock.deadline.map_or(false, |deadline| deadline <= chrono::Utc::now().naive_utc()) {
+            return Err(EngineError::BadRequest(format!("该股票的发行已经截止，不能再 IPO 买入！")));
+        }
+
         let effective_amount = std::cmp::min(new_stock.offer_unfulfilled, amount);
 
         new_stock.offer_unfulfilled -= effective_amount;
@@ -1133,32 +1508,29 @@ fn ipo_buy_query(stock_id: u64, ipobuy: IPOBuyModel, user: RememberUserModel, po
             EngineError::InternalError(format!("数据库重设 IPO 发行余量错误：{}", db_err))
         })?;
 
-        // 检查钱，扣钱
+        // 检查钱，扣钱（认购新股按吃单费率收取佣金）
 
-        let query_charge = diesel::update(usrdsl::users.find(user.id))
-                        .set(usrdsl::balance.eq(usrdsl::balance - new_stock.offer_price as i64 * effective_amount));
+        let cost = new_stock.offer_price.checked_value(effective_amount)
+            .ok_or_else(|| EngineError::BadRequest(format!("认购金额过大！")))?;
+        let commission = fees.commission(cost, Liquidity::Taker);
 
-        debug!("New charge balance query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query_charge));
+        let balance_after = add_balance(user.id, &currency, -(cost + commission), conn)?;
 
-        let user_after = query_charge.get_result::<User>(conn)
-            .map_err(|db_err| {
-                debug!("Database query error: {}", db_err);
-                EngineError::InternalError(format!("数据库更新余额错误：{}", db_err))
-            })?;
-
-        if user_after.balance < 0 {
-            let err_msg = format!("账户余额不足，你还需要 {} 元来购买剩余数量的新发行股票。", (-user_after.balance) as f32 / 100.);
+        if balance_after < Money::zero() {
+            let err_msg = format!("{} 账户余额不足（含佣金 {} {}），你还需要 {} {}来购买剩余数量的新发行股票。", currency, commission, currency, -balance_after, currency);
             return Err(EngineError::Insufficient(
                 OrderResult {
                     succeed: false,
                     message: Some(err_msg.clone()),
                     error: Some(err_msg),
                     deal_amount: None,
-                    lack: Some(-user_after.balance)
+                    lack: Some((-balance_after).0)
                 }
             ));
         }
 
+        credit_house(commission, &currency, &fees, conn)?;
+
         // 加交易、加股票
 
         let deal = NewDeal {
@@ -1167,7 +1539,10 @@ fn ipo_buy_query(stock_id: u64, ipobuy: IPOBuyModel, user: RememberUserModel, po
             stock_id: stock_id,
             price: new_stock.offer_price,
             amount: effective_amount,
-            created_at: chrono::Utc::now().naive_utc()
+            created_at: chrono::Utc::now().naive_utc(),
+            buyer_commission: commission,
+            seller_commission: Money::zero(),
+            stamp_duty: Money::zero()
         };
         
         diesel::insert_into(dldsl::deals).values(&deal)
@@ -1222,11 +1597,17 @@ pub struct DealModel {
     #[sql_type = "sql_types::Nullable<sql_types::Varchar>"]
     pub sell_user_name: Option<String>,
     #[sql_type = "sql_types::Int4"]
-    pub price: i32,
+    pub price: Price,
     #[sql_type = "sql_types::Int8"]
     pub amount: i64,
     #[sql_type = "sql_types::Timestamp"]
-    pub created_at: chrono::NaiveDateTime
+    pub created_at: chrono::NaiveDateTime,
+    #[sql_type = "sql_types::Int8"]
+    pub buyer_commission: Money,
+    #[sql_type = "sql_types::Int8"]
+    pub seller_commission: Money,
+    #[sql_type = "sql_types::Int8"]
+    pub stamp_duty: Money
 }
 
 
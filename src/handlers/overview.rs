@@ -0,0 +1,527 @@
+use actix_web::{
+    web, HttpResponse
+};
+use actix_web::error::BlockingError;
+use crate::models::MarketIndex;
+use crate::money::{Money, Price};
+use crate::candles::{Candle, Interval, fill_gaps};
+use crate::index::{IndexSettings, base_divisor, rebalance_divisor, index_value};
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::quotation::{CandlesQueryModel, CandleRowModel, PriceModel, candle_range};
+
+use diesel::sql_types;
+
+// 涨跌幅和成交量排行默认返回的股票数，以及一次最多返回的股票数
+const DEFAULT_MOVERS : u32 = 10;
+const MAX_MOVERS : u32 = 100;
+
+#[derive(Debug, Clone, Copy)]
+enum Movers {
+    Gainers,    // 涨幅榜
+    Losers,     // 跌幅榜
+    Active      // 成交量榜
+}
+
+impl Movers {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Movers::Gainers => "gainers",
+            Movers::Losers => "losers",
+            Movers::Active => "active",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MoversQueryModel {
+    pub limit: Option<u32>
+}
+
+// 当天（UTC）有成交的股票，涨跌相对上一个有成交的交易日的收盘价
+#[derive(QueryableByName, Serialize)]
+pub struct MoverModel {
+    #[sql_type = "sql_types::Int8"]
+    pub stock_id: i64,
+    #[sql_type = "sql_types::Varchar"]
+    pub name: String,
+    #[sql_type = "sql_types::Varchar"]
+    pub currency: String,
+    #[sql_type = "sql_types::Int4"]
+    pub price: Price,
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub prev_close: Option<Price>,
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub change: Option<Price>,
+    #[sql_type = "sql_types::Nullable<sql_types::Double>"]
+    pub change_percent: Option<f64>,
+    #[sql_type = "sql_types::Int8"]
+    pub volume: i64,
+    #[sql_type = "sql_types::Int8"]
+    pub turnover: Money
+}
+
+pub fn get_gainers(
+    movers: web::Query<MoversQueryModel>,
+    _: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    get_movers(Movers::Gainers, movers.into_inner(), pool)
+}
+
+pub fn get_losers(
+    movers: web::Query<MoversQueryModel>,
+    _: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    get_movers(Movers::Losers, movers.into_inner(), pool)
+}
+
+pub fn get_most_active(
+    movers: web::Query<MoversQueryModel>,
+    _: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    get_movers(Movers::Active, movers.into_inner(), pool)
+}
+
+fn get_movers(kind: Movers, movers: MoversQueryModel, pool: web::Data<Pool>) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    web::block(
+        move || {
+            get_movers_query(kind, movers, pool)
+        }
+    ).then(
+        move |res: Result<Vec<MoverModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(m) => Ok(HttpResponse::Ok().json(m)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_movers_query(kind: Movers, movers: MoversQueryModel, pool: web::Data<Pool>) -> Result<Vec<MoverModel>, EngineError> {
+    let limit = movers.limit.unwrap_or(DEFAULT_MOVERS);
+    if limit == 0 || limit > MAX_MOVERS {
+        return Err(EngineError::BadRequest(format!("排行的股票数必须在 1 到 {} 之间。", MAX_MOVERS)));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let session = Interval::OneDay.bucket(chrono::Utc::now().naive_utc());
+
+    let query = diesel::sql_query(include_str!("movers.sql"))
+                    .bind::<sql_types::Int8, _>(session)
+                    .bind::<sql_types::Varchar, _>(kind.as_str())
+                    .bind::<sql_types::Int8, _>(limit as i64);
+
+    debug!("Get movers SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.load::<MoverModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+//////////////////
+#[derive(Serialize)]
+pub struct IndexModel {
+    pub name: String,
+    pub currency: String,
+    pub value: Option<Price>,           // 最新点位，单位为 0.01 点
+    pub prev_close: Option<Price>,      // 上一个交易日的收盘点位
+    pub change: Option<Price>,
+    pub change_percent: Option<f64>,
+    pub members: Vec<i64>,              // 成分股
+    pub updated_at: chrono::NaiveDateTime
+}
+
+pub fn get_index(
+    _: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    settings: web::Data<IndexSettings>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            get_index_query(pool, settings)
+        }
+    ).then(
+        move |res: Result<IndexModel, BlockingError<EngineError>>|
+            match res {
+                Ok(m) => Ok(HttpResponse::Ok().json(m)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_index_query(pool: web::Data<Pool>, settings: web::Data<IndexSettings>) -> Result<IndexModel, EngineError> {
+    use crate::schema::market_indices::dsl as idxdsl;
+    use crate::schema::index_candles::dsl as icdsl;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = idxdsl::market_indices.find(&settings.name);
+
+    debug!("Get market index SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let index = query.get_result::<MarketIndex>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?
+        .ok_or_else(|| EngineError::NotFound(format!("指数 {} 尚未开始计算，请稍后再试。", settings.name)))?;
+
+    // 当天的日 K 线收盘即最新点位
+    let session = Interval::OneDay.bucket(chrono::Utc::now().naive_utc());
+    let query = icdsl::index_candles
+                    .filter(
+                        icdsl::index_name.eq(&index.name).and(
+                            icdsl::period.eq(Interval::OneDay.as_str())
+                        ).and(
+                            icdsl::bucket.le(session)
+                        )
+                    )
+                    .order_by(icdsl::bucket.desc())
+                    .select((icdsl::bucket, icdsl::close))
+                    .limit(2);
+
+    debug!("Get market index closes SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let closes = query.get_results::<(i64, Price)>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    let value = closes.first().map(|(_, close)| *close);
+    let prev_close = closes.iter()
+        .find(|(bucket, _)| *bucket < session)
+        .map(|(_, close)| *close);
+    let change = match (value, prev_close) {
+        (Some(value), Some(prev_close)) => Some(Price(value.0 - prev_close.0)),
+        _ => None
+    };
+    let change_percent = match (change, prev_close) {
+        (Some(change), Some(prev_close)) if prev_close.0 != 0 => Some((change.0 as f64 * 10_000.0 / prev_close.0 as f64).round() / 100.0),
+        _ => None
+    };
+
+    Ok(IndexModel {
+        name: index.name,
+        currency: index.currency,
+        value,
+        prev_close,
+        change,
+        change_percent,
+        members: index.members,
+        updated_at: index.updated_at
+    })
+}
+
+pub fn get_index_candles(
+    candles: web::Query<CandlesQueryModel>,
+    _: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    settings: web::Data<IndexSettings>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let candles = candles.into_inner();
+
+    web::block(
+        move || {
+            get_index_candles_query(candles, pool, settings)
+        }
+    ).then(
+        move |res: Result<Vec<Candle>, BlockingError<EngineError>>|
+            match res {
+                Ok(m) => Ok(HttpResponse::Ok().json(m)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+// 同股票的 K 线，没有记录的 K 线用前一根的收盘点位补齐，成交量和成交额为 0
+fn get_index_candles_query(candles: CandlesQueryModel, pool: web::Data<Pool>, settings: web::Data<IndexSettings>) -> Result<Vec<Candle>, EngineError> {
+    let (interval, first, last) = candle_range(&candles)?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = diesel::sql_query(include_str!("indexcandles.sql"))
+                    .bind::<sql_types::Varchar, _>(&settings.name)
+                    .bind::<sql_types::Varchar, _>(interval.as_str())
+                    .bind::<sql_types::Int8, _>(first)
+                    .bind::<sql_types::Int8, _>(last);
+
+    debug!("Get index candles SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let rows = query.load::<CandleRowModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    let query = diesel::sql_query(include_str!("indexprevclose.sql"))
+                    .bind::<sql_types::Varchar, _>(&settings.name)
+                    .bind::<sql_types::Varchar, _>(interval.as_str())
+                    .bind::<sql_types::Int8, _>(first);
+
+    debug!("Get index previous close SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let prev_close = query.get_result::<PriceModel>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?
+        .and_then(|model| model.price);
+
+    let rows = rows.into_iter()
+        .map(|row| row.into_candle(interval))
+        .collect();
+
+    Ok(fill_gaps(rows, first, last, interval, prev_close))
+}
+
+//////////////////
+#[derive(QueryableByName)]
+struct IndexCapModel {
+    #[sql_type = "sql_types::Int8"]
+    stock_id: i64,
+    #[sql_type = "sql_types::Int8"]
+    shares: i64,
+    #[sql_type = "sql_types::Int8"]
+    cap: Money,
+    #[sql_type = "sql_types::Int8"]
+    continuing_cap: Money       // 按上次计算时的流通股数（按其后的拆股合股折算）和当前价格计算的市值，新调入的股票为 0
+}
+
+// 按成分股的当前市值计算指数点位，记入各周期的指数 K 线
+pub fn process_index(settings: &IndexSettings, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::market_indices::dsl as idxdsl;
+
+    conn.transaction(|| {
+        // 保证原子性
+        let query = idxdsl::market_indices
+                        .find(&settings.name)
+                        .for_update();
+
+        debug!("Market index SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let index = query.get_result::<MarketIndex>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let (prev_members, prev_shares, prev_updated_at) = match &index {
+            Some(index) => (index.members.clone(), index.shares.clone(), Some(index.updated_at)),
+            None => (Vec::new(), Vec::new(), None)
+        };
+
+        let query = diesel::sql_query(include_str!("indexcaps.sql"))
+                        .bind::<sql_types::Varchar, _>(&settings.currency)
+                        .bind::<sql_types::Array<sql_types::BigInt>, _>(&settings.stock_ids)
+                        .bind::<sql_types::Array<sql_types::BigInt>, _>(&prev_members)
+                        .bind::<sql_types::Array<sql_types::BigInt>, _>(&prev_shares)
+                        .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(prev_updated_at);
+
+        debug!("Index market caps SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let caps = query.load::<IndexCapModel>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let total_cap: Money = caps.iter().map(|member| member.cap).sum();
+        if total_cap <= Money::zero() {
+            return Ok(());
+        }
+        let members: Vec<i64> = caps.iter().map(|member| member.stock_id).collect();
+        let shares: Vec<i64> = caps.iter().map(|member| member.shares).collect();
+
+        let divisor = match &index {
+            None => base_divisor(total_cap, settings.base_value),
+            Some(index) if index.currency != settings.currency => {
+                return Err(EngineError::InternalError(format!("指数 {} 以 {} 计价，更改币种时请同时更改 MARKET_INDEX_NAME。", index.name, index.currency)));
+            },
+            Some(index) => {
+                // 成分股调入调出，或流通股数因增发、回购、拆股合股而变化时，调整除数使点位连续；
+                // 没有变化时两者相等，除数不变
+                let continuing_cap = caps.iter().map(|member| member.continuing_cap).sum();
+                rebalance_divisor(index.divisor, total_cap, continuing_cap)
+            }
+        }.ok_or_else(|| EngineError::InternalError(format!("指数 {} 的除数超出范围，请更改 MARKET_INDEX_BASE 或 MARKET_INDEX_NAME。", settings.name)))?;
+
+        let now = chrono::Utc::now().naive_utc();
+        let record = MarketIndex {
+            name: settings.name.clone(),
+            currency: settings.currency.clone(),
+            members,
+            divisor,
+            updated_at: now,
+            shares
+        };
+
+        let query = diesel::insert_into(idxdsl::market_indices)
+                        .values(&record)
+                        .on_conflict(idxdsl::name)
+                        .do_update()
+                        .set(&record);
+
+        debug!("Save market index SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        query.execute(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库更新指数错误：{}", db_err))
+            })?;
+
+        let value = index_value(total_cap, divisor);
+        let periods: Vec<&str> = Interval::ALL.iter().map(|interval| interval.as_str()).collect();
+        let buckets: Vec<i64> = Interval::ALL.iter().map(|interval| interval.bucket(now)).collect();
+
+        let query = diesel::sql_query(include_str!("updateindexcandles.sql"))
+                        .bind::<sql_types::Varchar, _>(&settings.name)
+                        .bind::<sql_types::Array<sql_types::Varchar>, _>(&periods)
+                        .bind::<sql_types::Array<sql_types::BigInt>, _>(&buckets)
+                        .bind::<sql_types::Int4, _>(value);
+
+        debug!("Update index candles SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        query.execute(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库更新指数 K 线错误：{}", db_err))
+            })?;
+
+        Ok(())
+    })
+}
+
+pub fn run_index_worker(pool: Pool, settings: IndexSettings, interval: std::time::Duration) {
+    std::thread::spawn(move || {
+        loop {
+            match pool.get() {
+                Ok(conn) => {
+                    if let Err(err) = process_index(&settings, &conn) {
+                        error!("Processing market index failed: {}", err);
+                    }
+                },
+                Err(pool_err) => error!("Processing market index failed, cannot get database connection: {}", pool_err)
+            }
+            std::thread::sleep(interval);
+        }
+    });
+}
+
+#[test]
+fn test_index_continuous_on_share_changes() {
+    use crate::schema::users::dsl as usrdsl;
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+    use crate::schema::stock_splits::dsl as spldsl;
+    use crate::schema::market_indices::dsl as idxdsl;
+    let conn = crate::test_get_data_connection();
+
+    // 在测试事务中进行，结束后回滚
+    conn.test_transaction::<_, EngineError, _>(|| {
+        let now = chrono::Utc::now().naive_utc();
+        let issuer_id = diesel::insert_into(usrdsl::users)
+            .values((usrdsl::name.eq("指数测试发行人"), usrdsl::password_hashed.eq(""), usrdsl::created_at.eq(now)))
+            .returning(usrdsl::id)
+            .get_result::<i64>(&conn)?;
+
+        let stock_id = diesel::insert_into(stkdsl::stocks)
+            .values((
+                stkdsl::name.eq("指数测试股票"),
+                stkdsl::into_market.eq(true),
+                stkdsl::into_market_at.eq(now),
+                stkdsl::price_decimals.eq(2i16),
+                stkdsl::currency.eq("CNY")
+            ))
+            .returning(stkdsl::id)
+            .get_result::<i64>(&conn)?;
+
+        // 发行 1000 股，只售出 800 股，市值按售出的股数计算
+        diesel::insert_into(newdsl::new_stocks)
+            .values((
+                newdsl::id.eq(stock_id),
+                newdsl::issuer_id.eq(issuer_id),
+                newdsl::offer_circ.eq(1000i64),
+                newdsl::offer_price.eq(Price(100)),
+                newdsl::offer_unfulfilled.eq(200i64),
+                newdsl::created_at.eq(now)
+            ))
+            .execute(&conn)?;
+
+        let settings = IndexSettings {
+            name: format!("index-test-{}", stock_id),
+            currency: "CNY".to_owned(),
+            stock_ids: vec![stock_id],
+            base_value: Price(100_000)
+        };
+        let current_value = |conn: &PgConnection| -> Result<Price, EngineError> {
+            let index = idxdsl::market_indices.find(&settings.name).get_result::<MarketIndex>(conn)?;
+            let (circ, unfulfilled, price) = newdsl::new_stocks
+                .find(stock_id)
+                .select((newdsl::offer_circ, newdsl::offer_unfulfilled, newdsl::offer_price))
+                .get_result::<(i64, i64, Price)>(conn)?;
+            assert_eq!(index.shares, vec![circ - unfulfilled]);
+            Ok(index_value(price.value(circ - unfulfilled), index.divisor))
+        };
+
+        process_index(&settings, &conn)?;
+        assert_eq!(current_value(&conn)?, Price(100_000));
+
+        // 增发 300 股，点位不变
+        diesel::update(newdsl::new_stocks.find(stock_id))
+            .set(newdsl::offer_circ.eq(1300i64))
+            .execute(&conn)?;
+        process_index(&settings, &conn)?;
+        assert_eq!(current_value(&conn)?, Price(100_000));
+
+        // 2:1 拆股，股数翻倍、价格减半，点位不变
+        diesel::update(newdsl::new_stocks.find(stock_id))
+            .set((
+                newdsl::offer_circ.eq(2600i64),
+                newdsl::offer_unfulfilled.eq(400i64),
+                newdsl::offer_price.eq(Price(50))
+            ))
+            .execute(&conn)?;
+        diesel::insert_into(spldsl::stock_splits)
+            .values((
+                spldsl::stock_id.eq(stock_id),
+                spldsl::numerator.eq(2i64),
+                spldsl::denominator.eq(1i64),
+                spldsl::reference_price.eq(Price(50)),
+                spldsl::created_at.eq(chrono::Utc::now().naive_utc() + chrono::Duration::seconds(1))
+            ))
+            .execute(&conn)?;
+        process_index(&settings, &conn)?;
+        assert_eq!(current_value(&conn)?, Price(100_000));
+
+        Ok(())
+    });
+}
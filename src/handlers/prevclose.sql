@@ -0,0 +1,8 @@
+SELECT
+    close AS price
+FROM candles
+WHERE stock_id = $1
+    AND period = $2
+    AND bucket < $3
+ORDER BY bucket DESC
+LIMIT 1;
@@ -9,6 +9,8 @@ use crate::models::Stock;
 use crate::models::AskOrder;
 use crate::models::BidOrder;
 use crate::models::Deal;
+use crate::money::{Money, Price};
+use crate::candles::{Candle, Interval, fill_gaps};
 
 use std::convert::TryFrom;
 use std::convert::TryInto;
@@ -33,8 +35,8 @@ use diesel::sql_types;
 pub struct TimeIntervalQuotationModel {
     #[sql_type = "sql_types::Timestamp"]
     pub time: chrono::NaiveDateTime,
-    #[sql_type = "sql_types::Nullable<sql_types::Double>"]
-    pub price: Option<f64>,
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub price: Option<Price>,     // 成交量加权平均价，四舍五入到分
 }
 
 #[derive(Queryable, Serialize, Deserialize)]
@@ -42,7 +44,7 @@ pub struct RecentDealQuotationModel {
     pub id: i64,
     pub buy_user_id: i64,
     pub sell_user_id: Option<i64>,  // 当是 NULL 时，表示是购买发行新股
-    pub price: i32,
+    pub price: Price,
     pub amount: i64,
     pub created_at: chrono::NaiveDateTime
 }
@@ -52,7 +54,25 @@ pub struct OrderByPriceModel {
     #[sql_type = "sql_types::Int8"]
     pub amount: i64,
     #[sql_type = "sql_types::Int4"]
-    pub price: i32,
+    pub price: Price,
+}
+
+// 盘口默认返回的价位档数
+pub const DEFAULT_DEPTH : u32 = 5;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DepthQueryModel {
+    pub depth: Option<u32>      // 每边返回的价位档数，不填为 5 档，0 表示整个委托簿
+}
+
+impl DepthQueryModel {
+    // 绑定到 SQL 的档数限制，None 表示不限制
+    pub fn levels(&self) -> Option<i64> {
+        match self.depth.unwrap_or(DEFAULT_DEPTH) {
+            0 => None,
+            depth => Some(depth as i64)
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -65,10 +85,12 @@ pub struct QuotationModel {
 
 pub fn get_quotation(
     stock_id: web::Path<u64>,
+    depth: web::Query<DepthQueryModel>,
     _: RememberUserModel,
     pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
 ) -> impl Future<Item = HttpResponse, Error = EngineError> {
     let stock_id = stock_id.into_inner();
+    let levels = depth.levels();
     use futures::future::join_all;
 
     enum QueryType {
@@ -81,8 +103,8 @@ pub fn get_quotation(
     let get_block = |query: QueryType, pool: Pool| web::block(move || match query {
         QueryType::Time => get_timequote_query(stock_id, pool),
         QueryType::Deal => get_dealquote_query(stock_id, pool),
-        QueryType::Ask => get_askquote_query(stock_id, pool),
-        QueryType::Bid => get_bidquote_query(stock_id, pool)
+        QueryType::Ask => get_askquote_query(stock_id, levels, pool),
+        QueryType::Bid => get_bidquote_query(stock_id, levels, pool)
     }).from_err();
 
     let pool = pool.into_inner();
@@ -177,7 +199,8 @@ fn get_quotation_query(stock_id: u64, pool: web::Data<Pool>) -> Result<Quotation
     // 买卖委托
 
     let query = diesel::sql_query(include_str!("askquote.sql"))
-                    .bind::<sql_types::BigInt, _>(stock_id);
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(Some(DEFAULT_DEPTH as i64));
 
     debug!("Get ask quote SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
 
@@ -189,7 +212,8 @@ fn get_quotation_query(stock_id: u64, pool: web::Data<Pool>) -> Result<Quotation
 
 
     let query = diesel::sql_query(include_str!("bidquote.sql"))
-                    .bind::<sql_types::BigInt, _>(stock_id);
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(Some(DEFAULT_DEPTH as i64));
 
     debug!("Get bid quote SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
 
@@ -279,7 +303,7 @@ fn get_dealquote_query(stock_id: u64, pool: Pool) -> Result<serde_json::Value, E
     })
 }
 
-fn get_askquote_query(stock_id: u64, pool: Pool) -> Result<serde_json::Value, EngineError> {
+fn get_askquote_query(stock_id: u64, levels: Option<i64>, pool: Pool) -> Result<serde_json::Value, EngineError> {
     use crate::schema::stocks::dsl as stkdsl;
     use crate::schema::users::dsl as usrdsl;
     use crate::schema::new_stocks::dsl as newdsl;
@@ -296,7 +320,8 @@ fn get_askquote_query(stock_id: u64, pool: Pool) -> Result<serde_json::Value, En
     // 买卖委托
 
     let query = diesel::sql_query(include_str!("askquote.sql"))
-                    .bind::<sql_types::BigInt, _>(stock_id);
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(levels);
 
     debug!("Get ask quote SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
 
@@ -311,7 +336,7 @@ fn get_askquote_query(stock_id: u64, pool: Pool) -> Result<serde_json::Value, En
     })
 }
 
-fn get_bidquote_query(stock_id: u64, pool: Pool) -> Result<serde_json::Value, EngineError> {
+fn get_bidquote_query(stock_id: u64, levels: Option<i64>, pool: Pool) -> Result<serde_json::Value, EngineError> {
     use crate::schema::stocks::dsl as stkdsl;
     use crate::schema::users::dsl as usrdsl;
     use crate::schema::new_stocks::dsl as newdsl;
@@ -326,7 +351,8 @@ fn get_bidquote_query(stock_id: u64, pool: Pool) -> Result<serde_json::Value, En
     let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
 
     let query = diesel::sql_query(include_str!("bidquote.sql"))
-                    .bind::<sql_types::BigInt, _>(stock_id);
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(levels);
 
     debug!("Get bid quote SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
 
@@ -341,11 +367,126 @@ fn get_bidquote_query(stock_id: u64, pool: Pool) -> Result<serde_json::Value, En
     })
 }
 
+//////////
+// 逐笔委托，不含委托人
+#[derive(QueryableByName, Serialize)]
+pub struct BookOrderModel {
+    #[sql_type = "sql_types::Int8"]
+    pub id: i64,
+    #[sql_type = "sql_types::Int4"]
+    pub price: Price,
+    #[sql_type = "sql_types::Int8"]
+    pub unfulfilled: i64,
+    #[sql_type = "sql_types::Timestamp"]
+    pub created_at: chrono::NaiveDateTime
+}
+
+#[derive(Serialize)]
+pub struct OrderBookModel {
+    pub ask_orders: Vec<BookOrderModel>,    // 买入委托，价格从高到低
+    pub bid_orders: Vec<BookOrderModel>     // 卖出委托，价格从低到高
+}
+
+pub fn get_order_book(
+    stock_id: web::Path<u64>,
+    depth: web::Query<DepthQueryModel>,
+    _: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let stock_id = stock_id.into_inner();
+    let levels = depth.levels();
+
+    web::block(
+        move || {
+            get_order_book_query(stock_id, levels, pool)
+        }
+    ).then(
+        move |res: Result<OrderBookModel, BlockingError<EngineError>>|
+            match res {
+                Ok(m) => Ok(HttpResponse::Ok().json(m)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+// 委托簿前 levels 档价位上的每一笔委托，同一价位按时间先后排列，即撮合的顺序
+fn get_order_book_query(stock_id: u64, levels: Option<i64>, pool: web::Data<Pool>) -> Result<OrderBookModel, EngineError> {
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = diesel::sql_query(include_str!("askbook.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(levels);
+
+    debug!("Get ask book SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let ask_orders = query.load::<BookOrderModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    let query = diesel::sql_query(include_str!("bidbook.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(levels);
+
+    debug!("Get bid book SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let bid_orders = query.load::<BookOrderModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    Ok(OrderBookModel {
+        ask_orders,
+        bid_orders
+    })
+}
+
 //////////
 #[derive(QueryableByName, Serialize)]
 pub struct PriceModel {
     #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
-    pub price: Option<i32>,
+    pub price: Option<Price>,
+}
+
+// 行情摘要，交易时段为当天（UTC）
+#[derive(QueryableByName, Serialize)]
+pub struct TickerModel {
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub price: Option<Price>,       // 最新成交价
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub open: Option<Price>,
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub high: Option<Price>,
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub low: Option<Price>,
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub prev_close: Option<Price>,  // 上一个有成交的交易日的收盘价
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub change: Option<Price>,
+    #[sql_type = "sql_types::Nullable<sql_types::Double>"]
+    pub change_percent: Option<f64>,
+    #[sql_type = "sql_types::Int8"]
+    pub volume: i64,
+    #[sql_type = "sql_types::Int8"]
+    pub turnover: Money,
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub vwap: Option<Price>,        // 成交量加权平均价
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub best_ask_price: Option<Price>,      // 买一
+    #[sql_type = "sql_types::Nullable<sql_types::Int8>"]
+    pub best_ask_amount: Option<i64>,
+    #[sql_type = "sql_types::Nullable<sql_types::Int4>"]
+    pub best_bid_price: Option<Price>,      // 卖一
+    #[sql_type = "sql_types::Nullable<sql_types::Int8>"]
+    pub best_bid_amount: Option<i64>
 }
 
 pub fn get_prices(
@@ -361,7 +502,7 @@ pub fn get_prices(
             get_prices_query(stock_ids, pool)
         }
     ).then(
-        move |res: Result<Vec<PriceModel>, BlockingError<EngineError>>|
+        move |res: Result<Vec<TickerModel>, BlockingError<EngineError>>|
             match res {
                 Ok(m) => Ok(HttpResponse::Ok().json(m)),
                 Err(err) => match err {
@@ -372,7 +513,8 @@ pub fn get_prices(
     )
 }
 
-fn get_prices_query(stock_ids: Vec<u64>, pool: web::Data<Pool>) -> Result<Vec<PriceModel>, EngineError> {
+// 每只股票的最新价、当天的开高低收和成交量、前收盘价以及买一卖一，一次查询取得
+fn get_prices_query(stock_ids: Vec<u64>, pool: web::Data<Pool>) -> Result<Vec<TickerModel>, EngineError> {
     use crate::schema::stocks::dsl as stkdsl;
     use crate::schema::users::dsl as usrdsl;
     use crate::schema::new_stocks::dsl as newdsl;
@@ -386,14 +528,218 @@ fn get_prices_query(stock_ids: Vec<u64>, pool: web::Data<Pool>) -> Result<Vec<Pr
 
     let stock_ids = stock_ids.into_iter().map(|stock_id| i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))).collect::<Result<Vec<i64>, EngineError>>()?;
 
+    let session = Interval::OneDay.bucket(chrono::Utc::now().naive_utc());
+
     let query = diesel::sql_query(include_str!("latestdeal.sql"))
-                    .bind::<sql_types::Array<sql_types::BigInt>, _>(&stock_ids);
+                    .bind::<sql_types::Array<sql_types::BigInt>, _>(&stock_ids)
+                    .bind::<sql_types::Int8, _>(session);
 
     debug!("Get prices SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
 
-    query.load::<PriceModel>(conn)
+    query.load::<TickerModel>(conn)
         .map_err(|db_err| {
             debug!("Database query error: {}", db_err);
             EngineError::InternalError(format!("数据库查询错误：{}", db_err))
         })
 }
+
+//////////
+// 不指定时间范围时返回最近的 K 线根数，以及一次最多返回的根数
+const DEFAULT_CANDLES : i64 = 100;
+const MAX_CANDLES : i64 = 1000;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CandlesQueryModel {
+    pub interval: String,   // 1m、5m、15m、1h、1d 或 1w
+    pub from: Option<chrono::NaiveDateTime>,
+    pub to: Option<chrono::NaiveDateTime>   // 不含该时刻，不填则到当前时刻
+}
+
+#[derive(QueryableByName)]
+pub struct CandleRowModel {
+    #[sql_type = "sql_types::Int8"]
+    pub bucket: i64,
+    #[sql_type = "sql_types::Int4"]
+    pub open: Price,
+    #[sql_type = "sql_types::Int4"]
+    pub high: Price,
+    #[sql_type = "sql_types::Int4"]
+    pub low: Price,
+    #[sql_type = "sql_types::Int4"]
+    pub close: Price,
+    #[sql_type = "sql_types::Int8"]
+    pub volume: i64,
+    #[sql_type = "sql_types::Int8"]
+    pub turnover: Money
+}
+
+impl CandleRowModel {
+    pub fn into_candle(self, interval: Interval) -> (i64, Candle) {
+        (self.bucket, Candle {
+            time: interval.bucket_start(self.bucket),
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            volume: self.volume,
+            turnover: self.turnover
+        })
+    }
+}
+
+pub fn get_candles(
+    stock_id: web::Path<u64>,
+    candles: web::Query<CandlesQueryModel>,
+    _: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let stock_id = stock_id.into_inner();
+    let candles = candles.into_inner();
+
+    web::block(
+        move || {
+            get_candles_query(stock_id, candles, pool)
+        }
+    ).then(
+        move |res: Result<Vec<Candle>, BlockingError<EngineError>>|
+            match res {
+                Ok(m) => Ok(HttpResponse::Ok().json(m)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+// 查询的 K 线周期和序号范围 [first, last)
+pub fn candle_range(candles: &CandlesQueryModel) -> Result<(Interval, i64, i64), EngineError> {
+    let interval = Interval::from_name(&candles.interval)
+        .ok_or_else(|| EngineError::BadRequest(format!("不支持的 K 线周期 {}，可选 1m、5m、15m、1h、1d、1w。", candles.interval)))?;
+
+    let to = candles.to.unwrap_or(chrono::Utc::now().naive_utc());
+    // 结束时刻所在的 K 线不完整时也返回
+    let last = interval.bucket(to) + if interval.bucket_start(interval.bucket(to)) < to { 1 } else { 0 };
+    let first = match candles.from {
+        Some(from) => interval.bucket(from),
+        None => last - DEFAULT_CANDLES
+    };
+    if first >= last {
+        return Err(EngineError::BadRequest(format!("K 线的开始时间必须早于结束时间！")));
+    }
+    if last - first > MAX_CANDLES {
+        return Err(EngineError::BadRequest(format!("一次最多查询 {} 根 K 线，请缩小时间范围或使用更长的周期。", MAX_CANDLES)));
+    }
+
+    Ok((interval, first, last))
+}
+
+// 从预先汇总的 candles 表读取，没有成交的 K 线用前一根的收盘价补齐
+fn get_candles_query(stock_id: u64, candles: CandlesQueryModel, pool: web::Data<Pool>) -> Result<Vec<Candle>, EngineError> {
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    let (interval, first, last) = candle_range(&candles)?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    load_candles(stock_id, interval, first, last, conn)
+}
+
+// 读取序号范围 [first, last) 的 K 线并补齐
+pub fn load_candles(stock_id: i64, interval: Interval, first: i64, last: i64, conn: &PgConnection) -> Result<Vec<Candle>, EngineError> {
+    let query = diesel::sql_query(include_str!("candles.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Varchar, _>(interval.as_str())
+                    .bind::<sql_types::Int8, _>(first)
+                    .bind::<sql_types::Int8, _>(last);
+
+    debug!("Get candles SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let rows = query.load::<CandleRowModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    let query = diesel::sql_query(include_str!("prevclose.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Varchar, _>(interval.as_str())
+                    .bind::<sql_types::Int8, _>(first);
+
+    debug!("Get previous close SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let prev_close = query.get_result::<PriceModel>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?
+        .and_then(|model| model.price);
+
+    let rows = rows.into_iter()
+        .map(|row| row.into_candle(interval))
+        .collect();
+
+    Ok(fill_gaps(rows, first, last, interval, prev_close))
+}
+
+// 一笔撮合成交后更新各周期的 K 线，与插入成交记录在同一事务中
+pub fn update_candles(stock_id: i64, price: Price, amount: i64, traded_at: chrono::NaiveDateTime, conn: &PgConnection) -> Result<(), EngineError> {
+    let periods: Vec<&str> = Interval::ALL.iter().map(|interval| interval.as_str()).collect();
+    let buckets: Vec<i64> = Interval::ALL.iter().map(|interval| interval.bucket(traded_at)).collect();
+
+    let query = diesel::sql_query(include_str!("updatecandles.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Array<sql_types::Varchar>, _>(&periods)
+                    .bind::<sql_types::Array<sql_types::BigInt>, _>(&buckets)
+                    .bind::<sql_types::Int4, _>(price)
+                    .bind::<sql_types::Int8, _>(amount)
+                    .bind::<sql_types::Int8, _>(price.value(amount));
+
+    debug!("Update candles SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库更新 K 线错误：{}", db_err))
+        })?;
+
+    Ok(())
+}
+
+// 从成交记录重建 K 线，stock_id 为空时重建全部股票；拆股或合股后价格口径改变，也需要重建。返回 K 线的根数
+pub fn rebuild_candles(stock_id: Option<i64>, conn: &PgConnection) -> Result<usize, EngineError> {
+    use crate::schema::candles::dsl as cnddsl;
+
+    let periods: Vec<&str> = Interval::ALL.iter().map(|interval| interval.as_str()).collect();
+    let seconds: Vec<i64> = Interval::ALL.iter().map(|interval| interval.seconds()).collect();
+    let origins: Vec<i64> = Interval::ALL.iter().map(|interval| interval.origin()).collect();
+
+    conn.transaction(|| {
+        // 保证原子性
+        let deleted = match stock_id {
+            Some(stock_id) => diesel::delete(cnddsl::candles.filter(cnddsl::stock_id.eq(stock_id))).execute(conn),
+            None => diesel::delete(cnddsl::candles).execute(conn)
+        }.map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库删除 K 线错误：{}", db_err))
+        })?;
+
+        debug!("Deleted {} candles", deleted);
+
+        let query = diesel::sql_query(include_str!("rebuildcandles.sql"))
+                        .bind::<sql_types::Nullable<sql_types::BigInt>, _>(stock_id)
+                        .bind::<sql_types::Array<sql_types::Varchar>, _>(&periods)
+                        .bind::<sql_types::Array<sql_types::BigInt>, _>(&seconds)
+                        .bind::<sql_types::Array<sql_types::BigInt>, _>(&origins);
+
+        debug!("Rebuild candles SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        query.execute(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库重建 K 线错误：{}", db_err))
+            })
+    })
+}
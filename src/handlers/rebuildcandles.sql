@@ -0,0 +1,32 @@
+INSERT INTO candles (stock_id, period, bucket, open, high, low, close, volume, turnover)
+SELECT
+    stock_id,
+    period,
+    bucket,
+    (ARRAY_AGG(price ORDER BY created_at, id))[1],
+    MAX(price),
+    MIN(price),
+    (ARRAY_AGG(price ORDER BY created_at DESC, id DESC))[1],
+    SUM(volume)::BIGINT,
+    SUM(turnover)::BIGINT
+FROM (
+    SELECT
+        d.id,
+        d.stock_id,
+        d.created_at,
+        p.period,
+        FLOOR((EXTRACT(EPOCH FROM d.created_at) - p.origin) / p.seconds)::BIGINT AS bucket,
+        ROUND(d.price * d.adjustment)::INTEGER AS price,
+        ROUND(d.amount / d.adjustment)::BIGINT AS volume,
+        d.price::BIGINT * d.amount AS turnover
+    FROM (
+        SELECT
+            deals.*,
+            split_adjustment(deals.stock_id, deals.created_at) AS adjustment
+        FROM deals
+        WHERE deals.sell_user_id IS NOT NULL
+            AND ($1::BIGINT IS NULL OR deals.stock_id = $1)
+    ) AS d
+    CROSS JOIN unnest($2::VARCHAR[], $3::BIGINT[], $4::BIGINT[]) AS p(period, seconds, origin)
+) AS t
+GROUP BY stock_id, period, bucket;
@@ -4,6 +4,7 @@ use actix_web::{
 use actix_web::error::BlockingError;
 use actix_identity::Identity;
 use crate::models::User;
+use crate::money::{Money, DEFAULT_CURRENCY};
 
 use std::convert::TryFrom;
 use std::convert::TryInto;
@@ -18,23 +19,27 @@ use diesel::prelude::*;
 use std::str::FromStr;
 
 use super::users::{RememberUserModel};
+use super::wallets::{add_balance, check_currency};
+use super::notify::UserFeed;
 
 
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct RechargeModel {
     pub cash: u64,
+    pub currency: Option<String>,   // 默认为人民币
 }
 
 pub fn recharge(
     recharge: web::Json<RechargeModel>,
     curr_user: RememberUserModel,
-    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    notify: web::Data<UserFeed>
 ) -> impl Future<Item = HttpResponse, Error = EngineError> {
    
     web::block(
         move || {
-            recharge_query(recharge.into_inner(), curr_user, pool)
+            recharge_query(recharge.into_inner(), curr_user, pool, notify)
         }
     ).then(
         move |res: Result<(), BlockingError<EngineError>>|
@@ -48,26 +53,20 @@ pub fn recharge(
     )
 }
 
-fn recharge_query(recharge: RechargeModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<(), EngineError> {
-    use crate::schema::users::dsl::*;
-
-    let recharge_cash = i64::try_from(recharge.cash).map_err(|try_err| EngineError::InternalError(format!("输入的整数无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+fn recharge_query(recharge: RechargeModel, user: RememberUserModel, pool: web::Data<Pool>, notify: web::Data<UserFeed>) -> Result<(), EngineError> {
+    let recharge_cash = i64::try_from(recharge.cash).map(Money).map_err(|try_err| EngineError::InternalError(format!("输入的整数无法安全转为 64 字节有符号整数：{}。", try_err)))?;
 
     // 取出数据库连接
     let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
 
-    let query = diesel::update(&user)
-                    .set(balance.eq(balance + recharge_cash));
+    let currency = recharge.currency.unwrap_or(DEFAULT_CURRENCY.to_owned());
+    check_currency(&currency, conn)?;
+
+    add_balance(user.id, &currency, recharge_cash, conn)?;
 
-    debug!("Recharege SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+    if let Err(err) = notify.publish_account(user.id, None, conn) {
+        error!("Publishing user events failed: {}", err);
+    }
 
-    query.get_result::<User>(conn)
-        .optional()
-        .map_err(|db_err| {
-            debug!("Database query error: {}", db_err);
-            EngineError::InternalError(format!("数据库更新用户余额错误：{}", db_err))
-        })?
-        .ok_or_else(|| EngineError::InternalError(format!("未找到当前登录的用户")))?;
-    
     Ok(())
 }
@@ -0,0 +1,40 @@
+WITH due AS (
+	DELETE FROM pending_settlements
+	WHERE
+		pending_settlements.settle_at <= $1
+	RETURNING user_id, stock_id, amount, currency
+), cash AS (
+	INSERT INTO user_wallets (user_id, currency, balance, updated_at)
+	SELECT
+		user_id,
+		currency,
+		SUM(amount),
+		$1
+	FROM due
+	WHERE stock_id IS NULL
+	GROUP BY user_id, currency
+	ON CONFLICT (user_id, currency) DO UPDATE
+	SET
+		balance = user_wallets.balance + EXCLUDED.balance,
+		updated_at = EXCLUDED.updated_at
+	RETURNING user_id
+), hold AS (
+	INSERT INTO user_hold_stock (user_id, stock_id, hold, updated_at)
+	SELECT
+		user_id,
+		stock_id,
+		SUM(amount),
+		$1
+	FROM due
+	WHERE stock_id IS NOT NULL
+	GROUP BY user_id, stock_id
+	ON CONFLICT (user_id, stock_id) DO UPDATE
+	SET
+		hold = user_hold_stock.hold + EXCLUDED.hold,
+		updated_at = EXCLUDED.updated_at
+	RETURNING user_id
+)
+SELECT DISTINCT
+	user_id,
+	stock_id
+FROM due;
@@ -0,0 +1,36 @@
+WITH due AS (
+	DELETE FROM pending_settlements
+	WHERE
+		pending_settlements.user_id = $1
+			AND
+		pending_settlements.settle_at <= $2
+	RETURNING stock_id, amount, currency
+), cash AS (
+	INSERT INTO user_wallets (user_id, currency, balance, updated_at)
+	SELECT
+		$1,
+		currency,
+		SUM(amount),
+		$2
+	FROM due
+	WHERE stock_id IS NULL
+	GROUP BY currency
+	ON CONFLICT (user_id, currency) DO UPDATE
+	SET
+		balance = user_wallets.balance + EXCLUDED.balance,
+		updated_at = EXCLUDED.updated_at
+	RETURNING user_id
+)
+INSERT INTO user_hold_stock (user_id, stock_id, hold, updated_at)
+SELECT
+	$1,
+	stock_id,
+	SUM(amount),
+	$2
+FROM due
+WHERE stock_id IS NOT NULL
+GROUP BY stock_id
+ON CONFLICT (user_id, stock_id) DO UPDATE
+SET
+	hold = user_hold_stock.hold + EXCLUDED.hold,
+	updated_at = EXCLUDED.updated_at;
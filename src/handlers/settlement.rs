@@ -0,0 +1,234 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::shorts::accrue_all_borrow_fees;
+use super::notify::UserFeed;
+use super::PagingModel;
+
+use crate::schema::*;
+use diesel::sql_types;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/settlements")
+        .service(
+            web::resource("/my/")
+                .route(web::get().to_async(get_my_pending))     // 查询自己尚未交收的股票和现金
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+#[derive(Insertable, Debug)]
+#[table_name="pending_settlements"]
+pub struct NewPendingSettlement {
+    pub user_id: i64,
+    pub stock_id: Option<i64>,
+    pub amount: i64,
+    pub settle_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub currency: Option<String>    // 现金的币种，股票时为 None
+}
+
+// 记录一笔待交收的股票（stock_id 为 NULL 时为现金）
+pub fn add_pending(pending: NewPendingSettlement, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::pending_settlements::dsl as pnddsl;
+
+    let query = diesel::insert_into(pnddsl::pending_settlements)
+                    .values(&pending);
+
+    debug!("New pending settlement SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库插入待交收记录错误：{}", db_err))
+        })?;
+
+    Ok(())
+}
+
+// 把该用户已到交收时间的股票、现金转入可用的持有量和余额
+pub fn settle_due(user_id: i64, conn: &PgConnection) -> Result<(), EngineError> {
+    let query = diesel::sql_query(include_str!("settledue.sql"))
+                    .bind::<sql_types::BigInt, _>(user_id)
+                    .bind::<sql_types::Timestamp, _>(chrono::Utc::now().naive_utc());
+
+    debug!("Settle due SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库交收错误：{}", db_err))
+        })?;
+
+    Ok(())
+}
+
+// 把所有用户已到交收时间的股票、现金转入可用的持有量和余额，并计提借券费用
+pub fn process_settlements(notify: &UserFeed, conn: &PgConnection) -> Result<(), EngineError> {
+    let (settled, lenders) = conn.transaction::<_, EngineError, _>(|| {
+        // 保证原子性
+        let query = diesel::sql_query(include_str!("settlealldue.sql"))
+                        .bind::<sql_types::Timestamp, _>(chrono::Utc::now().naive_utc());
+
+        debug!("Settle all due SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let settled = query.load::<SettledAccount>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库交收错误：{}", db_err))
+            })?;
+
+        let lenders = accrue_all_borrow_fees(conn)?;
+        Ok((settled, lenders))
+    })?;
+
+    // 提交后推送交收到账和收到借券费用的用户的账户变化，推送失败不影响交收
+    for account in &settled {
+        if let Err(err) = notify.publish_account(account.user_id, account.stock_id, conn) {
+            error!("Publishing user events failed: {}", err);
+        }
+    }
+    for lender_id in lenders {
+        if let Err(err) = notify.publish_account(lender_id, None, conn) {
+            error!("Publishing user events failed: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(QueryableByName, Debug)]
+struct SettledAccount {
+    #[sql_type = "sql_types::BigInt"]
+    user_id: i64,
+    #[sql_type = "sql_types::Nullable<sql_types::BigInt>"]
+    stock_id: Option<i64>
+}
+
+// 定期交收并计提借券费用，在单独的线程中运行；查询接口只读，看到的是最近一次处理后的结果
+pub fn run_settlement_worker(pool: Pool, notify: UserFeed, interval: std::time::Duration) {
+    std::thread::spawn(move || {
+        loop {
+            match pool.get() {
+                Ok(conn) => {
+                    if let Err(err) = process_settlements(&notify, &conn) {
+                        error!("Processing settlements failed: {}", err);
+                    }
+                },
+                Err(pool_err) => error!("Processing settlements failed, cannot get database connection: {}", pool_err)
+            }
+            std::thread::sleep(interval);
+        }
+    });
+}
+
+// 该用户某只股票尚未交收的数量，以及最早的交收时间
+pub fn pending_stock(user_id: i64, stock_id: i64, conn: &PgConnection) -> Result<(i64, Option<chrono::NaiveDateTime>), EngineError> {
+    use crate::schema::pending_settlements::dsl as pnddsl;
+
+    let query = pnddsl::pending_settlements
+                    .filter(
+                        pnddsl::user_id.eq(user_id).and(
+                            pnddsl::stock_id.eq(stock_id)
+                        )
+                    )
+                    .select((pnddsl::amount, pnddsl::settle_at));
+
+    debug!("Pending stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let pendings = query.get_results::<(i64, chrono::NaiveDateTime)>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    Ok((
+        pendings.iter().map(|(amount, _)| amount).sum(),
+        pendings.iter().map(|(_, settle_at)| *settle_at).min()
+    ))
+}
+
+//////////////////
+#[derive(Queryable, Serialize)]
+pub struct PendingSettlementModel {
+    pub id: i64,
+    pub stock_id: Option<i64>,  // 当是 NULL 时，表示现金
+    pub stock_name: Option<String>,
+    pub currency: Option<String>,   // 现金的币种
+    pub amount: i64,
+    pub settle_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime
+}
+
+pub fn get_my_pending(
+    paging: web::Query<PagingModel>,
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_my_pending_query(paging, user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<PendingSettlementModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(pendings) => Ok(HttpResponse::Ok().json(pendings)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_pending_query(paging: PagingModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<PendingSettlementModel>, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::pending_settlements::dsl as pnddsl;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = pnddsl::pending_settlements
+                    .left_join(stkdsl::stocks)
+                    .filter(
+                        pnddsl::user_id.eq(user.id)
+                    )
+                    .order(pnddsl::settle_at.asc())
+                    .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .select(
+                        (
+                            pnddsl::id,
+                            pnddsl::stock_id,
+                            stkdsl::name.nullable(),
+                            pnddsl::currency,
+                            pnddsl::amount,
+                            pnddsl::settle_at,
+                            pnddsl::created_at
+                        )
+                    );
+
+    debug!("Get my pending settlements SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<PendingSettlementModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
@@ -0,0 +1,789 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+use crate::models::{LendingPool, StockLoan};
+use crate::money::{Money, Price};
+use crate::shorting::{ShortSelling, accrued_days, borrow_fee};
+use crate::margin::MarginSettings;
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::orders::{OrderResult, UserStockRel};
+use super::settlement::settle_due;
+use super::dividends::record_dividends;
+use super::ipo::check_lockup;
+use super::wallets::{add_balance, check_balance};
+use super::margin::check_margin;
+use super::PagingModel;
+
+use crate::schema::*;
+use diesel::sql_types;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/shorts")
+        .service(
+            web::resource("/lending/")
+                .route(web::post().to_async(lend))      // 把持有的股票放入借券池
+                .route(web::delete().to_async(withdraw))      // 从借券池中取回未借出的股票
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/lending/my/")
+                .route(web::get().to_async(get_my_lending))     // 查询自己放入借券池的股票
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/loans/my/")
+                .route(web::get().to_async(get_my_loans))     // 查询自己的卖空仓位（尚未归还的借券）
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/loans/{id}/return")
+                .route(web::post().to_async(return_loan))     // 用持有的股票归还借券
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+// 卖空时从借券池借入股票，并冻结现金担保。借入的股票直接作为卖出委托冻结的股票
+pub fn borrow_shares(user_id: i64, stock_id: i64, amount: i64, price: Price, currency: &str, shorting: &ShortSelling, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::lending_pool::dsl as lnddsl;
+    use crate::schema::stock_loans::dsl as loandsl;
+
+    // 先放入借券池的先借出，不能借自己的股票
+    let query = lnddsl::lending_pool
+                    .filter(
+                        lnddsl::stock_id.eq(stock_id).and(
+                            lnddsl::user_id.ne(user_id)
+                        ).and(
+                            lnddsl::available.gt(0)
+                        )
+                    )
+                    .order(lnddsl::updated_at.asc())
+                    .for_update();
+
+    debug!("Borrow shares lending pool SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let pools = query.get_results::<LendingPool>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    let available: i64 = pools.iter().map(|pool| pool.available).sum();
+    if available < amount {
+        let err_msg = format!("借券池中可借的股票不足，当前只有 {} 股可借。", available);
+        return Err(EngineError::Insufficient(
+            OrderResult {
+                succeed: false,
+                message: Some(err_msg.clone()),
+                error: Some(err_msg),
+                deal_amount: None,
+                lack: Some(amount - available)
+            }
+        ));
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut remaining = amount;
+    let mut total_collateral = Money::zero();
+    for mut pool in pools {
+        if remaining == 0 {
+            break;
+        }
+        let take = std::cmp::min(pool.available, remaining);
+        remaining -= take;
+
+        pool.available -= take;
+        pool.lent += take;
+        pool.updated_at = now;
+        pool.save_changes::<LendingPool>(conn).map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库重设借券池错误：{}", db_err))
+            })?;
+
+        let collateral = shorting.collateral(price.value(take));
+        total_collateral += collateral;
+
+        diesel::insert_into(loandsl::stock_loans)
+            .values((
+                loandsl::borrower_id.eq(user_id),
+                loandsl::lender_id.eq(pool.user_id),
+                loandsl::stock_id.eq(stock_id),
+                loandsl::amount.eq(take),
+                loandsl::price.eq(price),
+                loandsl::collateral.eq(collateral),
+                loandsl::currency.eq(currency),
+                loandsl::fee_rate.eq(shorting.borrow_fee_rate),
+                loandsl::fee_accrued_at.eq(now),
+                loandsl::created_at.eq(now)
+            ))
+            .execute(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库插入借券记录错误：{}", db_err))
+            })?;
+    }
+
+    let balance_after = add_balance(user_id, currency, -total_collateral, conn)?;
+    check_balance(balance_after, currency, "提供卖空的现金担保")?;
+
+    Ok(())
+}
+
+// 计提与该用户有关（借入或借出）的借券费用：从借券的现金担保中扣除，付给出借人
+pub fn accrue_borrow_fees(user_id: i64, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::stock_loans::dsl as loandsl;
+
+    let now = chrono::Utc::now().naive_utc();
+    let today = now.date().and_hms(0, 0, 0);
+
+    let query = loandsl::stock_loans
+                    .filter(
+                        loandsl::borrower_id.eq(user_id).or(
+                            loandsl::lender_id.eq(user_id)
+                        )
+                    )
+                    .filter(
+                        loandsl::closed_at.is_null().and(
+                            loandsl::fee_accrued_at.lt(today)
+                        )
+                    )
+                    .for_update();
+
+    debug!("Accrue borrow fees SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let loans = query.get_results::<StockLoan>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    accrue_loans(loans, now, conn)?;
+    Ok(())
+}
+
+// 计提所有未归还借券的借券费用，返回收到费用的出借人
+pub fn accrue_all_borrow_fees(conn: &PgConnection) -> Result<Vec<i64>, EngineError> {
+    use crate::schema::stock_loans::dsl as loandsl;
+
+    let now = chrono::Utc::now().naive_utc();
+    let today = now.date().and_hms(0, 0, 0);
+
+    let query = loandsl::stock_loans
+                    .filter(
+                        loandsl::closed_at.is_null().and(
+                            loandsl::fee_accrued_at.lt(today)
+                        )
+                    )
+                    .order(loandsl::id.asc())
+                    .for_update();
+
+    debug!("Accrue all borrow fees SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let loans = query.get_results::<StockLoan>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    accrue_loans(loans, now, conn)
+}
+
+// 计提这些借券截至今天的费用，返回收到费用的出借人
+fn accrue_loans(loans: Vec<StockLoan>, now: chrono::NaiveDateTime, conn: &PgConnection) -> Result<Vec<i64>, EngineError> {
+    let today = now.date().and_hms(0, 0, 0);
+
+    let mut lenders = Vec::new();
+    for mut loan in loans {
+        let days = accrued_days(loan.fee_accrued_at, now);
+        // 担保不足以支付时，只付剩余的担保
+        let fee = std::cmp::min(borrow_fee(loan.price.value(loan.amount), loan.fee_rate, days), loan.collateral);
+
+        loan.collateral -= fee;
+        loan.fees_paid += fee;
+        loan.fee_accrued_at = today;
+        loan.save_changes::<StockLoan>(conn).map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库重设借券记录错误：{}", db_err))
+            })?;
+
+        if fee > Money::zero() {
+            add_balance(loan.lender_id, &loan.currency, fee, conn)?;
+            if !lenders.contains(&loan.lender_id) {
+                lenders.push(loan.lender_id);
+            }
+        }
+    }
+
+    Ok(lenders)
+}
+
+// 归还一笔借券中的 amount 股，股票回到出借人的借券池，按比例释放现金担保
+fn repay_loan(loan: &mut StockLoan, amount: i64, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::lending_pool::dsl as lnddsl;
+
+    let now = chrono::Utc::now().naive_utc();
+    let release = if amount == loan.amount {
+        loan.collateral
+    } else {
+        loan.collateral.ratio_floor(amount, loan.amount)
+    };
+
+    loan.amount -= amount;
+    loan.collateral -= release;
+    if loan.amount == 0 {
+        loan.closed_at = Some(now);
+    }
+    loan.save_changes::<StockLoan>(conn).map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库重设借券记录错误：{}", db_err))
+        })?;
+
+    let query = diesel::update(lnddsl::lending_pool.find((loan.lender_id, loan.stock_id)))
+                    .set((
+                        lnddsl::available.eq(lnddsl::available + amount),
+                        lnddsl::lent.eq(lnddsl::lent - amount),
+                        lnddsl::updated_at.eq(now)
+                    ));
+
+    debug!("Repay loan lending pool SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库重设借券池错误：{}", db_err))
+        })?;
+
+    if release > Money::zero() {
+        add_balance(loan.borrower_id, &loan.currency, release, conn)?;
+    }
+
+    Ok(())
+}
+
+// 买入平仓：用买入的 amount 股按借入的先后归还该股票的借券，返回实际用于归还的数量
+pub fn cover_loans(user_id: i64, stock_id: i64, amount: i64, conn: &PgConnection) -> Result<i64, EngineError> {
+    use crate::schema::stock_loans::dsl as loandsl;
+
+    accrue_borrow_fees(user_id, conn)?;
+
+    let query = loandsl::stock_loans
+                    .filter(
+                        loandsl::borrower_id.eq(user_id).and(
+                            loandsl::stock_id.eq(stock_id)
+                        ).and(
+                            loandsl::closed_at.is_null()
+                        )
+                    )
+                    .order(loandsl::created_at.asc())
+                    .for_update();
+
+    debug!("Cover loans SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    let loans = query.get_results::<StockLoan>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?;
+
+    let mut remaining = amount;
+    for mut loan in loans {
+        if remaining == 0 {
+            break;
+        }
+        let take = std::cmp::min(loan.amount, remaining);
+        remaining -= take;
+        repay_loan(&mut loan, take, conn)?;
+    }
+
+    Ok(amount - remaining)
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct LendingModel {
+    pub stock_id: u64,
+    pub amount: u64,
+}
+
+pub fn lend(
+    lending: web::Json<LendingModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    margin: web::Data<MarginSettings>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            lend_query(lending.into_inner(), curr_user, pool, margin)
+        }
+    ).then(
+        move |res: Result<(), BlockingError<EngineError>>|
+            match res {
+                Ok(_) => Ok(HttpResponse::Ok().finish()),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn lend_query(lending: LendingModel, user: RememberUserModel, pool: web::Data<Pool>, margin: web::Data<MarginSettings>) -> Result<(), EngineError> {
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::lending_pool::dsl as lnddsl;
+    use crate::schema::stocks::dsl as stkdsl;
+
+    let stock_id = i64::try_from(lending.stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+    let amount = i64::try_from(lending.amount).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    if amount == 0 {
+        return Err(EngineError::BadRequest(format!("出借数量必须大于 0。")));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    conn.transaction(|| {
+        // 保证原子性
+        check_lockup(stock_id, user.id, conn)?;
+        record_dividends(conn)?;
+        settle_due(user.id, conn)?;
+
+        // 扣持有的股票
+        let query = diesel::update(reldsl::user_hold_stock.find(
+                        (user.id, stock_id)
+                    ))
+                    .set((
+                        reldsl::hold.eq(reldsl::hold - amount),
+                        reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
+                    ));
+
+        debug!("Lend stock debit SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let hold_after = query.get_result::<UserStockRel>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库更新股票持有量错误：{}", db_err))
+            })?
+            .map(|rel| rel.hold)
+            .unwrap_or(-amount);
+
+        if hold_after < 0 {
+            let err_msg = format!("股票持有量不足，你还需要 {} 股来放入借券池。", -hold_after);
+            return Err(EngineError::Insufficient(
+                OrderResult {
+                    succeed: false,
+                    message: Some(err_msg.clone()),
+                    error: Some(err_msg),
+                    deal_amount: None,
+                    lack: Some(-hold_after)
+                }
+            ));
+        }
+
+        // 放入借券池的股票不再计入持仓市值，不能是融资借款的担保
+        let query = stkdsl::stocks
+                        .find(stock_id)
+                        .select(stkdsl::currency);
+
+        debug!("Lend stock currency SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let currency = query.get_result::<String>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+        check_margin(user.id, &currency, &margin, "放入借券池", conn)?;
+
+        // 放入借券池
+        let now = chrono::Utc::now().naive_utc();
+        diesel::insert_into(lnddsl::lending_pool)
+            .values(
+                LendingPool {
+                    user_id: user.id,
+                    stock_id,
+                    available: amount,
+                    lent: 0,
+                    updated_at: now
+                }
+            )
+            .on_conflict((lnddsl::user_id, lnddsl::stock_id))
+            .do_update()
+            .set((
+                lnddsl::available.eq(lnddsl::available + amount),
+                lnddsl::updated_at.eq(now)
+            ))
+            .execute(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库重设借券池错误：{}", db_err))
+            })?;
+
+        Ok(())
+    })
+}
+
+//////////////////
+pub fn withdraw(
+    lending: web::Json<LendingModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            withdraw_query(lending.into_inner(), curr_user, pool)
+        }
+    ).then(
+        move |res: Result<(), BlockingError<EngineError>>|
+            match res {
+                Ok(_) => Ok(HttpResponse::Ok().finish()),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn withdraw_query(lending: LendingModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<(), EngineError> {
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::lending_pool::dsl as lnddsl;
+
+    let stock_id = i64::try_from(lending.stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+    let amount = i64::try_from(lending.amount).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    if amount == 0 {
+        return Err(EngineError::BadRequest(format!("取回数量必须大于 0。")));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    conn.transaction(|| {
+        // 保证原子性
+        // 已借出的股票要等借入方归还后才能取回
+        let query = diesel::update(lnddsl::lending_pool.find(
+                        (user.id, stock_id)
+                    ))
+                    .set((
+                        lnddsl::available.eq(lnddsl::available - amount),
+                        lnddsl::updated_at.eq(chrono::Utc::now().naive_utc())
+                    ));
+
+        debug!("Withdraw lending SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let available_after = query.get_result::<LendingPool>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库重设借券池错误：{}", db_err))
+            })?
+            .map(|pool| pool.available)
+            .unwrap_or(-amount);
+
+        if available_after < 0 {
+            let err_msg = format!("借券池中未借出的股票不足，你还需要 {} 股才能取回这么多。", -available_after);
+            return Err(EngineError::Insufficient(
+                OrderResult {
+                    succeed: false,
+                    message: Some(err_msg.clone()),
+                    error: Some(err_msg),
+                    deal_amount: None,
+                    lack: Some(-available_after)
+                }
+            ));
+        }
+
+        // 回到持有的股票
+        diesel::insert_into(reldsl::user_hold_stock)
+            .values(
+                UserStockRel {
+                    user_id: user.id,
+                    stock_id,
+                    hold: amount,
+                    updated_at: chrono::Utc::now().naive_utc()
+                }
+            )
+            .on_conflict((reldsl::user_id, reldsl::stock_id))
+            .do_update()
+            .set((
+                reldsl::hold.eq(reldsl::hold + amount),
+                reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
+            ))
+            .execute(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库更新股票持有量错误：{}", db_err))
+            })?;
+
+        Ok(())
+    })
+}
+
+//////////////////
+#[derive(Queryable, Serialize)]
+pub struct LendingPoolModel {
+    pub stock_id: i64,
+    pub stock_name: String,
+    pub available: i64,
+    pub lent: i64,
+    pub updated_at: chrono::NaiveDateTime
+}
+
+pub fn get_my_lending(
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            get_my_lending_query(user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<LendingPoolModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(pools) => Ok(HttpResponse::Ok().json(pools)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_lending_query(user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<LendingPoolModel>, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::lending_pool::dsl as lnddsl;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = lnddsl::lending_pool
+                    .inner_join(stkdsl::stocks)
+                    .filter(
+                        lnddsl::user_id.eq(user.id)
+                    )
+                    .order(lnddsl::updated_at.desc())
+                    .select(
+                        (
+                            lnddsl::stock_id,
+                            stkdsl::name,
+                            lnddsl::available,
+                            lnddsl::lent,
+                            lnddsl::updated_at
+                        )
+                    );
+
+    debug!("Get my lending SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<LendingPoolModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+//////////////////
+#[derive(Queryable, Serialize)]
+pub struct LoanModel {
+    pub id: i64,
+    pub lender_id: i64,
+    pub stock_id: i64,
+    pub stock_name: String,
+    pub amount: i64,        // 尚未归还的数量，即卖空仓位
+    pub price: Price,
+    pub collateral: Money,
+    pub currency: String,
+    pub fee_rate: i64,
+    pub fees_paid: Money,
+    pub created_at: chrono::NaiveDateTime
+}
+
+pub fn get_my_loans(
+    paging: web::Query<PagingModel>,
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_my_loans_query(paging, user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<LoanModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(loans) => Ok(HttpResponse::Ok().json(loans)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_loans_query(paging: PagingModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<LoanModel>, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::stock_loans::dsl as loandsl;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = loandsl::stock_loans
+                    .inner_join(stkdsl::stocks)
+                    .filter(
+                        loandsl::borrower_id.eq(user.id).and(
+                            loandsl::closed_at.is_null()
+                        )
+                    )
+                    .order(loandsl::created_at.asc())
+                    .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .select(
+                        (
+                            loandsl::id,
+                            loandsl::lender_id,
+                            loandsl::stock_id,
+                            stkdsl::name,
+                            loandsl::amount,
+                            loandsl::price,
+                            loandsl::collateral,
+                            loandsl::currency,
+                            loandsl::fee_rate,
+                            loandsl::fees_paid,
+                            loandsl::created_at
+                        )
+                    );
+
+    debug!("Get my loans SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<LoanModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReturnLoanModel {
+    pub amount: Option<u64>,    // 默认全部归还
+}
+
+pub fn return_loan(
+    loan_id: web::Path<u64>,
+    return_model: web::Json<ReturnLoanModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            return_loan_query(loan_id.into_inner(), return_model.into_inner(), curr_user, pool)
+        }
+    ).then(
+        move |res: Result<(), BlockingError<EngineError>>|
+            match res {
+                Ok(_) => Ok(HttpResponse::Ok().finish()),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn return_loan_query(loan_id: u64, return_model: ReturnLoanModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<(), EngineError> {
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::stock_loans::dsl as loandsl;
+
+    let loan_id = i64::try_from(loan_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    conn.transaction(|| {
+        // 保证原子性
+        record_dividends(conn)?;
+        settle_due(user.id, conn)?;
+        accrue_borrow_fees(user.id, conn)?;
+
+        let query = loandsl::stock_loans
+                        .filter(
+                            loandsl::id.eq(loan_id).and(
+                                loandsl::borrower_id.eq(user.id)
+                            ).and(
+                                loandsl::closed_at.is_null()
+                            )
+                        )
+                        .for_update();
+
+        debug!("Return loan SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let mut loan = query.get_result::<StockLoan>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::NotFound(format!("未找到请求的借券，或已全部归还。")))?;
+
+        let amount = match return_model.amount {
+            Some(amount) => i64::try_from(amount).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?,
+            None => loan.amount
+        };
+
+        if amount == 0 || amount > loan.amount {
+            return Err(EngineError::BadRequest(format!("归还数量必须在 1 到 {} 股之间。", loan.amount)));
+        }
+
+        // 扣持有的股票
+        let query = diesel::update(reldsl::user_hold_stock.find(
+                        (user.id, loan.stock_id)
+                    ))
+                    .set((
+                        reldsl::hold.eq(reldsl::hold - amount),
+                        reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
+                    ));
+
+        debug!("Return loan stock debit SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let hold_after = query.get_result::<UserStockRel>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库更新股票持有量错误：{}", db_err))
+            })?
+            .map(|rel| rel.hold)
+            .unwrap_or(-amount);
+
+        if hold_after < 0 {
+            let err_msg = format!("股票持有量不足，你还需要 {} 股来归还借券。", -hold_after);
+            return Err(EngineError::Insufficient(
+                OrderResult {
+                    succeed: false,
+                    message: Some(err_msg.clone()),
+                    error: Some(err_msg),
+                    deal_amount: None,
+                    lack: Some(-hold_after)
+                }
+            ));
+        }
+
+        repay_loan(&mut loan, amount, conn)
+    })
+}
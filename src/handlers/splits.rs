@@ -0,0 +1,564 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+use crate::models::{AskOrder, BidOrder, LendingPool, StockSplit};
+use crate::money::{Money, Price};
+use crate::splits::SplitRatio;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::orders::UserStockRel;
+use super::dividends::record_dividends;
+use super::wallets::{add_balance, check_balance};
+use super::quotation::rebuild_candles;
+use super::notify::{UserFeed, OrderUpdate, OrderStatus};
+use super::history::{OrderEventKind, save_ask, save_bid};
+use super::PagingModel;
+
+use crate::schema::*;
+use diesel::sql_types;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/splits")
+        .service(
+            web::resource("/")  // Scope 会自动加尾 /，所以 /splits 无法匹配
+                .route(web::post().to_async(split_stock))       // 发行人拆股或合股
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/stock/{id}")
+                .route(web::get().to_async(get_stock_splits))     // 查询某只股票的拆股和合股记录
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct SplitModel {
+    pub stock_id: u64,
+    pub numerator: u32,     // 每 denominator 股旧股变为 numerator 股新股
+    pub denominator: u32,
+}
+
+pub fn split_stock(
+    split: web::Json<SplitModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    notify: web::Data<UserFeed>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            split_stock_query(split.into_inner(), curr_user, pool, notify)
+        }
+    ).then(
+        move |res: Result<StockSplit, BlockingError<EngineError>>|
+            match res {
+                Ok(split) => Ok(HttpResponse::Ok().json(split)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+// 调整后的价格超出表示范围时拒绝拆股或合股
+fn adjusted_price(price: Option<Price>) -> Result<Price, EngineError> {
+    price.ok_or_else(|| EngineError::BadRequest(format!("按该比例调整后的价格超出允许的范围，不能拆股或合股。")))
+}
+
+// 调整后的股数（或零股折算的现金）超出表示范围时拒绝拆股或合股
+fn adjusted_shares<T>(shares: Option<T>) -> Result<T, EngineError> {
+    shares.ok_or_else(|| EngineError::BadRequest(format!("按该比例调整后的股数超出允许的范围，不能拆股或合股。")))
+}
+
+fn split_stock_query(split: SplitModel, user: RememberUserModel, pool: web::Data<Pool>, notify: web::Data<UserFeed>) -> Result<StockSplit, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+    use crate::schema::new_stocks::dsl as newdsl;
+    use crate::schema::deals::dsl as dldsl;
+    use crate::schema::dividends::dsl as divdsl;
+    use crate::schema::stock_loans::dsl as loandsl;
+    use crate::schema::offerings::dsl as offdsl;
+    use crate::schema::buybacks::dsl as bbdsl;
+    use crate::schema::lending_pool::dsl as lnddsl;
+    use crate::schema::pending_settlements::dsl as pnddsl;
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::user_ask_orders::dsl as askdsl;
+    use crate::schema::user_bid_orders::dsl as biddsl;
+    use crate::schema::stock_splits::dsl as spldsl;
+
+    let stock_id = i64::try_from(split.stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+    let ratio = SplitRatio::new(split.numerator as i64, split.denominator as i64)
+        .ok_or_else(|| EngineError::BadRequest(format!("拆股比例必须为正数，且不能为 1:1！")))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let (stock_split, expired) = conn.transaction(|| {
+        // 保证原子性
+        // 只有已上市股票的发行人可以拆股或合股
+        let query = stkdsl::stocks
+                        .find(stock_id)
+                        .filter(stkdsl::into_market.eq(true))
+                        .select((stkdsl::price_decimals, stkdsl::currency))
+                        .for_update();
+
+        debug!("Split stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let (price_decimals, currency) = query.get_result::<(i16, String)>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::BadRequest(format!("该股票还未上市！")))?;
+
+        let query = newdsl::new_stocks
+                        .find(stock_id)
+                        .select((newdsl::issuer_id, newdsl::offer_circ, newdsl::offer_price, newdsl::offer_unfulfilled));
+
+        debug!("Split stock new_stocks SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let (issuer_id, offer_circ, offer_price, offer_unfulfilled) = query.get_result::<(i64, i64, Price, i64)>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::InternalError(format!("该股票没有新股发行信息，请联系管理员维护！")))?;
+
+        if issuer_id != user.id {
+            return Err(EngineError::Unauthorized(format!("只有该股票的发行人可以拆股或合股。")));
+        }
+
+        // 借券和尚未登记的分红都按旧的股数约定，不能调整
+        let query = loandsl::stock_loans
+                        .filter(
+                            loandsl::stock_id.eq(stock_id).and(
+                                loandsl::closed_at.is_null()
+                            )
+                        )
+                        .count();
+
+        debug!("Split open loans SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let open_loans = query.get_result::<i64>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        if open_loans > 0 {
+            return Err(EngineError::BadRequest(format!("该股票还有未归还的借券，不能拆股或合股。")));
+        }
+
+        record_dividends(conn)?;
+
+        let query = divdsl::dividends
+                        .filter(
+                            divdsl::stock_id.eq(stock_id).and(
+                                divdsl::recorded_at.is_null()
+                            )
+                        )
+                        .count();
+
+        debug!("Split unrecorded dividends SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let unrecorded_dividends = query.get_result::<i64>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        if unrecorded_dividends > 0 {
+            return Err(EngineError::BadRequest(format!("该股票有尚未登记的分红，请在股权登记后再拆股或合股。")));
+        }
+
+        // 增发按旧的股数和价格约定，认购期内不能调整
+        let query = offdsl::offerings
+                        .filter(
+                            offdsl::stock_id.eq(stock_id).and(
+                                offdsl::ends_at.gt(chrono::Utc::now().naive_utc())
+                            ).and(
+                                offdsl::remaining.gt(0)
+                            )
+                        )
+                        .count();
+
+        debug!("Split open offerings SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let open_offerings = query.get_result::<i64>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        if open_offerings > 0 {
+            return Err(EngineError::BadRequest(format!("该股票有尚未结束的增发，请在认购期结束后再拆股或合股。")));
+        }
+
+        // 回购计划按旧的价格上限挂单，进行中不能调整
+        let query = bbdsl::buybacks
+                        .filter(
+                            bbdsl::stock_id.eq(stock_id).and(
+                                bbdsl::closed_at.is_null()
+                            )
+                        )
+                        .count();
+
+        debug!("Split active buybacks SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let active_buybacks = query.get_result::<i64>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        if active_buybacks > 0 {
+            return Err(EngineError::BadRequest(format!("该股票有进行中的回购计划，请在回购计划结束或终止后再拆股或合股。")));
+        }
+
+        // 零股按调整后的最新成交价折算现金，没有成交时按发行价
+        let query = dldsl::deals
+                        .filter(
+                            dldsl::stock_id.eq(stock_id).and(
+                                dldsl::sell_user_id.is_not_null()
+                            )
+                        )
+                        .order((dldsl::created_at.desc(), dldsl::id.desc()))
+                        .select(dldsl::price);
+
+        debug!("Split last price SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let last_price = query.first::<Price>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .unwrap_or(offer_price);
+        let reference_price = adjusted_price(ratio.price_ceil(last_price, price_decimals))?;
+        let new_offer_price = adjusted_price(ratio.price_ceil(offer_price, price_decimals))?;
+        let new_offer_circ = adjusted_shares(ratio.shares(offer_circ))?;
+        let new_offer_unfulfilled = adjusted_shares(ratio.shares(offer_unfulfilled))?;
+
+        // 每个用户调整前的总股数，以及除持有量以外已调整的股数
+        let mut old_totals: BTreeMap<i64, i64> = BTreeMap::new();
+        let mut new_assigned: BTreeMap<i64, i64> = BTreeMap::new();
+
+        // 卖出委托：冻结的股数按比例调整，价格向上取整
+        let query = biddsl::user_bid_orders
+                        .filter(
+                            biddsl::stock_id.eq(stock_id).and(
+                                biddsl::unfulfilled.gt(0)
+                            )
+                        )
+                        .for_update();
+
+        debug!("Split bid orders SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let bids = query.get_results::<BidOrder>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let bid_prices = bids.iter()
+            .map(|bid| adjusted_price(ratio.price_ceil(bid.price, price_decimals)))
+            .collect::<Result<Vec<Price>, EngineError>>()?;
+
+        // 买入委托，与卖出委托一起先检查调整后的价格
+        let query = askdsl::user_ask_orders
+                        .filter(
+                            askdsl::stock_id.eq(stock_id).and(
+                                askdsl::unfulfilled.gt(0)
+                            )
+                        )
+                        .for_update();
+
+        debug!("Split ask orders SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let asks = query.get_results::<AskOrder>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let ask_prices = asks.iter()
+            .map(|ask| adjusted_price(ratio.price_floor(ask.price, price_decimals)))
+            .collect::<Result<Vec<Price>, EngineError>>()?;
+
+        // 所有调整后的价格都在允许范围内，之后才修改委托
+        for (mut bid, price) in bids.into_iter().zip(bid_prices) {
+            let unfulfilled = adjusted_shares(ratio.shares(bid.unfulfilled))?;
+            *old_totals.entry(bid.user_id).or_insert(0) += bid.unfulfilled;
+            *new_assigned.entry(bid.user_id).or_insert(0) += unfulfilled;
+
+            bid.volume = adjusted_shares(ratio.shares(bid.volume))?;
+            bid.unfulfilled = unfulfilled;
+            bid.price = price;
+            bid.updated_at = chrono::Utc::now().naive_utc();
+            save_bid(OrderEventKind::Amended, &bid, conn)?;
+        }
+
+        // 尚未交收的股票
+        let query = pnddsl::pending_settlements
+                        .filter(pnddsl::stock_id.eq(stock_id))
+                        .select((pnddsl::id, pnddsl::user_id, pnddsl::amount))
+                        .for_update();
+
+        debug!("Split pending settlements SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let pendings = query.get_results::<(i64, i64, i64)>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        for (pending_id, user_id, amount) in pendings {
+            let new_amount = adjusted_shares(ratio.shares(amount))?;
+            *old_totals.entry(user_id).or_insert(0) += amount;
+            *new_assigned.entry(user_id).or_insert(0) += new_amount;
+
+            diesel::update(pnddsl::pending_settlements.find(pending_id))
+                .set(pnddsl::amount.eq(new_amount))
+                .execute(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库重设待交收记录错误：{}", db_err))
+                })?;
+        }
+
+        // 借券池中未借出的股票
+        let query = lnddsl::lending_pool
+                        .filter(lnddsl::stock_id.eq(stock_id))
+                        .for_update();
+
+        debug!("Split lending pool SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let lending = query.get_results::<LendingPool>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        for mut lending_pool in lending {
+            let available = adjusted_shares(ratio.shares(lending_pool.available))?;
+            *old_totals.entry(lending_pool.user_id).or_insert(0) += lending_pool.available;
+            *new_assigned.entry(lending_pool.user_id).or_insert(0) += available;
+
+            lending_pool.available = available;
+            lending_pool.updated_at = chrono::Utc::now().naive_utc();
+            lending_pool.save_changes::<LendingPool>(conn).map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库重设借券池错误：{}", db_err))
+                })?;
+        }
+
+        // 持有量
+        let query = reldsl::user_hold_stock
+                        .filter(reldsl::stock_id.eq(stock_id))
+                        .for_update();
+
+        debug!("Split holdings SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let holdings = query.get_results::<UserStockRel>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        for rel in holdings {
+            *old_totals.entry(rel.user_id).or_insert(0) += rel.hold;
+        }
+
+        // 按每个用户的总股数计算调整后的股数，各部分舍去的零股计入持有量，不足一股的部分折算为现金
+        let mut cash_paid = Money::zero();
+        for (user_id, old_total) in old_totals {
+            let hold = adjusted_shares(ratio.shares(old_total))? - new_assigned.get(&user_id).cloned().unwrap_or(0);
+
+            diesel::insert_into(reldsl::user_hold_stock)
+                .values(
+                    UserStockRel {
+                        user_id,
+                        stock_id,
+                        hold,
+                        updated_at: chrono::Utc::now().naive_utc()
+                    }
+                )
+                .on_conflict((reldsl::user_id, reldsl::stock_id))
+                .do_update()
+                .set((
+                    reldsl::hold.eq(hold),
+                    reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
+                ))
+                .execute(conn)
+                .map_err(|db_err| {
+                    debug!("Database query error: {}", db_err);
+                    EngineError::InternalError(format!("数据库更新股票持有量错误：{}", db_err))
+                })?;
+
+            let cash = adjusted_shares(ratio.fraction_cash(old_total, reference_price))?;
+            if cash > Money::zero() {
+                add_balance(user_id, &currency, cash, conn)?;
+                cash_paid += cash;
+            }
+        }
+
+        // 买入委托：数量向下取整，价格向下取整，多冻结的现金返还给买方
+        let mut expired = Vec::new();
+        for (mut ask, price) in asks.into_iter().zip(ask_prices) {
+            let unfulfilled = adjusted_shares(ratio.shares(ask.unfulfilled))?;
+            let frozen = ask.price.value(ask.unfulfilled);
+
+            let refund = if unfulfilled == 0 || price <= Price(0) {
+                // 调整后不足一股，撤销剩余部分
+                let refund = frozen + ask.fee_frozen;
+                ask.volume -= ask.unfulfilled;
+                ask.unfulfilled = 0;
+                ask.fee_frozen = Money::zero();
+                refund
+            } else {
+                ask.volume = adjusted_shares(ratio.shares(ask.volume))?;
+                ask.unfulfilled = unfulfilled;
+                ask.price = price;
+                frozen - price.value(unfulfilled)
+            };
+            ask.updated_at = chrono::Utc::now().naive_utc();
+            if ask.unfulfilled == 0 {
+                save_ask(OrderEventKind::Expired, &ask, conn)?;
+                expired.push(OrderUpdate::from_ask(&ask));
+            } else {
+                save_ask(OrderEventKind::Amended, &ask, conn)?;
+            }
+
+            if refund > Money::zero() {
+                add_balance(ask.user_id, &currency, refund, conn)?;
+            }
+        }
+
+        // 零股现金由发行人支付
+        let issuer_balance_after = add_balance(issuer_id, &currency, -cash_paid, conn)?;
+        check_balance(issuer_balance_after, &currency, "支付零股折算的现金")?;
+
+        diesel::update(newdsl::new_stocks.find(stock_id))
+            .set((
+                newdsl::offer_circ.eq(new_offer_circ),
+                newdsl::offer_price.eq(new_offer_price),
+                newdsl::offer_unfulfilled.eq(new_offer_unfulfilled)
+            ))
+            .execute(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库重设新股发行信息错误：{}", db_err))
+            })?;
+
+        let query = diesel::insert_into(spldsl::stock_splits)
+                        .values((
+                            spldsl::stock_id.eq(stock_id),
+                            spldsl::numerator.eq(ratio.numerator),
+                            spldsl::denominator.eq(ratio.denominator),
+                            spldsl::reference_price.eq(reference_price),
+                            spldsl::cash_paid.eq(cash_paid),
+                            spldsl::created_at.eq(chrono::Utc::now().naive_utc())
+                        ));
+
+        debug!("Split stock insert SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let stock_split = query.get_result::<StockSplit>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库插入拆股记录错误：{}", db_err))
+            })?;
+
+        // 历史 K 线按新的股数口径复权
+        rebuild_candles(Some(stock_id), conn)?;
+
+        Ok((stock_split, expired))
+    })?;
+
+    // 提交后推送被撤销的买入委托和返还的余额
+    for order in &expired {
+        notify.publish_order(OrderStatus::Expired, order);
+        if let Err(err) = notify.publish_account(order.user_id, Some(stock_id), conn) {
+            error!("Publishing user events failed: {}", err);
+        }
+    }
+
+    Ok(stock_split)
+}
+
+//////////////////
+#[derive(QueryableByName, Serialize)]
+pub struct StockSplitModel {
+    #[sql_type = "sql_types::BigInt"]
+    pub id: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub numerator: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub denominator: i64,
+    #[sql_type = "sql_types::Integer"]
+    pub reference_price: Price,
+    #[sql_type = "sql_types::BigInt"]
+    pub cash_paid: Money,
+    #[sql_type = "sql_types::Timestamp"]
+    pub created_at: chrono::NaiveDateTime,
+    #[sql_type = "sql_types::Double"]
+    pub adjust_factor: f64      // 此次拆股之前的价格乘以该因子，即为按当前股数复权的价格
+}
+
+pub fn get_stock_splits(
+    stock_id: web::Path<u64>,
+    paging: web::Query<PagingModel>,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_stock_splits_query(stock_id.into_inner(), paging, pool)
+        }
+    ).then(
+        move |res: Result<Vec<StockSplitModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(splits) => Ok(HttpResponse::Ok().json(splits)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_stock_splits_query(stock_id: u64, paging: PagingModel, pool: web::Data<Pool>) -> Result<Vec<StockSplitModel>, EngineError> {
+    let stock_id = i64::try_from(stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = diesel::sql_query(include_str!("stocksplits.sql"))
+                    .bind::<sql_types::BigInt, _>(stock_id)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.offset.unwrap_or(0)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.limit.unwrap_or(10)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get stock splits SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.load::<StockSplitModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
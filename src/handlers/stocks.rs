@@ -3,7 +3,10 @@ use actix_web::{
 };
 use actix_web::error::BlockingError;
 use actix_identity::Identity;
-use crate::models::Stock;
+use crate::models::{Stock, NewStock, Buyback};
+use crate::money::{Price, MONEY_SCALE, DEFAULT_PRICE_DECIMALS, DEFAULT_CURRENCY, is_valid_price_decimals};
+use crate::allocation::Allocation;
+use crate::fees::FeeSchedule;
 
 use std::convert::TryFrom;
 use std::convert::TryInto;
@@ -19,6 +22,8 @@ use diesel::prelude::*;
 use std::str::FromStr;
 
 use super::users::{RememberUserModel};
+use super::buybacks::active_buybacks;
+use super::notify::UserFeed;
 use diesel::sql_types;
 
 pub fn make_scope() -> actix_web::Scope {
@@ -67,6 +72,26 @@ pub fn make_scope() -> actix_web::Scope {
                 .route(web::get().to_async(super::quotation::get_quotation))      // 查看行情
                 .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
         )
+        .service(
+            web::resource("/{id}/book")
+                .route(web::get().to_async(super::quotation::get_order_book))      // 查看逐笔委托
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/{id}/book/history")
+                .route(web::get().to_async(super::history::get_order_book_at))      // 重建某一时刻的逐笔委托
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/{id}/candles")
+                .route(web::get().to_async(super::quotation::get_candles))      // 查看 K 线
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/{id}/indicators")
+                .route(web::get().to_async(super::indicators::get_indicators))      // 查看技术指标
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
         .service(
             web::resource("/{id}")
                 .route(web::get().to_async(get_stock))      // 获取股票
@@ -86,7 +111,17 @@ pub fn make_scope() -> actix_web::Scope {
 pub struct IPOModel {
     pub name: String,
     pub offer_circ: i64,
-    pub offer_price: i32,
+    pub offer_price: Price,
+    pub price_decimals: Option<i16>,    // 价格保留的小数位数，0 到 2，默认精确到分
+    pub currency: Option<String>,       // 报价币种，默认为人民币
+    pub subscribe_ends_at: Option<chrono::NaiveDateTime>,   // 认购截止时间，不填则先到先得
+    pub allocation: Option<Allocation>,     // 超额认购时的配售方式，默认摇号
+    pub lot_size: Option<i64>,              // 每手股数，默认 1
+    pub min_subscription: Option<i64>,      // 最低发行量，须同时设置截止时间
+    pub deadline: Option<chrono::NaiveDateTime>,    // 截止时间，到期达到最低发行量则自动上市，否则发行失败
+    pub lockup_days: Option<i32>,           // 上市后的锁定天数，锁定期内发行人不能卖出
+    pub lockup_allottees: Option<bool>,     // 锁定期是否也适用于新股认购者，默认否
+    pub auction: Option<bool>,              // 荷兰式拍卖发行，此时 offer_price 为最低价格，须设置认购截止时间
 }
 
 use crate::schema::*;
@@ -96,20 +131,39 @@ pub struct IPONewStockModel {
     pub id: i64,
     pub issuer_id: i64,
     pub offer_circ: i64,
-    pub offer_price: i32,
+    pub offer_price: Price,
     pub created_at: chrono::NaiveDateTime,
     pub offer_unfulfilled: i64,
+    pub subscribe_ends_at: Option<chrono::NaiveDateTime>,
+    pub allocation: Option<String>,
+    pub lot_size: i64,
+    pub lottery_seed: Option<i64>,
+    pub min_subscription: Option<i64>,
+    pub deadline: Option<chrono::NaiveDateTime>,
+    pub lockup_days: Option<i32>,
+    pub lockup_allottees: bool,
+    pub auction: bool,
 }
 
 impl IPONewStockModel {
     fn from_borrowed_ipo_and_id_and_user(ipo: &IPOModel, user: &RememberUserModel, id: i64) -> IPONewStockModel {
+        let now = chrono::Utc::now();
         IPONewStockModel {
             id,
             issuer_id: user.id,
             offer_circ: ipo.offer_circ,
             offer_price: ipo.offer_price,
             offer_unfulfilled: ipo.offer_circ,
-            created_at: chrono::Utc::now().naive_utc(),
+            created_at: now.naive_utc(),
+            subscribe_ends_at: ipo.subscribe_ends_at,
+            allocation: ipo.subscribe_ends_at.map(|_| ipo.allocation.unwrap_or(Allocation::Lottery).as_str().to_owned()),
+            lot_size: ipo.lot_size.unwrap_or(1),
+            lottery_seed: None,     // 认购期结束配售时才抽取摇号种子
+            min_subscription: ipo.min_subscription,
+            deadline: ipo.deadline,
+            lockup_days: ipo.lockup_days,
+            lockup_allottees: ipo.lockup_allottees.unwrap_or(false),
+            auction: ipo.auction.unwrap_or(false),
         }
     }
 }
@@ -120,6 +174,8 @@ use crate::schema::*;
 pub struct IPOStockModel {
     pub name: String,
     pub into_market: bool,
+    pub price_decimals: i16,
+    pub currency: String,
 }
 
 impl IPOStockModel {
@@ -127,6 +183,8 @@ impl IPOStockModel {
         IPOStockModel {
             name: ipo.name.to_owned(),
             into_market: false,
+            price_decimals: ipo.price_decimals.unwrap_or(DEFAULT_PRICE_DECIMALS),
+            currency: ipo.currency.clone().unwrap_or(DEFAULT_CURRENCY.to_owned()),
         }
     }
 }
@@ -159,11 +217,61 @@ fn ipo_stock_query(ipo: IPOModel, user: RememberUserModel, pool: web::Data<Pool>
     use crate::schema::stocks::dsl::*;
     use crate::schema::new_stocks::dsl::*;
 
+    // 检查发行价格和数量
+    let decimals = ipo.price_decimals.unwrap_or(DEFAULT_PRICE_DECIMALS);
+    if !is_valid_price_decimals(decimals) {
+        return Err(EngineError::BadRequest(format!("价格保留的小数位数 {} 无效：价格以分为单位保存，只能保留 0 到 {} 位小数，不支持低于一分的报价！", decimals, MONEY_SCALE)));
+    }
+    if ipo.offer_price <= Price(0) || ipo.offer_circ <= 0 {
+        return Err(EngineError::BadRequest(format!("发行价格和发行量必须为正数！")));
+    }
+    if !ipo.offer_price.is_on_tick(decimals) {
+        return Err(EngineError::BadRequest(format!("发行价格 {} 元不符合设置的价格精度！", ipo.offer_price)));
+    }
+    ipo.offer_price.checked_value(ipo.offer_circ)
+        .ok_or_else(|| EngineError::BadRequest(format!("发行总金额过大！")))?;
+    let shares_per_lot = ipo.lot_size.unwrap_or(1);
+    if shares_per_lot <= 0 || ipo.offer_circ % shares_per_lot != 0 {
+        return Err(EngineError::BadRequest(format!("每手股数必须为正数，且发行量必须是每手股数的整数倍！")));
+    }
+    if let Some(min) = ipo.min_subscription {
+        if min <= 0 || min > ipo.offer_circ {
+            return Err(EngineError::BadRequest(format!("最低发行量必须为正数，且不能超过发行量！")));
+        }
+        if ipo.deadline.is_none() {
+            return Err(EngineError::BadRequest(format!("设置最低发行量时必须同时设置截止时间！")));
+        }
+    }
+    if let Some(closes_at) = ipo.deadline {
+        if closes_at <= chrono::Utc::now().naive_utc() || ipo.subscribe_ends_at.map_or(false, |ends_at| closes_at < ends_at) {
+            return Err(EngineError::BadRequest(format!("截止时间必须晚于当前时间，且不能早于认购截止时间！")));
+        }
+    }
+    if ipo.auction.unwrap_or(false) && ipo.subscribe_ends_at.is_none() {
+        return Err(EngineError::BadRequest(format!("拍卖发行必须设置认购截止时间！")));
+    }
+    if ipo.lockup_days.map_or(false, |days| days <= 0) {
+        return Err(EngineError::BadRequest(format!("锁定天数必须为正数！")));
+    }
+    if ipo.lockup_allottees.unwrap_or(false) && ipo.lockup_days.is_none() {
+        return Err(EngineError::BadRequest(format!("锁定新股认购者时必须设置锁定天数！")));
+    }
+    match ipo.subscribe_ends_at {
+        Some(ends_at) => if ends_at <= chrono::Utc::now().naive_utc() {
+            return Err(EngineError::BadRequest(format!("认购截止时间必须晚于当前时间！")));
+        },
+        None => if ipo.allocation.is_some() {
+            return Err(EngineError::BadRequest(format!("只有设置了认购截止时间的发行才能指定配售方式！")));
+        }
+    }
+
     // 取出数据库连接
     let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
 
     conn.transaction(|| {
         // 保证原子性
+        super::wallets::check_currency(ipo.currency.as_ref().map(String::as_str).unwrap_or(DEFAULT_CURRENCY), conn)?;
+
         // 第一步：建立 stock，有名字重复则马上失败
         let query_stock = diesel::insert_into(stocks)
             .values(IPOStockModel::from_ipo(&ipo));
@@ -205,13 +313,15 @@ fn ipo_stock_query(ipo: IPOModel, user: RememberUserModel, pool: web::Data<Pool>
 pub fn list_stock(
     stock_id: web::Path<u64>,
     curr_user: RememberUserModel,
-    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    fees: web::Data<FeeSchedule>,
+    notify: web::Data<UserFeed>
 ) -> impl Future<Item = HttpResponse, Error = EngineError> {
     let stock_id = stock_id.into_inner();
    
     web::block(
         move || {
-            list_stock_query(stock_id, curr_user, pool)
+            list_stock_query(stock_id, curr_user, pool, fees, notify)
         }
     ).then(
         move |res: Result<(), BlockingError<EngineError>>|
@@ -225,7 +335,7 @@ pub fn list_stock(
     )
 }
 
-fn list_stock_query(stock_id: u64, curr_user: RememberUserModel, pool: web::Data<Pool>) -> Result<(), EngineError> {
+fn list_stock_query(stock_id: u64, curr_user: RememberUserModel, pool: web::Data<Pool>, fees: web::Data<FeeSchedule>, notify: web::Data<UserFeed>) -> Result<(), EngineError> {
     use crate::schema::stocks::dsl::*;
     use crate::schema::new_stocks::dsl::*;
 
@@ -234,28 +344,82 @@ fn list_stock_query(stock_id: u64, curr_user: RememberUserModel, pool: web::Data
     // 取出数据库连接
     let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
 
-    // 第一步：验证此 stock 是用户本人发行
-    let query_target = stocks.inner_join(new_stocks)
-                            .filter(
-                                crate::schema::new_stocks::dsl::id.eq(stock_id).and(
-                                    issuer_id.eq(curr_user.id)
-                                ).and(
-                                    into_market.eq(false)
-                                )
-                            );
-    let query_check_issuer = query_target.select(crate::schema::new_stocks::dsl::id);
+    let allocated = conn.transaction(|| {
+        // 保证原子性，锁定新股发行信息，避免与截止时间处理或重复请求并发上市
+        // 第一步：验证此 stock 是用户本人发行
+        let query_target = stocks.inner_join(new_stocks)
+                                .filter(
+                                    crate::schema::new_stocks::dsl::id.eq(stock_id).and(
+                                        issuer_id.eq(curr_user.id)
+                                    ).and(
+                                        into_market.eq(false)
+                                    )
+                                );
+        let query_check_issuer = query_target.select((crate::schema::new_stocks::dsl::id, subscribe_ends_at, crate::schema::new_stocks::dsl::allocated_at, failed_at))
+                                    .for_update();
+
+        debug!("List stock check_issuer SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query_check_issuer));
+
+        let (_, subscribe_deadline, allocated, failed) = query_check_issuer
+            .get_result::<(i64, Option<chrono::NaiveDateTime>, Option<chrono::NaiveDateTime>, Option<chrono::NaiveDateTime>)>(conn)
+            .optional()
+            .map_err(|db_err| EngineError::InternalError(format!("数据库查询失败：{}", db_err)))?
+            .ok_or_else(|| EngineError::BadRequest(format!("没有这只股票，或这只股票不是你发行的。")))?;
+
+        if let Some(failed) = failed {
+            return Err(EngineError::BadRequest(format!("这只股票已于 {} 发行失败，不能上市。", failed)));
+        }
 
-    debug!("List stock check_issuer SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query_check_issuer));
+        // 有认购期的发行须先完成配售
+        if let Some(ends_at) = subscribe_deadline {
+            if !super::ipo::allocate_ipo(stock_id, &fees, conn)? {
+                return Err(EngineError::BadRequest(format!("认购期到 {} 才结束，配售完成后才能上市。", ends_at)));
+            }
+        }
 
-    query_check_issuer
-        .get_result::<i64>(conn)
-        .optional()
-        .map_err(|db_err| EngineError::InternalError(format!("数据库查询失败：{}", db_err)))?
-        .ok_or_else(|| EngineError::BadRequest(format!("没有这只股票，或这只股票不是你发行的。")))?;
+        // 须达到最低发行量
+        let query_new_stock = new_stocks.find(stock_id);
+
+        debug!("List stock new_stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query_new_stock));
+
+        let new_stock = query_new_stock.get_result::<NewStock>(conn)
+            .map_err(|db_err| EngineError::InternalError(format!("数据库查询失败：{}", db_err)))?;
+
+        if !new_stock.reached_minimum() {
+            return Err(EngineError::BadRequest(format!("已发行 {} 股，未达到最低发行量 {} 股，不能上市。", new_stock.sold(), new_stock.min_subscription.unwrap_or(0))));
+        }
+
+        // 第二步：上市
+        mark_listed(stock_id, conn)?;
+
+        // 本次请求完成了配售
+        Ok(subscribe_deadline.is_some() && allocated.is_none())
+    })?;
+
+    // 提交后推送配售带来的余额和持有量变化，推送失败不影响上市
+    if allocated {
+        super::ipo::publish_ipo_accounts(stock_id, &notify, conn);
+    }
+
+    Ok(())
+}
+
+// 将股票设为已上市
+pub fn mark_listed(stock_id: i64, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::stocks::dsl::*;
+    use crate::schema::new_stocks::dsl as newdsl;
 
-    // 第二步：上市
+    // 只有尚未上市且没有发行失败的股票可以上市
     let query_list_stock = diesel::update(stocks.filter(
-                                crate::schema::stocks::dsl::id.eq(stock_id)
+                                id.eq(stock_id).and(
+                                    into_market.eq(false)
+                                ).and(
+                                    id.eq_any(
+                                        newdsl::new_stocks
+                                            .filter(newdsl::failed_at.is_null())
+                                            .select(newdsl::id)
+                                    )
+                                )
                             ))
                             .set((
                                     into_market.eq(true),
@@ -264,11 +428,13 @@ fn list_stock_query(stock_id: u64, curr_user: RememberUserModel, pool: web::Data
 
     debug!("List stock list_stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query_list_stock));
 
-    query_list_stock.get_result::<(i64, String, bool, Option<chrono::NaiveDateTime>)>(conn)
+    query_list_stock.get_result::<Stock>(conn)
+        .optional()
         .map_err(|db_err| {
             debug!("Database query error: {}", db_err);
             EngineError::InternalError(format!("数据库插入上市股票错误：{}", db_err))
-        })?;
+        })?
+        .ok_or_else(|| EngineError::BadRequest(format!("这只股票已经上市或已发行失败，不能上市。")))?;
 
     Ok(())
 }
@@ -334,7 +500,7 @@ fn get_stocks_query(paging: PagingModel, pool: web::Data<Pool>) -> Result<Vec<Ge
                     .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .select(
-                        (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
+                        (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, crate::schema::stocks::dsl::price_decimals, crate::schema::stocks::dsl::currency, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
                     );
 
     debug!("Get stocks SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
@@ -357,7 +523,7 @@ pub struct GetNewStockModelNotNull {
     pub into_market: bool,
     pub into_market_at: Option<chrono::NaiveDateTime>,
     pub offer_circ: i64,
-    pub offer_price: i32,
+    pub offer_price: Price,
     pub offer_unfulfilled: i64,
     pub created_at: chrono::NaiveDateTime
 }
@@ -370,8 +536,10 @@ pub struct GetNewStockModel {
     pub issuer_name: Option<String>,
     pub into_market: bool,
     pub into_market_at: Option<chrono::NaiveDateTime>,
+    pub price_decimals: i16,
+    pub currency: String,
     pub offer_circ: Option<i64>,
-    pub offer_price: Option<i32>,
+    pub offer_price: Option<Price>,
     pub offer_unfulfilled: Option<i64>,
     pub created_at: Option<chrono::NaiveDateTime>
 }
@@ -408,13 +576,15 @@ fn get_ipo_stocks_query(paging: PagingModel, pool: web::Data<Pool>) -> Result<Ve
     let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
 
     let query = new_stocks.inner_join(stocks).inner_join(users).filter(
-                    into_market.eq(false)
+                    into_market.eq(false).and(
+                        failed_at.is_null()
+                    )
                 )
                     .order(crate::schema::new_stocks::dsl::created_at.desc())
                     .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .select(
-                        (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
+                        (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, crate::schema::stocks::dsl::price_decimals, crate::schema::stocks::dsl::currency, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
                     );
 
     debug!("Get ipo stocks SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
@@ -468,7 +638,7 @@ fn get_my_stocks_query(paging: PagingModel, user: RememberUserModel, pool: web::
                     .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .select(
-                        (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
+                        (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, crate::schema::stocks::dsl::price_decimals, crate::schema::stocks::dsl::currency, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
                     );
 
     debug!("Get my stocks SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
@@ -524,7 +694,7 @@ fn get_my_holds_query(paging: PagingModel, user: RememberUserModel, pool: web::D
                     .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .select(
-                        (stkdsl::id, stkdsl::name, usrdsl::id.nullable(), usrdsl::name.nullable(), stkdsl::into_market, stkdsl::into_market_at, newdsl::offer_circ.nullable(), newdsl::offer_price.nullable(), newdsl::offer_unfulfilled.nullable(), newdsl::created_at.nullable())
+                        (stkdsl::id, stkdsl::name, usrdsl::id.nullable(), usrdsl::name.nullable(), stkdsl::into_market, stkdsl::into_market_at, stkdsl::price_decimals, stkdsl::currency, newdsl::offer_circ.nullable(), newdsl::offer_price.nullable(), newdsl::offer_unfulfilled.nullable(), newdsl::created_at.nullable())
                     );
 
     debug!("Get my holds SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
@@ -578,7 +748,7 @@ fn get_my_ipo_stocks_query(paging: PagingModel, user: RememberUserModel, pool: w
                     .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
                     .select(
-                        (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
+                        (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, crate::schema::stocks::dsl::price_decimals, crate::schema::stocks::dsl::currency, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
                     );
 
     debug!("Get my ipo stocks SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
@@ -605,7 +775,7 @@ pub fn get_stock(
             get_stock_query(stock_id, pool)
         }
     ).then(
-        move |res: Result<GetNewStockModel, BlockingError<EngineError>>|
+        move |res: Result<StockDetailModel, BlockingError<EngineError>>|
             match res {
                 Ok(stock) => Ok(HttpResponse::Ok().json(stock)),
                 Err(err) => match err {
@@ -616,7 +786,14 @@ pub fn get_stock(
     )
 }
 
-fn get_stock_query(stock_id: u64, pool: web::Data<Pool>) -> Result<GetNewStockModel, EngineError> {
+#[derive(Serialize)]
+pub struct StockDetailModel {
+    #[serde(flatten)]
+    pub stock: GetNewStockModel,
+    pub buybacks: Vec<Buyback>      // 进行中的回购计划及其进度
+}
+
+fn get_stock_query(stock_id: u64, pool: web::Data<Pool>) -> Result<StockDetailModel, EngineError> {
     use crate::schema::new_stocks::dsl::*;
     use crate::schema::stocks::dsl::*;
     use crate::schema::users::dsl::*;
@@ -630,15 +807,22 @@ fn get_stock_query(stock_id: u64, pool: web::Data<Pool>) -> Result<GetNewStockMo
                     crate::schema::stocks::dsl::id.eq(stock_id)
                 )
                 .select(
-                    (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
+                    (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, crate::schema::stocks::dsl::price_decimals, crate::schema::stocks::dsl::currency, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
                 );
 
     debug!("Get stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
 
-    query.get_result::<GetNewStockModel>(conn)
+    let stock = query.get_result::<GetNewStockModel>(conn)
         .optional()
         .map_err(|db_err| EngineError::InternalError(format!("数据库查询失败：{}", db_err)))?
-        .ok_or_else(|| EngineError::NotFound(format!("没有这只股票。")))
+        .ok_or_else(|| EngineError::NotFound(format!("没有这只股票。")))?;
+
+    let buybacks = active_buybacks(stock_id, conn)?;
+
+    Ok(StockDetailModel {
+        stock,
+        buybacks
+    })
 }
 
 
@@ -679,7 +863,7 @@ fn get_stock_by_name_query(stock_name: String, pool: web::Data<Pool>) -> Result<
                     crate::schema::stocks::dsl::name.eq(stock_name)
                 )
                 .select(
-                    (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
+                    (crate::schema::stocks::dsl::id, crate::schema::stocks::dsl::name, crate::schema::users::dsl::id.nullable(),crate::schema::users::dsl::name.nullable(), into_market, into_market_at, crate::schema::stocks::dsl::price_decimals, crate::schema::stocks::dsl::currency, offer_circ.nullable(), offer_price.nullable(), offer_unfulfilled.nullable(), crate::schema::new_stocks::dsl::created_at.nullable())
                 );
 
     debug!("Get stock by name SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
@@ -695,7 +879,9 @@ fn get_stock_by_name_query(stock_name: String, pool: web::Data<Pool>) -> Result<
 #[derive(QueryableByName, Serialize)]
 pub struct HoldingModel {
     #[sql_type = "sql_types::BigInt"]
-    pub hold: i64
+    pub hold: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub short: i64      // 卖空仓位，即尚未归还的借券数量
 } 
 
 pub fn get_stocks_holding(
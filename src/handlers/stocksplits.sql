@@ -0,0 +1,13 @@
+SELECT
+	stock_splits.id AS id,
+	stock_splits.numerator AS numerator,
+	stock_splits.denominator AS denominator,
+	stock_splits.reference_price AS reference_price,
+	stock_splits.cash_paid AS cash_paid,
+	stock_splits.created_at AS created_at,
+	split_adjustment(stock_splits.stock_id, stock_splits.created_at - INTERVAL '1 microsecond')::DOUBLE PRECISION AS adjust_factor
+FROM stock_splits
+WHERE stock_splits.stock_id = $1
+ORDER BY
+	stock_splits.created_at DESC
+LIMIT $3 OFFSET $2;
@@ -1,13 +1,15 @@
-SELECT t1.ts as time, SUM(price*amount)::DOUBLE PRECISION/SUM(amount) as price
+SELECT t1.ts as time, ROUND(candles.turnover::NUMERIC/NULLIF(candles.volume, 0))::INTEGER as price
 FROM
 generate_series(
     DATE_TRUNC('minute', CURRENT_TIMESTAMP) - INTERVAL '30 minutes',
     DATE_TRUNC('minute', CURRENT_TIMESTAMP) - INTERVAL '0 minutes',
     '1 minute'::interval
 ) AS t1(ts)
-LEFT JOIN deals
+LEFT JOIN candles
 ON
-    t1.ts = DATE_TRUNC('minute', deals.created_at)
+    candles.stock_id = $1
         AND
-    stock_id = $1
-GROUP BY t1.ts;
\ No newline at end of file
+    candles.period = '1m'
+        AND
+    candles.bucket = FLOOR(EXTRACT(EPOCH FROM t1.ts) / 60)::BIGINT
+ORDER BY t1.ts;
\ No newline at end of file
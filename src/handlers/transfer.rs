@@ -0,0 +1,429 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+use crate::models::User;
+use crate::money::{Money, DEFAULT_CURRENCY};
+use crate::margin::MarginSettings;
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::orders::{OrderResult, UserStockRel};
+use super::settlement::settle_due;
+use super::dividends::record_dividends;
+use super::ipo::check_lockup;
+use super::wallets::{add_balance, check_balance, check_currency};
+use super::margin::check_margin;
+use super::notify::UserFeed;
+use super::PagingModel;
+
+use crate::schema::*;
+use diesel::sql_types;
+
+const MEMO_MAX_LEN: usize = 200;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/transfers")
+        .service(
+            web::resource("/cash")
+                .route(web::post().to_async(transfer_cash))     // 转账现金
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/stocks")
+                .route(web::post().to_async(transfer_stock))     // 转让股票
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/my/")
+                .route(web::get().to_async(get_my_transfers))     // 查询与自己有关的转账记录
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TransferCashModel {
+    pub to_user_id: u64,
+    pub cash: u64,
+    pub currency: Option<String>,   // 默认为人民币
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TransferStockModel {
+    pub to_user_id: u64,
+    pub stock_id: u64,
+    pub amount: u64,
+    pub memo: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name="transfers"]
+pub struct NewTransfer {
+    pub from_user_id: i64,
+    pub to_user_id: i64,
+    pub stock_id: Option<i64>,
+    pub amount: i64,
+    pub memo: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub currency: Option<String>
+}
+
+// 校验收款方、数量和附言，返回 (收款方 ID, 数量, 附言)
+fn check_transfer_target(to_user_id: u64, amount: u64, memo: Option<String>, user: &RememberUserModel, conn: &PgConnection) -> Result<(i64, i64, String), EngineError> {
+    use crate::schema::users::dsl as usrdsl;
+
+    let to_user_id = i64::try_from(to_user_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+    let amount = i64::try_from(amount).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+    let memo = memo.unwrap_or_default();
+
+    if amount == 0 {
+        return Err(EngineError::BadRequest(format!("转账数量必须大于 0。")));
+    }
+
+    if to_user_id == user.id {
+        return Err(EngineError::BadRequest(format!("不能转账给自己。")));
+    }
+
+    if memo.chars().count() > MEMO_MAX_LEN {
+        return Err(EngineError::BadRequest(format!("附言过长，最多 {} 个字符。", MEMO_MAX_LEN)));
+    }
+
+    let query = usrdsl::users
+                    .find(to_user_id)
+                    .select(usrdsl::id);
+
+    debug!("Transfer check target SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_result::<i64>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?
+        .ok_or_else(|| EngineError::NotFound(format!("收款用户不存在。")))?;
+
+    Ok((to_user_id, amount, memo))
+}
+
+fn insert_transfer(transfer: NewTransfer, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::transfers::dsl as trsdsl;
+
+    let query = diesel::insert_into(trsdsl::transfers)
+                    .values(&transfer);
+
+    debug!("New transfer SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.execute(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库插入转账记录错误：{}", db_err))
+        })?;
+
+    Ok(())
+}
+
+///////////////
+
+pub fn transfer_cash(
+    transfer: web::Json<TransferCashModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    margin: web::Data<MarginSettings>,
+    notify: web::Data<UserFeed>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            transfer_cash_query(transfer.into_inner(), curr_user, pool, margin, notify)
+        }
+    ).then(
+        move |res: Result<(), BlockingError<EngineError>>|
+            match res {
+                Ok(_) => Ok(HttpResponse::Ok().finish()),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn transfer_cash_query(transfer: TransferCashModel, user: RememberUserModel, pool: web::Data<Pool>, margin: web::Data<MarginSettings>, notify: web::Data<UserFeed>) -> Result<(), EngineError> {
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let to_user_id = conn.transaction::<_, EngineError, _>(|| {
+        // 保证原子性
+        let (to_user_id, cash, memo) = check_transfer_target(transfer.to_user_id, transfer.cash, transfer.memo.clone(), &user, conn)?;
+        let cash = Money(cash);
+        let currency = transfer.currency.clone().unwrap_or(DEFAULT_CURRENCY.to_owned());
+        check_currency(&currency, conn)?;
+        settle_due(user.id, conn)?;
+
+        // 扣转出方的钱
+        let balance_after = add_balance(user.id, &currency, -cash, conn)?;
+        check_balance(balance_after, &currency, "完成这笔转账")?;
+        check_margin(user.id, &currency, &margin, "转出这笔现金", conn)?;
+
+        // 加收款方的钱
+        add_balance(to_user_id, &currency, cash, conn)?;
+
+        insert_transfer(
+            NewTransfer {
+                from_user_id: user.id,
+                to_user_id,
+                stock_id: None,
+                amount: cash.0,
+                memo,
+                created_at: chrono::Utc::now().naive_utc(),
+                currency: Some(currency)
+            },
+            conn
+        )?;
+
+        Ok(to_user_id)
+    })?;
+
+    // 提交后推送双方的余额
+    for user_id in &[user.id, to_user_id] {
+        if let Err(err) = notify.publish_account(*user_id, None, conn) {
+            error!("Publishing user events failed: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+///////////////
+
+pub fn transfer_stock(
+    transfer: web::Json<TransferStockModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    margin: web::Data<MarginSettings>,
+    notify: web::Data<UserFeed>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            transfer_stock_query(transfer.into_inner(), curr_user, pool, margin, notify)
+        }
+    ).then(
+        move |res: Result<(), BlockingError<EngineError>>|
+            match res {
+                Ok(_) => Ok(HttpResponse::Ok().finish()),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn transfer_stock_query(transfer: TransferStockModel, user: RememberUserModel, pool: web::Data<Pool>, margin: web::Data<MarginSettings>, notify: web::Data<UserFeed>) -> Result<(), EngineError> {
+    use crate::schema::user_hold_stock::dsl as reldsl;
+    use crate::schema::stocks::dsl as stkdsl;
+
+    let stock_id = i64::try_from(transfer.stock_id).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let to_user_id = conn.transaction(|| {
+        // 保证原子性
+        let (to_user_id, amount, memo) = check_transfer_target(transfer.to_user_id, transfer.amount, transfer.memo.clone(), &user, conn)?;
+
+        // 检查股票是否上市
+        let query_stock = stkdsl::stocks
+                            .find(stock_id)
+                            .filter(
+                                stkdsl::into_market.eq(true)
+                            )
+                            .select(stkdsl::currency);
+
+        debug!("Transfer stock query_stock SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query_stock));
+
+        let currency = query_stock.get_result::<String>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?
+            .ok_or_else(|| EngineError::BadRequest(format!("该股票还未上市，不能转让！")))?;
+
+        check_lockup(stock_id, user.id, conn)?;
+        record_dividends(conn)?;
+        settle_due(user.id, conn)?;
+
+        // 扣转出方的股票（委托中冻结的股票不在 hold 中，不能转让）
+        let query = diesel::update(reldsl::user_hold_stock.find(
+                        (user.id, stock_id)
+                    ))
+                    .set((
+                        reldsl::hold.eq(reldsl::hold - amount),
+                        reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
+                    ));
+
+        debug!("Transfer stock debit SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let rel_after = query.get_result::<UserStockRel>(conn)
+            .optional()
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库更新股票持有量错误：{}", db_err))
+            })?
+            .ok_or_else(|| {
+                let err_msg = format!("股票持有量不足，你当前并未持有该股票。");
+                EngineError::Insufficient(
+                    OrderResult {
+                        succeed: false,
+                        message: Some(err_msg.clone()),
+                        error: Some(err_msg),
+                        deal_amount: None,
+                        lack: Some(amount)
+                    }
+                )
+            })?;
+
+        if rel_after.hold < 0 {
+            let err_msg = format!("股票持有量不足，你还需要 {} 股来完成这笔转让。", -rel_after.hold);
+            return Err(EngineError::Insufficient(
+                OrderResult {
+                    succeed: false,
+                    message: Some(err_msg.clone()),
+                    error: Some(err_msg),
+                    deal_amount: None,
+                    lack: Some(-rel_after.hold)
+                }
+            ));
+        }
+
+        // 转出的股票不能是融资借款的担保
+        check_margin(user.id, &currency, &margin, "转让这些股票", conn)?;
+
+        // 加收款方的股票
+        diesel::insert_into(reldsl::user_hold_stock)
+            .values(
+                UserStockRel {
+                    user_id: to_user_id,
+                    stock_id,
+                    hold: amount,
+                    updated_at: chrono::Utc::now().naive_utc()
+                }
+            )
+            .on_conflict((reldsl::user_id, reldsl::stock_id))
+            .do_update()
+            .set((
+                reldsl::hold.eq(reldsl::hold + amount),
+                reldsl::updated_at.eq(chrono::Utc::now().naive_utc())
+            ))
+            .execute(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库重设收款方股票数量错误：{}", db_err))
+            })?;
+
+        insert_transfer(
+            NewTransfer {
+                from_user_id: user.id,
+                to_user_id,
+                stock_id: Some(stock_id),
+                amount,
+                memo,
+                created_at: chrono::Utc::now().naive_utc(),
+                currency: None
+            },
+            conn
+        )?;
+
+        Ok(to_user_id)
+    })?;
+
+    // 提交后推送双方的持有量
+    for user_id in &[user.id, to_user_id] {
+        if let Err(err) = notify.publish_account(*user_id, Some(stock_id), conn) {
+            error!("Publishing user events failed: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+//////////////////
+#[derive(QueryableByName, Serialize, Deserialize)]
+pub struct TransferModel {
+    #[sql_type = "sql_types::BigInt"]
+    pub id: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub from_user_id: i64,
+    #[sql_type = "sql_types::Varchar"]
+    pub from_user_name: String,
+    #[sql_type = "sql_types::BigInt"]
+    pub to_user_id: i64,
+    #[sql_type = "sql_types::Varchar"]
+    pub to_user_name: String,
+    #[sql_type = "sql_types::Nullable<sql_types::BigInt>"]
+    pub stock_id: Option<i64>,  // 当是 NULL 时，表示转账现金
+    #[sql_type = "sql_types::Nullable<sql_types::Varchar>"]
+    pub stock_name: Option<String>,
+    #[sql_type = "sql_types::Nullable<sql_types::Varchar>"]
+    pub currency: Option<String>,   // 转账现金的币种
+    #[sql_type = "sql_types::Int8"]
+    pub amount: i64,
+    #[sql_type = "sql_types::Varchar"]
+    pub memo: String,
+    #[sql_type = "sql_types::Timestamp"]
+    pub created_at: chrono::NaiveDateTime
+}
+
+pub fn get_my_transfers(
+    paging: web::Query<PagingModel>,
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_my_transfers_query(paging, user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<TransferModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(transfers) => Ok(HttpResponse::Ok().json(transfers)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_transfers_query(paging: PagingModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<TransferModel>, EngineError> {
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = diesel::sql_query(include_str!("mytransfers.sql"))
+                    .bind::<sql_types::BigInt, _>(user.id)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.offset.unwrap_or(0)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .bind::<sql_types::Int8, _>(i64::try_from(paging.limit.unwrap_or(10)).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get my transfers SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.load::<TransferModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
@@ -0,0 +1,9 @@
+INSERT INTO candles (stock_id, period, bucket, open, high, low, close, volume, turnover)
+SELECT $1, t.period, t.bucket, $4, $4, $4, $4, $5, $6
+FROM unnest($2::VARCHAR[], $3::BIGINT[]) AS t(period, bucket)
+ON CONFLICT (stock_id, period, bucket) DO UPDATE SET
+    high = GREATEST(candles.high, EXCLUDED.high),
+    low = LEAST(candles.low, EXCLUDED.low),
+    close = EXCLUDED.close,
+    volume = candles.volume + EXCLUDED.volume,
+    turnover = candles.turnover + EXCLUDED.turnover;
@@ -0,0 +1,7 @@
+INSERT INTO index_candles (index_name, period, bucket, open, high, low, close)
+SELECT $1, t.period, t.bucket, $4, $4, $4, $4
+FROM unnest($2::VARCHAR[], $3::BIGINT[]) AS t(period, bucket)
+ON CONFLICT (index_name, period, bucket) DO UPDATE SET
+    high = GREATEST(index_candles.high, EXCLUDED.high),
+    low = LEAST(index_candles.low, EXCLUDED.low),
+    close = EXCLUDED.close;
\ No newline at end of file
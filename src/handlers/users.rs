@@ -66,8 +66,7 @@ use crate::schema::*;
 pub struct RegisteringUserModel {
     pub password_hashed: String,
     pub name: String,
-    pub created_at: chrono::NaiveDateTime,
-    pub balance: i64
+    pub created_at: chrono::NaiveDateTime
 }
 
 impl Into<RegisteringUserModel> for RegisterModel {
@@ -75,8 +74,7 @@ impl Into<RegisteringUserModel> for RegisterModel {
         RegisteringUserModel {
             password_hashed: crate::hash::hash_password(&self.password[..]),
             name: self.name,
-            created_at: chrono::Utc::now().naive_utc(),
-            balance: 0
+            created_at: chrono::Utc::now().naive_utc()
         }
     }
 }
@@ -166,8 +164,7 @@ fn register_insert_query(reg_data: RegisterModel, pool: Arc<web::Data<Pool>>) ->
 pub struct FetchUserModel {
     pub id: i64,
     pub name: String,
-    pub created_at: chrono::NaiveDateTime,
-    pub balance: i64
+    pub created_at: chrono::NaiveDateTime
 }
 
 pub fn get_user(
@@ -206,7 +203,7 @@ fn get_user_query(user_id: u64, pool: web::Data<Pool>) -> Result<FetchUserModel,
                 id.eq(user_id)
             )
             .select(
-                (id, name, created_at, balance)
+                (id, name, created_at)
             );
 
     debug!("User query SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
@@ -254,7 +251,7 @@ fn get_user_by_name_query(user_name: String, pool: web::Data<Pool>) -> Result<Fe
                 name.eq(user_name)
             )
             .select(
-                (id, name, created_at, balance)
+                (id, name, created_at)
             );
 
     debug!("User query by name SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
@@ -268,6 +265,12 @@ fn get_user_by_name_query(user_name: String, pool: web::Data<Pool>) -> Result<Fe
 
 
 //////////////////
+#[derive(Serialize)]
+pub struct FetchUserMeModel {
+    #[serde(flatten)]
+    pub user: FetchUserModel,
+    pub wallets: Vec<super::wallets::WalletModel>     // 各币种的余额
+}
 
 pub fn get_user_me(
     curr_user: RememberUserModel,
@@ -279,7 +282,7 @@ pub fn get_user_me(
             get_user_me_query(curr_user, pool)
         }
     ).then(
-        move |res: Result<FetchUserModel, BlockingError<EngineError>>|
+        move |res: Result<FetchUserMeModel, BlockingError<EngineError>>|
             match res {
                 Ok(fetch_user_model) => Ok(HttpResponse::Ok().json(fetch_user_model)),
                 Err(err) => match err {
@@ -290,7 +293,7 @@ pub fn get_user_me(
     )
 }
 
-fn get_user_me_query(curr_user: RememberUserModel, pool: web::Data<Pool>) -> Result<FetchUserModel, EngineError> {
+fn get_user_me_query(curr_user: RememberUserModel, pool: web::Data<Pool>) -> Result<FetchUserMeModel, EngineError> {
     use crate::schema::users::dsl::*;
 
     // 取出数据库连接
@@ -302,16 +305,21 @@ fn get_user_me_query(curr_user: RememberUserModel, pool: web::Data<Pool>) -> Res
                     id.eq(curr_user.id)
                 )
                 .select(
-                    (id, name, created_at, balance)
+                    (id, name, created_at)
                 );
 
     debug!("User get self query by name SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
             
-    query
+    let user = query
         .get_result::<FetchUserModel>(conn)
         .optional()
         .map_err(|db_err| EngineError::InternalError(format!("数据库查询失败：{}", db_err)))?
-        .ok_or_else(|| EngineError::NotFound(format!("查询错误，没有该用户。")))
+        .ok_or_else(|| EngineError::NotFound(format!("查询错误，没有该用户。")))?;
+
+    Ok(FetchUserMeModel {
+        user,
+        wallets: super::wallets::user_wallets(curr_user.id, conn)?
+    })
 }
 
 //////////////////
@@ -419,8 +427,7 @@ fn login_query(user: LoginModel, pool: web::Data<Pool>) -> Result<RememberUserMo
 pub struct TestAddingUserModel {
     pub password_hashed: String,
     pub name: String,
-    pub created_at: chrono::NaiveDateTime,
-    pub balance: i64
+    pub created_at: chrono::NaiveDateTime
 }
 
 #[test]
@@ -433,32 +440,27 @@ pub fn test_add_and_load_some_users() {
             TestAddingUserModel {
                 name: "张三".to_owned(),
                 password_hashed: hash_password("password"),
-                created_at: chrono::Local::now().naive_utc(),
-                balance: 100
+                created_at: chrono::Local::now().naive_utc()
             },
             TestAddingUserModel {
                 name: "李四".to_owned(),
                 password_hashed: hash_password("password"),
-                created_at: chrono::Local::now().naive_utc(),
-                balance: 100
+                created_at: chrono::Local::now().naive_utc()
             },
             TestAddingUserModel {
                 name: "王五".to_owned(),
                 password_hashed: hash_password("password"),
-                created_at: chrono::Local::now().naive_utc(),
-                balance: 100
+                created_at: chrono::Local::now().naive_utc()
             },
             TestAddingUserModel {
                 name: "赵六".to_owned(),
                 password_hashed: hash_password("password"),
-                created_at: chrono::Local::now().naive_utc(),
-                balance: 100
+                created_at: chrono::Local::now().naive_utc()
             },
             TestAddingUserModel {
                 name: "冯舜".to_owned(),
                 password_hashed: hash_password("password"),
-                created_at: chrono::Local::now().naive_utc(),
-                balance: 100
+                created_at: chrono::Local::now().naive_utc()
             },]
         )
         .on_conflict_do_nothing()
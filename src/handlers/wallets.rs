@@ -0,0 +1,425 @@
+use actix_web::{
+    web, Error, HttpRequest, HttpResponse, FromRequest
+};
+use actix_web::error::BlockingError;
+use actix_identity::Identity;
+use crate::models::{Conversion, FxRate};
+use crate::money::Money;
+use crate::margin::MarginSettings;
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use futures::Future;
+use crate::errors::EngineError;
+
+use crate::common::Pool;
+use diesel::PgConnection;
+use diesel::prelude::*;
+
+use super::users::{RememberUserModel};
+use super::orders::OrderResult;
+use super::settlement::settle_due;
+use super::shorts::accrue_borrow_fees;
+use super::margin::check_margin;
+use super::notify::UserFeed;
+use super::PagingModel;
+
+use crate::schema::*;
+use diesel::sql_types;
+
+pub fn make_scope() -> actix_web::Scope {
+    web::scope("/wallets")
+        .service(
+            web::resource("/my/")
+                .route(web::get().to_async(get_my_wallets))     // 查询自己各币种的余额
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/fx-rates/")
+                .route(web::get().to_async(get_fx_rates))     // 查询汇率表
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/convert")
+                .route(web::post().to_async(convert))     // 按汇率兑换币种
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+        .service(
+            web::resource("/conversions/my/")
+                .route(web::get().to_async(get_my_conversions))     // 查询自己的兑换记录
+                .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
+        )
+}
+
+// 检查币种是否存在
+pub fn check_currency(currency: &str, conn: &PgConnection) -> Result<(), EngineError> {
+    use crate::schema::currencies::dsl as curdsl;
+
+    let query = curdsl::currencies
+                    .find(currency)
+                    .select(curdsl::code);
+
+    debug!("Check currency SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_result::<String>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?
+        .ok_or_else(|| EngineError::BadRequest(format!("不支持的币种：{}。", currency)))?;
+
+    Ok(())
+}
+
+// 股票的报价币种
+pub fn stock_currency(stock_id: i64, conn: &PgConnection) -> Result<String, EngineError> {
+    use crate::schema::stocks::dsl as stkdsl;
+
+    let query = stkdsl::stocks
+                    .find(stock_id)
+                    .select(stkdsl::currency);
+
+    debug!("Stock currency SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_result::<String>(conn)
+        .optional()
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })?
+        .ok_or_else(|| EngineError::NotFound(format!("没有这只股票。")))
+}
+
+// 增减用户某一币种的余额（钱包不存在时自动创建），返回变动后的余额，可能为负，由调用方检查
+pub fn add_balance(user_id: i64, currency: &str, delta: Money, conn: &PgConnection) -> Result<Money, EngineError> {
+    use crate::schema::user_wallets::dsl as wltdsl;
+    use diesel::pg::upsert::excluded;
+
+    let query = diesel::insert_into(wltdsl::user_wallets)
+                    .values((
+                        wltdsl::user_id.eq(user_id),
+                        wltdsl::currency.eq(currency),
+                        wltdsl::balance.eq(delta),
+                        wltdsl::updated_at.eq(chrono::Utc::now().naive_utc())
+                    ))
+                    .on_conflict((wltdsl::user_id, wltdsl::currency))
+                    .do_update()
+                    .set((
+                        wltdsl::balance.eq(wltdsl::balance + excluded(wltdsl::balance)),
+                        wltdsl::updated_at.eq(excluded(wltdsl::updated_at))
+                    ))
+                    .returning(wltdsl::balance);
+
+    debug!("Add balance SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_result::<Money>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库更新余额错误：{}", db_err))
+        })
+}
+
+// 余额为负时返回余额不足的错误，action 描述要完成的操作
+pub fn check_balance(balance_after: Money, currency: &str, action: &str) -> Result<(), EngineError> {
+    if balance_after < Money::zero() {
+        let err_msg = format!("{} 账户余额不足，你还需要 {} {}来{}。", currency, -balance_after, currency, action);
+        return Err(EngineError::Insufficient(
+            OrderResult {
+                succeed: false,
+                message: Some(err_msg.clone()),
+                error: Some(err_msg),
+                deal_amount: None,
+                lack: Some((-balance_after).0)
+            }
+        ));
+    }
+    Ok(())
+}
+
+//////////////////
+#[derive(Queryable, Serialize)]
+pub struct WalletModel {
+    pub currency: String,
+    pub currency_name: String,
+    pub balance: Money,
+    pub updated_at: chrono::NaiveDateTime
+}
+
+pub fn get_my_wallets(
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            get_my_wallets_query(user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<WalletModel>, BlockingError<EngineError>>|
+            match res {
+                Ok(wallets) => Ok(HttpResponse::Ok().json(wallets)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_wallets_query(user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<WalletModel>, EngineError> {
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    user_wallets(user.id, conn)
+}
+
+// 用户各币种的余额
+pub fn user_wallets(user_id: i64, conn: &PgConnection) -> Result<Vec<WalletModel>, EngineError> {
+    use crate::schema::currencies::dsl as curdsl;
+    use crate::schema::user_wallets::dsl as wltdsl;
+
+    let query = wltdsl::user_wallets
+                    .inner_join(curdsl::currencies)
+                    .filter(
+                        wltdsl::user_id.eq(user_id)
+                    )
+                    .order(wltdsl::currency.asc())
+                    .select(
+                        (
+                            wltdsl::currency,
+                            curdsl::name,
+                            wltdsl::balance,
+                            wltdsl::updated_at
+                        )
+                    );
+
+    debug!("Get user wallets SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<WalletModel>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+//////////////////
+pub fn get_fx_rates(
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            get_fx_rates_query(pool)
+        }
+    ).then(
+        move |res: Result<Vec<FxRate>, BlockingError<EngineError>>|
+            match res {
+                Ok(rates) => Ok(HttpResponse::Ok().json(rates)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_fx_rates_query(pool: web::Data<Pool>) -> Result<Vec<FxRate>, EngineError> {
+    use crate::schema::fx_rates::dsl as fxdsl;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = fxdsl::fx_rates
+                    .order((fxdsl::base_currency.asc(), fxdsl::quote_currency.asc()));
+
+    debug!("Get fx rates SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<FxRate>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
+
+//////////////////
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConvertModel {
+    pub from_currency: String,
+    pub to_currency: String,
+    pub amount: u64,    // 兑出的金额，单位为 from_currency 的分
+}
+
+#[derive(Insertable, Debug)]
+#[table_name="conversions"]
+pub struct NewConversion {
+    pub user_id: i64,
+    pub from_currency: String,
+    pub to_currency: String,
+    pub from_amount: Money,
+    pub to_amount: Money,
+    pub rate: i64,
+    pub created_at: chrono::NaiveDateTime
+}
+
+pub fn convert(
+    convert: web::Json<ConvertModel>,
+    curr_user: RememberUserModel,
+    pool: web::Data<Pool>,   // 此处将之前附加到应用的数据库连接取出
+    margin: web::Data<MarginSettings>,
+    notify: web::Data<UserFeed>
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+
+    web::block(
+        move || {
+            convert_query(convert.into_inner(), curr_user, pool, margin, notify)
+        }
+    ).then(
+        move |res: Result<Conversion, BlockingError<EngineError>>|
+            match res {
+                Ok(conversion) => Ok(HttpResponse::Ok().json(conversion)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn convert_query(convert: ConvertModel, user: RememberUserModel, pool: web::Data<Pool>, margin: web::Data<MarginSettings>, notify: web::Data<UserFeed>) -> Result<Conversion, EngineError> {
+    use crate::schema::fx_rates::dsl as fxdsl;
+    use crate::schema::conversions::dsl as cvtdsl;
+
+    let from_amount = i64::try_from(convert.amount).map(Money).map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?;
+
+    if from_amount == Money::zero() {
+        return Err(EngineError::BadRequest(format!("兑换金额必须大于 0。")));
+    }
+
+    if convert.from_currency == convert.to_currency {
+        return Err(EngineError::BadRequest(format!("兑出和兑入的币种不能相同。")));
+    }
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let conversion = conn.transaction(|| {
+        // 保证原子性
+        check_currency(&convert.from_currency, conn)?;
+        check_currency(&convert.to_currency, conn)?;
+        settle_due(user.id, conn)?;
+
+        // 优先使用正向汇率，没有时使用反向汇率
+        let query = fxdsl::fx_rates
+                        .filter(
+                            fxdsl::base_currency.eq(&convert.from_currency).and(
+                                fxdsl::quote_currency.eq(&convert.to_currency)
+                            ).or(
+                                fxdsl::base_currency.eq(&convert.to_currency).and(
+                                    fxdsl::quote_currency.eq(&convert.from_currency)
+                                )
+                            )
+                        );
+
+        debug!("Convert fx rate SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        let rates = query.get_results::<FxRate>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+            })?;
+
+        let direct = rates.iter().find(|r| r.base_currency == convert.from_currency);
+        let inverse = rates.iter().find(|r| r.base_currency == convert.to_currency);
+        let (to_amount, rate) = match (direct, inverse) {
+            (Some(fx), _) => (from_amount.convert(fx.rate), fx.rate),
+            (None, Some(fx)) => (from_amount.convert_inverse(fx.rate), Money(crate::money::FX_RATE_UNIT).convert_inverse(fx.rate).0),
+            (None, None) => return Err(EngineError::BadRequest(format!("没有 {} 兑 {} 的汇率。", convert.from_currency, convert.to_currency)))
+        };
+
+        if to_amount == Money::zero() {
+            return Err(EngineError::BadRequest(format!("兑换金额过小，兑换后不足 0.01 {}。", convert.to_currency)));
+        }
+
+        let balance_after = add_balance(user.id, &convert.from_currency, -from_amount, conn)?;
+        check_balance(balance_after, &convert.from_currency, "完成这笔兑换")?;
+        check_margin(user.id, &convert.from_currency, &margin, "兑出这笔现金", conn)?;
+
+        add_balance(user.id, &convert.to_currency, to_amount, conn)?;
+
+        let query = diesel::insert_into(cvtdsl::conversions)
+                        .values(
+                            NewConversion {
+                                user_id: user.id,
+                                from_currency: convert.from_currency.clone(),
+                                to_currency: convert.to_currency.clone(),
+                                from_amount,
+                                to_amount,
+                                rate,
+                                created_at: chrono::Utc::now().naive_utc()
+                            }
+                        );
+
+        debug!("New conversion SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+        query.get_result::<Conversion>(conn)
+            .map_err(|db_err| {
+                debug!("Database query error: {}", db_err);
+                EngineError::InternalError(format!("数据库插入兑换记录错误：{}", db_err))
+            })
+    })?;
+
+    if let Err(err) = notify.publish_account(user.id, None, conn) {
+        error!("Publishing user events failed: {}", err);
+    }
+
+    Ok(conversion)
+}
+
+//////////////////
+pub fn get_my_conversions(
+    paging: web::Query<PagingModel>,
+    user: RememberUserModel,
+    pool: web::Data<Pool>   // 此处将之前附加到应用的数据库连接取出
+) -> impl Future<Item = HttpResponse, Error = EngineError> {
+    let paging = paging.into_inner();
+
+    web::block(
+        move || {
+            get_my_conversions_query(paging, user, pool)
+        }
+    ).then(
+        move |res: Result<Vec<Conversion>, BlockingError<EngineError>>|
+            match res {
+                Ok(conversions) => Ok(HttpResponse::Ok().json(conversions)),
+                Err(err) => match err {
+                    BlockingError::Error(eng_err) => Err(eng_err),
+                    BlockingError::Canceled => Err(EngineError::InternalError("不明原因，内部请求被中断。服务端遇到错误。".to_owned()))
+                }
+            }
+    )
+}
+
+fn get_my_conversions_query(paging: PagingModel, user: RememberUserModel, pool: web::Data<Pool>) -> Result<Vec<Conversion>, EngineError> {
+    use crate::schema::conversions::dsl as cvtdsl;
+
+    // 取出数据库连接
+    let conn : &PgConnection = &*(pool.get().map_err(|pool_err| EngineError::InternalError(format!("服务端遇到错误，无法取得与数据库的连接：{}。", pool_err)))?);
+
+    let query = cvtdsl::conversions
+                    .filter(
+                        cvtdsl::user_id.eq(user.id)
+                    )
+                    .order(cvtdsl::created_at.desc())
+                    .offset(paging.offset.unwrap_or(0).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?)
+                    .limit(paging.limit.unwrap_or(10).try_into().map_err(|try_err| EngineError::InternalError(format!("输入的整数太大，无法安全转为 64 字节有符号整数：{}。", try_err)))?);
+
+    debug!("Get my conversions SQL: {}", diesel::debug_query::<diesel::pg::Pg, _>(&query));
+
+    query.get_results::<Conversion>(conn)
+        .map_err(|db_err| {
+            debug!("Database query error: {}", db_err);
+            EngineError::InternalError(format!("数据库查询错误：{}", db_err))
+        })
+}
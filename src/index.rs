@@ -0,0 +1,105 @@
+use crate::money::{Money, Price, DEFAULT_CURRENCY};
+
+use std::convert::TryFrom;
+
+const DEFAULT_INDEX_NAME : &str = "composite";
+
+// 首次计算时的指数点位，单位为 0.01 点，即 1000 点
+const DEFAULT_INDEX_BASE : i32 = 100_000;
+
+// 市值加权综合指数的设置，成分股的市值为流通股数乘以最新成交价
+#[derive(Debug, Clone)]
+pub struct IndexSettings {
+    pub name: String,           // 指数代码，更改后另起一条指数序列
+    pub currency: String,       // 只有以该币种报价的股票可以作为成分股
+    pub stock_ids: Vec<i64>,    // 成分股，为空时包括该币种的全部已上市股票
+    pub base_value: Price       // 首次计算时的指数点位
+}
+
+impl IndexSettings {
+    pub fn from_env() -> IndexSettings {
+        let stock_ids = match std::env::var("MARKET_INDEX_STOCKS") {
+            Ok(value) => value.split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(|id| id.parse::<i64>().expect("环境变量 MARKET_INDEX_STOCKS 必须是以逗号分隔的股票 ID！"))
+                .collect(),
+            Err(_) => Vec::new()
+        };
+        let base_value = match std::env::var("MARKET_INDEX_BASE") {
+            Ok(value) => match value.parse::<i32>() {
+                Ok(base) if base > 0 => Price(base),
+                _ => panic!("环境变量 MARKET_INDEX_BASE 必须是正整数！")
+            },
+            Err(_) => Price(DEFAULT_INDEX_BASE)
+        };
+
+        IndexSettings {
+            name: std::env::var("MARKET_INDEX_NAME").unwrap_or(DEFAULT_INDEX_NAME.to_owned()),
+            currency: std::env::var("MARKET_INDEX_CURRENCY").unwrap_or(DEFAULT_CURRENCY.to_owned()),
+            stock_ids,
+            base_value
+        }
+    }
+}
+
+// 除数以定点整数保存，避免浮点误差在多次调整中累积，DIVISOR_SCALE 表示 1
+pub const DIVISOR_SCALE : i64 = 1_000_000;
+
+// 正数相除，四舍五入
+fn div_round(n: i128, d: i128) -> i128 {
+    (n + d / 2) / d
+}
+
+// 首次计算时的除数，使指数等于基点；超出表示范围时返回 None
+pub fn base_divisor(total_cap: Money, base_value: Price) -> Option<i64> {
+    let divisor = div_round(total_cap.0 as i128 * DIVISOR_SCALE as i128, base_value.0 as i128);
+    i64::try_from(divisor).ok().filter(|divisor| *divisor > 0)
+}
+
+// 成分股调入调出时调整除数，使调整前后的点位相同：continuing_cap 为原成分股中仍在指数内的股票的总市值，
+// 以其计算的点位作为调整前的点位；原成分股都已调出时无法衔接，保持原除数
+pub fn rebalance_divisor(divisor: i64, total_cap: Money, continuing_cap: Money) -> Option<i64> {
+    if continuing_cap <= Money::zero() {
+        return Some(divisor);
+    }
+    let divisor = div_round(divisor as i128 * total_cap.0 as i128, continuing_cap.0 as i128);
+    i64::try_from(divisor).ok().filter(|divisor| *divisor > 0)
+}
+
+pub fn index_value(total_cap: Money, divisor: i64) -> Price {
+    let value = div_round(total_cap.0 as i128 * DIVISOR_SCALE as i128, divisor as i128);
+    Price(i32::try_from(value).unwrap_or(std::i32::MAX))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_index_value() {
+        let divisor = base_divisor(Money(50_000_000), Price(100_000)).unwrap();
+        assert_eq!(divisor, 500 * DIVISOR_SCALE);
+        assert_eq!(index_value(Money(50_000_000), divisor), Price(100_000));
+        assert_eq!(index_value(Money(55_000_000), divisor), Price(110_000));
+
+        // 市值 5500 万时调入一只市值 1100 万的股票，点位不变
+        let rebalanced = rebalance_divisor(divisor, Money(66_000_000), Money(55_000_000)).unwrap();
+        assert_eq!(index_value(Money(66_000_000), rebalanced), Price(110_000));
+        assert_eq!(rebalance_divisor(divisor, Money(66_000_000), Money::zero()), Some(divisor));
+
+        // 反复调整后点位仍然连续，不因舍入漂移
+        let mut divisor = divisor;
+        let mut total_cap = Money(50_000_000);
+        for step in 1..=1000 {
+            let added = Money(step * 3_333);
+            divisor = rebalance_divisor(divisor, total_cap + added, total_cap).unwrap();
+            total_cap += added;
+        }
+        assert_eq!(index_value(total_cap, divisor), Price(100_000));
+
+        // 除数超出表示范围
+        assert_eq!(base_divisor(Money(std::i64::MAX), Price(1)), None);
+        assert_eq!(base_divisor(Money(1), Price(std::i32::MAX)), None);
+    }
+}
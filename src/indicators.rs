@@ -0,0 +1,181 @@
+// 技术指标，输入为按时间升序的收盘价，输出与输入逐一对齐，数据不足以计算的位置为 None
+
+// 简单移动平均
+pub fn sma(values: &[f64], period: usize) -> Vec<Option<f64>> {
+    let mut result = vec![None; values.len()];
+    if period == 0 {
+        return result;
+    }
+    let mut sum = 0.0;
+    for (i, value) in values.iter().enumerate() {
+        sum += value;
+        if i >= period {
+            sum -= values[i - period];
+        }
+        if i + 1 >= period {
+            result[i] = Some(sum / period as f64);
+        }
+    }
+    result
+}
+
+// 指数移动平均，以前 period 个值的简单平均为起点，平滑系数为 2 / (period + 1)
+pub fn ema(values: &[f64], period: usize) -> Vec<Option<f64>> {
+    let mut result = vec![None; values.len()];
+    if period == 0 || values.len() < period {
+        return result;
+    }
+    let alpha = 2.0 / (period as f64 + 1.0);
+    let mut average = values[..period].iter().sum::<f64>() / period as f64;
+    result[period - 1] = Some(average);
+    for i in period..values.len() {
+        average += alpha * (values[i] - average);
+        result[i] = Some(average);
+    }
+    result
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bollinger {
+    pub middle: Vec<Option<f64>>,
+    pub upper: Vec<Option<f64>>,
+    pub lower: Vec<Option<f64>>
+}
+
+// 布林带：中轨为简单移动平均，上下轨为中轨加减 k 倍的总体标准差
+pub fn bollinger(values: &[f64], period: usize, k: f64) -> Bollinger {
+    let middle = sma(values, period);
+    let mut upper = vec![None; values.len()];
+    let mut lower = vec![None; values.len()];
+    for (i, mean) in middle.iter().enumerate() {
+        if let Some(mean) = mean {
+            let variance = values[i + 1 - period..=i].iter()
+                .map(|value| (value - mean) * (value - mean))
+                .sum::<f64>() / period as f64;
+            let width = k * variance.sqrt();
+            upper[i] = Some(mean + width);
+            lower[i] = Some(mean - width);
+        }
+    }
+    Bollinger {
+        middle,
+        upper,
+        lower
+    }
+}
+
+// 相对强弱指数，涨跌幅用 Wilder 平滑：以前 period 次涨跌的平均为起点，之后每次按 1 / period 的权重更新
+pub fn rsi(values: &[f64], period: usize) -> Vec<Option<f64>> {
+    let mut result = vec![None; values.len()];
+    if period == 0 || values.len() <= period {
+        return result;
+    }
+    let mut gain = 0.0;
+    let mut loss = 0.0;
+    for i in 1..values.len() {
+        let change = values[i] - values[i - 1];
+        let (up, down) = if change > 0.0 { (change, 0.0) } else { (0.0, -change) };
+        if i <= period {
+            gain += up / period as f64;
+            loss += down / period as f64;
+            if i < period {
+                continue;
+            }
+        } else {
+            gain = (gain * (period - 1) as f64 + up) / period as f64;
+            loss = (loss * (period - 1) as f64 + down) / period as f64;
+        }
+        result[i] = Some(if loss == 0.0 && gain == 0.0 {
+            50.0    // 价格没有变化
+        } else if loss == 0.0 {
+            100.0
+        } else {
+            100.0 - 100.0 / (1.0 + gain / loss)
+        });
+    }
+    result
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Macd {
+    pub macd: Vec<Option<f64>>,         // 快线与慢线指数移动平均之差
+    pub signal: Vec<Option<f64>>,       // macd 的指数移动平均
+    pub histogram: Vec<Option<f64>>     // macd 与 signal 之差
+}
+
+pub fn macd(values: &[f64], fast: usize, slow: usize, signal: usize) -> Macd {
+    let fast_ema = ema(values, fast);
+    let slow_ema = ema(values, slow);
+    let macd: Vec<Option<f64>> = fast_ema.iter().zip(slow_ema.iter())
+        .map(|pair| match pair {
+            (Some(fast), Some(slow)) => Some(fast - slow),
+            _ => None
+        })
+        .collect();
+
+    // 信号线从 macd 有值的位置开始计算
+    let start = macd.iter().position(Option::is_some).unwrap_or(macd.len());
+    let defined: Vec<f64> = macd[start..].iter().filter_map(|value| *value).collect();
+    let mut signal_line = vec![None; start];
+    signal_line.extend(ema(&defined, signal));
+
+    let histogram = macd.iter().zip(signal_line.iter())
+        .map(|pair| match pair {
+            (Some(macd), Some(signal)) => Some(macd - signal),
+            _ => None
+        })
+        .collect();
+
+    Macd {
+        macd,
+        signal: signal_line,
+        histogram
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn approx(series: &[Option<f64>]) -> Vec<Option<f64>> {
+        series.iter().map(|value| value.map(|value| (value * 1000.0).round() / 1000.0)).collect()
+    }
+
+    #[test]
+    fn test_moving_averages() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(sma(&values, 3), vec![None, None, Some(2.0), Some(3.0), Some(4.0)]);
+        assert_eq!(ema(&values, 3), vec![None, None, Some(2.0), Some(3.0), Some(4.0)]);
+        assert_eq!(approx(&ema(&[2.0, 4.0, 6.0, 6.0], 2)), vec![None, Some(3.0), Some(5.0), Some(5.667)]);
+        assert_eq!(sma(&values, 6), vec![None; 5]);
+    }
+
+    #[test]
+    fn test_bollinger() {
+        let bands = bollinger(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0], 8, 2.0);
+        assert_eq!(bands.middle[7], Some(5.0));
+        assert_eq!(bands.upper[7], Some(9.0));
+        assert_eq!(bands.lower[7], Some(1.0));
+        assert_eq!(bands.upper[6], None);
+    }
+
+    #[test]
+    fn test_rsi() {
+        let values = [10.0, 11.0, 10.0, 12.0, 12.0];
+        // 前两次涨跌：涨 1、跌 1，之后涨 2、平
+        assert_eq!(approx(&rsi(&values, 2)), vec![None, None, Some(50.0), Some(83.333), Some(83.333)]);
+        assert_eq!(rsi(&[1.0, 2.0, 3.0], 2)[2], Some(100.0));
+        assert_eq!(rsi(&[1.0, 1.0, 1.0], 2)[2], Some(50.0));
+    }
+
+    #[test]
+    fn test_macd() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let result = macd(&values, 2, 3, 2);
+        // 等差数列的快慢线之差固定为 0.5
+        assert_eq!(approx(&result.macd), vec![None, None, Some(0.5), Some(0.5), Some(0.5), Some(0.5)]);
+        assert_eq!(approx(&result.signal), vec![None, None, None, Some(0.5), Some(0.5), Some(0.5)]);
+        assert_eq!(approx(&result.histogram)[3], Some(0.0));
+        assert_eq!(result.histogram[2], None);
+    }
+}
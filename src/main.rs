@@ -17,6 +17,17 @@ pub mod hash;
 pub mod errors;
 pub mod handlers;
 pub mod common;
+pub mod money;
+pub mod fees;
+pub mod settlement;
+pub mod shorting;
+pub mod margin;
+pub mod splits;
+pub mod allocation;
+pub mod candles;
+pub mod websocket;
+pub mod index;
+pub mod indicators;
 
 use errors::EngineError;
 use diesel::prelude::*;
@@ -34,6 +45,21 @@ use actix_web::{web, App, HttpResponse, HttpServer, Responder};
 
 const DEFAULT_SECRET_KEY : &str = "hhxxsjnbhhxxsjnbhhxxsjnbhhxxsjnb";
 
+// 每隔多少秒检查一次到期的分红
+const DIVIDEND_WORKER_INTERVAL_SECS : u64 = 60;
+
+// 每隔多少秒检查一次认购期已结束的新股
+const IPO_WORKER_INTERVAL_SECS : u64 = 60;
+
+// 每隔多少秒为进行中的回购计划补挂委托
+const BUYBACK_WORKER_INTERVAL_SECS : u64 = 60;
+
+// 每隔多少秒计算一次综合指数
+const INDEX_WORKER_INTERVAL_SECS : u64 = 60;
+
+// 每隔多少秒交收一次到期的股票和现金，并计提借券费用
+const SETTLEMENT_WORKER_INTERVAL_SECS : u64 = 60;
+
 pub fn test_get_data_connection() -> PgConnection {
     dotenv::dotenv().ok();    // 引入本目录下 .env 文件作为环境变量
 
@@ -69,6 +95,71 @@ fn main() -> std::io::Result<()> {
     let conn_man = ConnectionManager::<PgConnection>::new(database_url);
     let pool = r2d2::Pool::builder().build(conn_man).expect("创建数据库连接线程池失败，请检查 .env 文件或环境变量中的 DATABASE_URL 数据库地址，以及是否使用了 diesel migration run 或者 ！");
 
+    // 命令行 backfill-candles [股票 ID]：从成交记录重建 K 线后退出
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("backfill-candles") {
+        let stock_id = args.get(2).map(|arg| arg.parse::<i64>().expect("股票 ID 必须是整数！"));
+        let rebuilt = handlers::quotation::rebuild_candles(
+            stock_id,
+            &pool.get().expect("无法取得与数据库的连接，请检查 DATABASE_URL ！")
+        ).unwrap_or_else(|err| panic!("重建 K 线失败：{}", err));
+        info!("Rebuilt {} candles", rebuilt);
+        return Ok(());
+    }
+
+    // 命令行 replay-book 股票 ID 时刻 [档数]：重放委托记录，输出该时刻（UTC，如 2020-02-08T10:30:00）的委托簿后退出，档数不填或为 0 时输出整个委托簿
+    if args.get(1).map(String::as_str) == Some("replay-book") {
+        let stock_id = args.get(2).and_then(|arg| arg.parse::<i64>().ok()).expect("股票 ID 必须是整数！");
+        let at = args.get(3).and_then(|arg| arg.parse::<chrono::NaiveDateTime>().ok()).expect("时刻的格式应为 2020-02-08T10:30:00！");
+        let levels = args.get(4).map(|arg| arg.parse::<i64>().expect("档数必须是整数！")).filter(|levels| *levels > 0);
+        let book = handlers::history::replay_book(
+            stock_id,
+            at,
+            levels,
+            &pool.get().expect("无法取得与数据库的连接，请检查 DATABASE_URL ！")
+        ).unwrap_or_else(|err| panic!("重建委托簿失败：{}", err));
+        println!("{}", serde_json::to_string_pretty(&book).expect("委托簿无法序列化！"));
+        return Ok(());
+    }
+
+    // 手续费率
+    let fee_schedule = fees::FeeSchedule::from_env(
+        &pool.get().expect("无法取得与数据库的连接，请检查 DATABASE_URL ！")
+    ).unwrap_or_else(|err| panic!("读取手续费设置失败：{}", err));
+
+    // 交收周期
+    let settlement_schedule = settlement::SettlementSchedule::from_env();
+
+    // 卖空担保比例和借券费率
+    let short_selling = shorting::ShortSelling::from_env();
+
+    // 融资额度和维持担保比例
+    let margin_settings = margin::MarginSettings::from_env();
+
+    // 行情推送的订阅表
+    let market_feed = handlers::market::MarketFeed::default();
+
+    // 用户委托和账户变化推送的连接表
+    let user_feed = handlers::notify::UserFeed::default();
+
+    // 综合指数的名称、计价币种和成分股
+    let index_settings = index::IndexSettings::from_env();
+
+    // 在后台定期做股权登记和派发分红
+    handlers::dividends::run_dividend_worker(pool.clone(), user_feed.clone(), std::time::Duration::from_secs(DIVIDEND_WORKER_INTERVAL_SECS));
+
+    // 在后台定期配售认购期已结束的新股
+    handlers::ipo::run_ipo_worker(pool.clone(), fee_schedule.clone(), user_feed.clone(), std::time::Duration::from_secs(IPO_WORKER_INTERVAL_SECS));
+
+    // 在后台定期为回购计划挂出买入委托
+    handlers::buybacks::run_buyback_worker(pool.clone(), fee_schedule.clone(), settlement_schedule.clone(), margin_settings.clone(), market_feed.clone(), user_feed.clone(), std::time::Duration::from_secs(BUYBACK_WORKER_INTERVAL_SECS));
+
+    // 在后台定期按成分股市值计算综合指数
+    handlers::overview::run_index_worker(pool.clone(), index_settings.clone(), std::time::Duration::from_secs(INDEX_WORKER_INTERVAL_SECS));
+
+    // 在后台定期交收到期的股票和现金
+    handlers::settlement::run_settlement_worker(pool.clone(), user_feed.clone(), std::time::Duration::from_secs(SETTLEMENT_WORKER_INTERVAL_SECS));
+
     // 创建在调试环境下可以即修改代码即重启的监听描述器，并创建 HTTP 服务器
 
     let mut listenfd = ListenFd::from_env();
@@ -76,6 +167,13 @@ fn main() -> std::io::Result<()> {
     let mut server = HttpServer::new(move || {
         App::new()
             .data(pool.clone())     // 每个传入的 HTTP 连接，都先从数据库线程池取出一条连接，附加到应用附加数据中
+            .data(fee_schedule.clone())     // 手续费率
+            .data(settlement_schedule.clone())      // 交收周期
+            .data(short_selling.clone())        // 卖空设置
+            .data(margin_settings.clone())      // 融资设置
+            .data(market_feed.clone())      // 行情推送
+            .data(user_feed.clone())        // 用户事件推送
+            .data(index_settings.clone())       // 综合指数设置
             .wrap(IdentityService::new(
                 CookieIdentityPolicy::new(
                     match std::env::var("SECRET_KEY") {
@@ -131,6 +229,42 @@ fn main() -> std::io::Result<()> {
                             .route(web::get().to_async(handlers::orders::get_my_deals))
                             .to(|| Err::<(), EngineError>(EngineError::MethodNotAllowed(format!("错误：不允许此 HTTP 谓词。"))))
                     )
+                    .service(
+                        handlers::transfer::make_scope()
+                    )
+                    .service(
+                        handlers::settlement::make_scope()
+                    )
+                    .service(
+                        handlers::wallets::make_scope()
+                    )
+                    .service(
+                        handlers::shorts::make_scope()
+                    )
+                    .service(
+                        handlers::margin::make_scope()
+                    )
+                    .service(
+                        handlers::dividends::make_scope()
+                    )
+                    .service(
+                        handlers::splits::make_scope()
+                    )
+                    .service(
+                        handlers::offerings::make_scope()
+                    )
+                    .service(
+                        handlers::ipo::make_scope()
+                    )
+                    .service(
+                        handlers::buybacks::make_scope()
+                    )
+                    .service(
+                        handlers::market::make_scope()
+                    )
+                    .service(
+                        handlers::notify::make_scope()
+                    )
                     .service(
                         web::resource("/recharge")
                             .route(web::post().to_async(handlers::recharge::recharge))
@@ -0,0 +1,65 @@
+use crate::fees::RATE_UNIT;
+use crate::common::env_non_negative;
+use crate::money::Money;
+
+// 融资（保证金交易）设置，比例的单位同手续费，为百万分之一
+#[derive(Debug, Clone)]
+pub struct MarginSettings {
+    pub loan_rate: i64,         // 最多可借入的现金占担保股票市值的比例，为 0 时不允许融资
+    pub maintenance_rate: i64,  // 维持担保比例：净资产不得低于持仓市值的这一比例，否则强制平仓
+}
+
+const DEFAULT_MAINTENANCE_RATE : i64 = 250_000;
+
+impl MarginSettings {
+    pub fn from_env() -> MarginSettings {
+        let settings = MarginSettings {
+            loan_rate: env_non_negative("MARGIN_LOAN_RATE", 0),
+            maintenance_rate: env_non_negative("MARGIN_MAINTENANCE_RATE", DEFAULT_MAINTENANCE_RATE),
+        };
+        if settings.loan_rate >= RATE_UNIT {
+            panic!("环境变量 MARGIN_LOAN_RATE 必须小于 {}！", RATE_UNIT);
+        }
+        settings
+    }
+
+    // 以市值为 collateral_value 的股票为担保，最多可借入的现金
+    pub fn max_loan(&self, collateral_value: Money) -> Money {
+        collateral_value.ratio_floor(self.loan_rate, RATE_UNIT)
+    }
+
+    // 净资产是否低于维持担保比例
+    pub fn below_maintenance(&self, equity: Money, long_value: Money) -> bool {
+        equity < long_value.ratio_ceil(self.maintenance_rate, RATE_UNIT)
+    }
+
+    // 需要卖出多少市值的股票（所得用于还款），才能使净资产恢复到维持担保比例
+    pub fn liquidation_value(&self, equity: Money, long_value: Money) -> Money {
+        if self.maintenance_rate == 0 || equity <= Money::zero() {
+            return long_value;
+        }
+        let keep = equity.ratio_floor(RATE_UNIT, self.maintenance_rate);
+        std::cmp::min(std::cmp::max(long_value - keep, Money::zero()), long_value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_margin() {
+        let margin = MarginSettings { loan_rate: 500_000, maintenance_rate: 250_000 };
+        assert_eq!(margin.max_loan(Money(10_001)), Money(5_000));
+
+        // 持仓 10000，借款 7000，净资产 3000，高于 25%
+        assert!(!margin.below_maintenance(Money(3_000), Money(10_000)));
+        assert!(!margin.below_maintenance(Money(2_500), Money(10_000)));
+        assert!(margin.below_maintenance(Money(2_499), Money(10_000)));
+
+        // 净资产 2000 时最多保留 8000 的持仓
+        assert_eq!(margin.liquidation_value(Money(2_000), Money(10_000)), Money(2_000));
+        assert_eq!(margin.liquidation_value(Money(-1), Money(10_000)), Money(10_000));
+        assert_eq!(margin.liquidation_value(Money(3_000), Money(10_000)), Money(0));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::schema::*;
+use crate::money::{Money, Price};
 
 #[derive(Queryable, Insertable)]
 #[table_name="users"]
@@ -6,8 +7,7 @@ pub struct User {
     pub id: i64,
     pub password_hashed: String,
     pub name: String,
-    pub created_at: chrono::NaiveDateTime,
-    pub balance: i64
+    pub created_at: chrono::NaiveDateTime
 }
 
 impl User {
@@ -23,9 +23,12 @@ pub struct Deal {
     pub buy_user_id: i64,
     pub sell_user_id: Option<i64>,  // 当是 NULL 时，表示是购买发行新股
     pub stock_id: i64,
-    pub price: i32,
+    pub price: Price,
     pub amount: i64,
-    pub created_at: chrono::NaiveDateTime
+    pub created_at: chrono::NaiveDateTime,
+    pub buyer_commission: Money,
+    pub seller_commission: Money,
+    pub stamp_duty: Money
 }
 
 impl Deal {
@@ -41,6 +44,8 @@ pub struct Stock {
     pub name: String,
     pub into_market: bool,
     pub into_market_at: Option<chrono::NaiveDateTime>,
+    pub price_decimals: i16,    // 价格保留的小数位数
+    pub currency: String,       // 报价币种
 }
 
 impl Stock {
@@ -55,13 +60,32 @@ pub struct NewStock {
     pub id: i64,
     pub issuer_id: i64,
     pub offer_circ: i64,
-    pub offer_price: i32,
+    pub offer_price: Price,
     pub offer_unfulfilled: i64,
-    pub created_at: chrono::NaiveDateTime
+    pub created_at: chrono::NaiveDateTime,
+    pub subscribe_ends_at: Option<chrono::NaiveDateTime>,   // 为 None 时先到先得
+    pub allocation: Option<String>,
+    pub lot_size: i64,
+    pub lottery_seed: Option<i64>,      // 配售时抽取，配售前为 None
+    pub allocated_at: Option<chrono::NaiveDateTime>,
+    pub min_subscription: Option<i64>,                  // 最低发行量
+    pub deadline: Option<chrono::NaiveDateTime>,        // 截止时间，到期自动上市或发行失败
+    pub failed_at: Option<chrono::NaiveDateTime>,
+    pub lockup_days: Option<i32>,       // 上市后的锁定天数
+    pub lockup_allottees: bool,         // 锁定期是否也适用于新股认购者
+    pub auction: bool                   // 荷兰式拍卖发行，配售前 offer_price 为最低价格
 }
 
 impl NewStock {
-
+    // 已售出的股数
+    pub fn sold(&self) -> i64 {
+        self.offer_circ - self.offer_unfulfilled
+    }
+
+    // 是否已达到最低发行量
+    pub fn reached_minimum(&self) -> bool {
+        self.min_subscription.map_or(true, |min| self.sold() >= min)
+    }
 }
 
 
@@ -73,11 +97,15 @@ pub struct AskOrder {
     pub id: i64,
     pub user_id: i64,
     pub stock_id: i64,
-    pub price: i32,
+    pub price: Price,
     pub volume: i64,
     pub unfulfilled: i64,
     pub created_at: chrono::NaiveDateTime,
-    pub updated_at: chrono::NaiveDateTime
+    pub updated_at: chrono::NaiveDateTime,
+    pub fee_frozen: Money,  // 剩余冻结的佣金
+    pub fee_paid: Money,    // 已收取的佣金
+    pub cover: bool,        // 是否为买入平仓
+    pub buyback_id: Option<i64>     // 回购计划挂出的委托
 }
 
 impl AskOrder {
@@ -92,11 +120,13 @@ pub struct BidOrder {
     pub id: i64,
     pub user_id: i64,
     pub stock_id: i64,
-    pub price: i32,
+    pub price: Price,
     pub volume: i64,
     pub unfulfilled: i64,
     pub created_at: chrono::NaiveDateTime,
-    pub updated_at: chrono::NaiveDateTime
+    pub updated_at: chrono::NaiveDateTime,
+    pub fee_paid: Money,    // 已收取的佣金
+    pub liquidation: bool   // 是否为强制平仓的委托
 }
 
 impl BidOrder {
@@ -105,4 +135,302 @@ impl BidOrder {
 
 
 
+#[derive(Queryable, Insertable)]
+#[table_name="transfers"]
+pub struct Transfer {
+    pub id: i64,
+    pub from_user_id: i64,
+    pub to_user_id: i64,
+    pub stock_id: Option<i64>,  // 当是 NULL 时，表示转账现金
+    pub amount: i64,
+    pub memo: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub currency: Option<String>    // 转账现金的币种
+}
+
+impl Transfer {
+
+}
+
+
+
+#[derive(Queryable, Insertable)]
+#[table_name="pending_settlements"]
+pub struct PendingSettlement {
+    pub id: i64,
+    pub user_id: i64,
+    pub stock_id: Option<i64>,  // 当是 NULL 时，表示现金
+    pub amount: i64,
+    pub settle_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub currency: Option<String>    // 现金的币种
+}
+
+impl PendingSettlement {
+
+}
+
+
+
+#[derive(Queryable, Insertable, Serialize)]
+#[table_name="currencies"]
+pub struct Currency {
+    pub code: String,
+    pub name: String
+}
+
+impl Currency {
+
+}
+
+
+
+#[derive(Queryable, Insertable, Identifiable)]
+#[primary_key(user_id, currency)]
+#[table_name="user_wallets"]
+pub struct Wallet {
+    pub user_id: i64,
+    pub currency: String,
+    pub balance: Money,
+    pub updated_at: chrono::NaiveDateTime
+}
+
+impl Wallet {
+
+}
+
+
+
+#[derive(Queryable, Insertable, Serialize)]
+#[table_name="fx_rates"]
+pub struct FxRate {
+    pub base_currency: String,
+    pub quote_currency: String,
+    pub rate: i64,  // 1 单位 base_currency 兑换 rate / FX_RATE_UNIT 单位 quote_currency
+    pub updated_at: chrono::NaiveDateTime
+}
+
+impl FxRate {
+
+}
+
+
+
+#[derive(Queryable, Insertable, Serialize)]
+#[table_name="conversions"]
+pub struct Conversion {
+    pub id: i64,
+    pub user_id: i64,
+    pub from_currency: String,
+    pub to_currency: String,
+    pub from_amount: Money,
+    pub to_amount: Money,
+    pub rate: i64,
+    pub created_at: chrono::NaiveDateTime
+}
+
+impl Conversion {
+
+}
+
+
+
+
+#[derive(Queryable, Insertable, AsChangeset, Identifiable)]
+#[primary_key(user_id, stock_id)]
+#[table_name="lending_pool"]
+pub struct LendingPool {
+    pub user_id: i64,
+    pub stock_id: i64,
+    pub available: i64,     // 可借出的数量
+    pub lent: i64,          // 已借出的数量
+    pub updated_at: chrono::NaiveDateTime
+}
+
+impl LendingPool {
+
+}
+
+
+
+#[derive(Queryable, Insertable, AsChangeset, Identifiable)]
+#[primary_key(user_id, currency)]
+#[table_name="margin_loans"]
+pub struct MarginLoan {
+    pub user_id: i64,
+    pub currency: String,
+    pub principal: Money,   // 尚未归还的借款
+    pub updated_at: chrono::NaiveDateTime
+}
+
+impl MarginLoan {
+
+}
+
+
+
+#[derive(Queryable, Insertable, AsChangeset, Identifiable)]
+#[changeset_options(treat_none_as_null="true")]
+#[table_name="stock_loans"]
+pub struct StockLoan {
+    pub id: i64,
+    pub borrower_id: i64,
+    pub lender_id: i64,
+    pub stock_id: i64,
+    pub amount: i64,        // 尚未归还的数量
+    pub price: Price,       // 借入时的卖空委托价
+    pub collateral: Money,  // 剩余的现金担保
+    pub currency: String,
+    pub fee_rate: i64,      // 每日借券费率
+    pub fees_paid: Money,
+    pub fee_accrued_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub closed_at: Option<chrono::NaiveDateTime>
+}
+
+impl StockLoan {
+
+}
+
+
+
+#[derive(Queryable, Insertable, AsChangeset, Identifiable, Serialize)]
+#[changeset_options(treat_none_as_null="true")]
+#[table_name="dividends"]
+pub struct Dividend {
+    pub id: i64,
+    pub stock_id: i64,
+    pub issuer_id: i64,
+    pub currency: String,
+    pub amount_per_share: Money,
+    pub record_date: chrono::NaiveDateTime,
+    pub pay_date: chrono::NaiveDateTime,
+    pub total: Money,       // 登记后应派发的总额
+    pub recorded_at: Option<chrono::NaiveDateTime>,
+    pub paid_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime
+}
+
+impl Dividend {
+
+}
+
+
+
+#[derive(Queryable, Insertable, Serialize)]
+#[table_name="stock_splits"]
+pub struct StockSplit {
+    pub id: i64,
+    pub stock_id: i64,
+    pub numerator: i64,     // 每 denominator 股旧股变为 numerator 股新股
+    pub denominator: i64,
+    pub reference_price: Price,
+    pub cash_paid: Money,
+    pub created_at: chrono::NaiveDateTime
+}
+
+impl StockSplit {
+
+}
+
+
+
+#[derive(Queryable, Insertable, AsChangeset, Identifiable, Serialize)]
+#[table_name="offerings"]
+pub struct Offering {
+    pub id: i64,
+    pub stock_id: i64,
+    pub issuer_id: i64,
+    pub price: Price,
+    pub size: i64,          // 增发的股数
+    pub remaining: i64,     // 尚未被认购的股数
+    pub currency: String,
+    pub starts_at: chrono::NaiveDateTime,
+    pub ends_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime
+}
+
+impl Offering {
+
+}
+
+
+
+#[derive(Queryable, Insertable, AsChangeset, Identifiable, Serialize)]
+#[table_name="ipo_subscriptions"]
+pub struct IPOSubscription {
+    pub id: i64,
+    pub stock_id: i64,
+    pub user_id: i64,
+    pub amount: i64,
+    pub frozen: Money,          // 冻结的资金，含佣金
+    pub allocated: i64,
+    pub refund: Money,
+    pub created_at: chrono::NaiveDateTime,
+    pub allocated_at: Option<chrono::NaiveDateTime>,
+    pub price: Option<Price>    // 拍卖发行的投标价格
+}
+
+impl IPOSubscription {
+
+}
+
+
+
+#[derive(Queryable, Insertable, AsChangeset, Identifiable, Serialize)]
+#[table_name="buybacks"]
+pub struct Buyback {
+    pub id: i64,
+    pub stock_id: i64,
+    pub issuer_id: i64,
+    pub currency: String,
+    pub budget: Money,          // 回购预算，含佣金
+    pub max_price: Price,
+    pub spent: Money,           // 已花费的金额，含佣金
+    pub repurchased: i64,       // 已回购并注销的股数
+    pub created_at: chrono::NaiveDateTime,
+    pub closed_at: Option<chrono::NaiveDateTime>
+}
+
+impl Buyback {
+
+}
+
+
+
+#[derive(Queryable, Insertable, AsChangeset, Identifiable, Serialize)]
+#[table_name="market_indices"]
+#[primary_key(name)]
+pub struct MarketIndex {
+    pub name: String,
+    pub currency: String,
+    pub members: Vec<i64>,      // 上次计算时的成分股
+    pub shares: Vec<i64>,       // 上次计算时各成分股的流通股数
+    pub divisor: i64,           // 指数点位 = 成分股总市值 / 除数，定点数，DIVISOR_SCALE 表示 1
+    pub updated_at: chrono::NaiveDateTime
+}
+
+impl MarketIndex {
+
+}
+
+
+
+#[derive(Queryable, Insertable, Serialize)]
+#[table_name="order_events"]
+pub struct OrderEvent {
+    pub id: i64,
+    pub order_id: i64,
+    pub side: String,       // ask 为买入委托，bid 为卖出委托
+    pub stock_id: i64,
+    pub user_id: i64,
+    pub kind: String,       // inserted、filled、amended、cancelled 或 expired
+    pub price: Price,
+    pub volume: i64,
+    pub unfulfilled: i64,   // 变化后的未成交数量
+    pub created_at: chrono::NaiveDateTime
+}
 
+impl OrderEvent {
+
+}
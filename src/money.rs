@@ -0,0 +1,205 @@
+use std::io::Write;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::{BigInt, Integer};
+
+// 金额和价格都是定点数，以“分”为最小单位，即保留两位小数
+pub const MONEY_SCALE : u32 = 2;
+const MONEY_UNIT : i64 = 100;
+
+// 股票价格默认精确到分，也可以为每只股票设置更粗的精度
+pub const DEFAULT_PRICE_DECIMALS : i16 = MONEY_SCALE as i16;
+
+// 未指定币种时使用的币种
+pub const DEFAULT_CURRENCY : &str = "CNY";
+
+// 汇率的单位：百万分之一。例如 1 美元兑 7.0512 人民币写作 7051200
+pub const FX_RATE_UNIT : i64 = 1_000_000;
+
+fn fmt_fixed(value: i64, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let sign = if value < 0 { "-" } else { "" };
+    let abs = (value as i128).abs();
+    write!(f, "{}{}.{:02}", sign, abs / MONEY_UNIT as i128, abs % MONEY_UNIT as i128)
+}
+
+// 金额，单位为分。JSON 中为整数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(transparent)]
+#[sql_type = "BigInt"]
+pub struct Money(pub i64);
+
+impl Money {
+    pub fn zero() -> Money {
+        Money(0)
+    }
+
+    pub fn checked_add(self, rhs: Money) -> Option<Money> {
+        self.0.checked_add(rhs.0).map(Money)
+    }
+
+    pub fn checked_sub(self, rhs: Money) -> Option<Money> {
+        self.0.checked_sub(rhs.0).map(Money)
+    }
+
+    // 按 numerator / denominator 的比例计算，不足一分按一分计
+    pub fn ratio_ceil(self, numerator: i64, denominator: i64) -> Money {
+        let n = self.0 as i128 * numerator as i128;
+        let d = denominator as i128;
+        Money(((n + d - 1).div_euclid(d)) as i64)
+    }
+
+    // 按 numerator / denominator 的比例计算，不足一分舍去
+    pub fn ratio_floor(self, numerator: i64, denominator: i64) -> Money {
+        let n = self.0 as i128 * numerator as i128;
+        Money(n.div_euclid(denominator as i128) as i64)
+    }
+
+    // 按汇率兑换为另一币种，不足一分舍去
+    pub fn convert(self, rate: i64) -> Money {
+        self.ratio_floor(rate, FX_RATE_UNIT)
+    }
+
+    // 按反向汇率兑换，即 rate 是目标币种兑本币种的汇率
+    pub fn convert_inverse(self, rate: i64) -> Money {
+        self.ratio_floor(FX_RATE_UNIT, rate)
+    }
+}
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_fixed(self.0, f)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, rhs: Money) -> Money {
+        Money(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0 - rhs.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        self.0 += rhs.0;
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, rhs: Money) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl std::iter::Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::zero(), Add::add)
+    }
+}
+
+impl<DB: Backend> ToSql<BigInt, DB> for Money where i64: ToSql<BigInt, DB> {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, DB>) -> serialize::Result {
+        self.0.to_sql(out)
+    }
+}
+
+impl<DB: Backend> FromSql<BigInt, DB> for Money where i64: FromSql<BigInt, DB> {
+    fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+        i64::from_sql(bytes).map(Money)
+    }
+}
+
+// 价格，单位为分。JSON 中为整数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(transparent)]
+#[sql_type = "Integer"]
+pub struct Price(pub i32);
+
+impl Price {
+    // 成交金额 = 价格 × 数量，溢出时返回 None
+    pub fn checked_value(self, volume: i64) -> Option<Money> {
+        (self.0 as i64).checked_mul(volume).map(Money)
+    }
+
+    // 调用前应已用 checked_value 校验过不会溢出
+    pub fn value(self, volume: i64) -> Money {
+        Money(self.0 as i64 * volume)
+    }
+
+    pub fn as_money(self) -> Money {
+        Money(self.0 as i64)
+    }
+
+    // 价格是否符合该精度的最小变动单位
+    pub fn is_on_tick(self, decimals: i16) -> bool {
+        self.0 % price_tick(decimals) == 0
+    }
+}
+
+// 某个价格精度（小数位数）对应的最小变动单位，单位为分
+pub fn price_tick(decimals: i16) -> i32 {
+    10i32.pow(MONEY_SCALE - decimals as u32)
+}
+
+// 价格和金额都以分为单位保存，各股票的价格精度只能比分粗（0 到 MONEY_SCALE 位小数），不支持低于一分的报价
+pub fn is_valid_price_decimals(decimals: i16) -> bool {
+    decimals >= 0 && decimals as u32 <= MONEY_SCALE
+}
+
+impl std::fmt::Display for Price {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_fixed(self.0 as i64, f)
+    }
+}
+
+impl<DB: Backend> ToSql<Integer, DB> for Price where i32: ToSql<Integer, DB> {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, DB>) -> serialize::Result {
+        self.0.to_sql(out)
+    }
+}
+
+impl<DB: Backend> FromSql<Integer, DB> for Price where i32: FromSql<Integer, DB> {
+    fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+        i32::from_sql(bytes).map(Price)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_money() {
+        assert_eq!(Money(12345).to_string(), "123.45");
+        assert_eq!(Money(-5).to_string(), "-0.05");
+        assert_eq!(Price(1000).to_string(), "10.00");
+        assert_eq!(Price(1999).checked_value(3), Some(Money(5997)));
+        assert_eq!(Price(std::i32::MAX).checked_value(std::i64::MAX / 2), None);
+        assert_eq!(Money(10001).ratio_ceil(1, 100), Money(101));
+        assert_eq!(Money(10001).ratio_floor(1, 100), Money(100));
+        assert!(Price(1230).is_on_tick(1));
+        assert!(!Price(1234).is_on_tick(1));
+        assert!(Price(1234).is_on_tick(2));
+        assert!(!Price(1250).is_on_tick(0));
+        assert!(!is_valid_price_decimals(3));
+        assert_eq!(Money(10000).convert(7051200), Money(70512));
+        assert_eq!(Money(70512).convert_inverse(7051200), Money(10000));
+        assert_eq!(Money(1).convert_inverse(7051200), Money(0));
+    }
+}
@@ -1,3 +1,52 @@
+table! {
+    buybacks (id) {
+        id -> Int8,
+        stock_id -> Int8,
+        issuer_id -> Int8,
+        currency -> Varchar,
+        budget -> Int8,
+        max_price -> Int4,
+        spent -> Int8,
+        repurchased -> Int8,
+        created_at -> Timestamp,
+        closed_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    candles (stock_id, period, bucket) {
+        stock_id -> Int8,
+        period -> Varchar,
+        bucket -> Int8,
+        open -> Int4,
+        high -> Int4,
+        low -> Int4,
+        close -> Int4,
+        volume -> Int8,
+        turnover -> Int8,
+    }
+}
+
+table! {
+    conversions (id) {
+        id -> Int8,
+        user_id -> Int8,
+        from_currency -> Varchar,
+        to_currency -> Varchar,
+        from_amount -> Int8,
+        to_amount -> Int8,
+        rate -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    currencies (code) {
+        code -> Varchar,
+        name -> Varchar,
+    }
+}
+
 table! {
     deals (id) {
         id -> Int8,
@@ -7,6 +56,100 @@ table! {
         price -> Int4,
         amount -> Int8,
         created_at -> Timestamp,
+        buyer_commission -> Int8,
+        seller_commission -> Int8,
+        stamp_duty -> Int8,
+    }
+}
+
+table! {
+    dividend_entitlements (dividend_id, user_id) {
+        dividend_id -> Int8,
+        user_id -> Int8,
+        shares -> Int8,
+        amount -> Int8,
+    }
+}
+
+table! {
+    dividends (id) {
+        id -> Int8,
+        stock_id -> Int8,
+        issuer_id -> Int8,
+        currency -> Varchar,
+        amount_per_share -> Int8,
+        record_date -> Timestamp,
+        pay_date -> Timestamp,
+        total -> Int8,
+        recorded_at -> Nullable<Timestamp>,
+        paid_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    fx_rates (base_currency, quote_currency) {
+        base_currency -> Varchar,
+        quote_currency -> Varchar,
+        rate -> Int8,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    index_candles (index_name, period, bucket) {
+        index_name -> Varchar,
+        period -> Varchar,
+        bucket -> Int8,
+        open -> Int4,
+        high -> Int4,
+        low -> Int4,
+        close -> Int4,
+    }
+}
+
+table! {
+    ipo_subscriptions (id) {
+        id -> Int8,
+        stock_id -> Int8,
+        user_id -> Int8,
+        amount -> Int8,
+        frozen -> Int8,
+        allocated -> Int8,
+        refund -> Int8,
+        created_at -> Timestamp,
+        allocated_at -> Nullable<Timestamp>,
+        price -> Nullable<Int4>,
+    }
+}
+
+table! {
+    lending_pool (user_id, stock_id) {
+        user_id -> Int8,
+        stock_id -> Int8,
+        available -> Int8,
+        lent -> Int8,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    margin_loans (user_id, currency) {
+        user_id -> Int8,
+        currency -> Varchar,
+        principal -> Int8,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    market_indices (name) {
+        name -> Varchar,
+        currency -> Varchar,
+        members -> Array<Int8>,
+        shares -> Array<Int8>,
+        divisor -> Int8,
+        updated_at -> Timestamp,
     }
 }
 
@@ -18,6 +161,101 @@ table! {
         offer_price -> Int4,
         offer_unfulfilled -> Int8,
         created_at -> Timestamp,
+        subscribe_ends_at -> Nullable<Timestamp>,
+        allocation -> Nullable<Varchar>,
+        lot_size -> Int8,
+        lottery_seed -> Nullable<Int8>,
+        allocated_at -> Nullable<Timestamp>,
+        min_subscription -> Nullable<Int8>,
+        deadline -> Nullable<Timestamp>,
+        failed_at -> Nullable<Timestamp>,
+        lockup_days -> Nullable<Int4>,
+        lockup_allottees -> Bool,
+        auction -> Bool,
+    }
+}
+
+table! {
+    offering_subscriptions (id) {
+        id -> Int8,
+        offering_id -> Int8,
+        user_id -> Int8,
+        amount -> Int8,
+        cost -> Int8,
+        commission -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    offerings (id) {
+        id -> Int8,
+        stock_id -> Int8,
+        issuer_id -> Int8,
+        price -> Int4,
+        size -> Int8,
+        remaining -> Int8,
+        currency -> Varchar,
+        starts_at -> Timestamp,
+        ends_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    order_events (id) {
+        id -> Int8,
+        order_id -> Int8,
+        side -> Varchar,
+        stock_id -> Int8,
+        user_id -> Int8,
+        kind -> Varchar,
+        price -> Int4,
+        volume -> Int8,
+        unfulfilled -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    pending_settlements (id) {
+        id -> Int8,
+        user_id -> Int8,
+        stock_id -> Nullable<Int8>,
+        amount -> Int8,
+        settle_at -> Timestamp,
+        created_at -> Timestamp,
+        currency -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    stock_loans (id) {
+        id -> Int8,
+        borrower_id -> Int8,
+        lender_id -> Int8,
+        stock_id -> Int8,
+        amount -> Int8,
+        price -> Int4,
+        collateral -> Int8,
+        currency -> Varchar,
+        fee_rate -> Int8,
+        fees_paid -> Int8,
+        fee_accrued_at -> Timestamp,
+        created_at -> Timestamp,
+        closed_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    stock_splits (id) {
+        id -> Int8,
+        stock_id -> Int8,
+        numerator -> Int8,
+        denominator -> Int8,
+        reference_price -> Int4,
+        cash_paid -> Int8,
+        created_at -> Timestamp,
     }
 }
 
@@ -27,6 +265,21 @@ table! {
         name -> Varchar,
         into_market -> Bool,
         into_market_at -> Nullable<Timestamp>,
+        price_decimals -> Int2,
+        currency -> Varchar,
+    }
+}
+
+table! {
+    transfers (id) {
+        id -> Int8,
+        from_user_id -> Int8,
+        to_user_id -> Int8,
+        stock_id -> Nullable<Int8>,
+        amount -> Int8,
+        memo -> Varchar,
+        created_at -> Timestamp,
+        currency -> Nullable<Varchar>,
     }
 }
 
@@ -40,6 +293,10 @@ table! {
         unfulfilled -> Int8,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        fee_frozen -> Int8,
+        fee_paid -> Int8,
+        cover -> Bool,
+        buyback_id -> Nullable<Int8>,
     }
 }
 
@@ -53,6 +310,8 @@ table! {
         unfulfilled -> Int8,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        fee_paid -> Int8,
+        liquidation -> Bool,
     }
 }
 
@@ -73,19 +332,62 @@ table! {
     }
 }
 
+table! {
+    user_wallets (user_id, currency) {
+        user_id -> Int8,
+        currency -> Varchar,
+        balance -> Int8,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Int8,
         password_hashed -> Varchar,
         name -> Varchar,
         created_at -> Timestamp,
-        balance -> Int8,
     }
 }
 
+joinable!(buybacks -> currencies (currency));
+joinable!(buybacks -> stocks (stock_id));
+joinable!(buybacks -> users (issuer_id));
+joinable!(candles -> stocks (stock_id));
+joinable!(conversions -> users (user_id));
 joinable!(deals -> stocks (stock_id));
+joinable!(dividend_entitlements -> dividends (dividend_id));
+joinable!(dividend_entitlements -> users (user_id));
+joinable!(dividends -> currencies (currency));
+joinable!(dividends -> stocks (stock_id));
+joinable!(dividends -> users (issuer_id));
+joinable!(index_candles -> market_indices (index_name));
+joinable!(ipo_subscriptions -> stocks (stock_id));
+joinable!(ipo_subscriptions -> users (user_id));
+joinable!(lending_pool -> stocks (stock_id));
+joinable!(lending_pool -> users (user_id));
+joinable!(margin_loans -> currencies (currency));
+joinable!(margin_loans -> users (user_id));
+joinable!(market_indices -> currencies (currency));
 joinable!(new_stocks -> stocks (id));
 joinable!(new_stocks -> users (issuer_id));
+joinable!(offering_subscriptions -> offerings (offering_id));
+joinable!(offering_subscriptions -> users (user_id));
+joinable!(offerings -> currencies (currency));
+joinable!(offerings -> stocks (stock_id));
+joinable!(offerings -> users (issuer_id));
+joinable!(order_events -> stocks (stock_id));
+joinable!(order_events -> users (user_id));
+joinable!(pending_settlements -> currencies (currency));
+joinable!(pending_settlements -> stocks (stock_id));
+joinable!(pending_settlements -> users (user_id));
+joinable!(stock_loans -> currencies (currency));
+joinable!(stock_loans -> stocks (stock_id));
+joinable!(stock_splits -> stocks (stock_id));
+joinable!(stocks -> currencies (currency));
+joinable!(transfers -> currencies (currency));
+joinable!(transfers -> stocks (stock_id));
+joinable!(user_ask_orders -> buybacks (buyback_id));
 joinable!(user_ask_orders -> stocks (stock_id));
 joinable!(user_ask_orders -> users (user_id));
 joinable!(user_bid_orders -> stocks (stock_id));
@@ -94,14 +396,36 @@ joinable!(user_fav_stock -> stocks (stock_id));
 joinable!(user_fav_stock -> users (user_id));
 joinable!(user_hold_stock -> stocks (stock_id));
 joinable!(user_hold_stock -> users (user_id));
+joinable!(user_wallets -> currencies (currency));
+joinable!(user_wallets -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    buybacks,
+    candles,
+    conversions,
+    currencies,
     deals,
+    dividend_entitlements,
+    dividends,
+    fx_rates,
+    index_candles,
+    ipo_subscriptions,
+    lending_pool,
+    margin_loans,
+    market_indices,
     new_stocks,
+    offering_subscriptions,
+    offerings,
+    order_events,
+    pending_settlements,
+    stock_loans,
+    stock_splits,
     stocks,
+    transfers,
     user_ask_orders,
     user_bid_orders,
     user_fav_stock,
     user_hold_stock,
+    user_wallets,
     users,
 );
@@ -0,0 +1,72 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+
+use crate::common::env_non_negative;
+
+// 交收周期，单位为交易日（周一至周五）。0 表示成交后立即交收（T+0）
+#[derive(Debug, Clone)]
+pub struct SettlementSchedule {
+    pub stock_cycle_days: u32,  // 买入的股票
+    pub cash_cycle_days: u32,   // 卖出所得的现金
+}
+
+fn is_trading_day(date: NaiveDate) -> bool {
+    match date.weekday() {
+        Weekday::Sat | Weekday::Sun => false,
+        _ => true
+    }
+}
+
+// 成交时间之后第 days 个交易日的零点
+pub fn settle_at(traded_at: NaiveDateTime, days: u32) -> NaiveDateTime {
+    let mut date = traded_at.date();
+    let mut remaining = days;
+    while remaining > 0 {
+        date = date + Duration::days(1);
+        if is_trading_day(date) {
+            remaining -= 1;
+        }
+    }
+    date.and_hms(0, 0, 0)
+}
+
+impl SettlementSchedule {
+    pub fn from_env() -> SettlementSchedule {
+        SettlementSchedule {
+            stock_cycle_days: env_non_negative("STOCK_SETTLEMENT_DAYS", 0),
+            cash_cycle_days: env_non_negative("CASH_SETTLEMENT_DAYS", 0),
+        }
+    }
+
+    // 返回 None 表示立即交收
+    pub fn stock_settle_at(&self, traded_at: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self.stock_cycle_days {
+            0 => None,
+            days => Some(settle_at(traded_at, days))
+        }
+    }
+
+    pub fn cash_settle_at(&self, traded_at: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self.cash_cycle_days {
+            0 => None,
+            days => Some(settle_at(traded_at, days))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_settle_at() {
+        // 2019-10-18 是周五
+        let friday = NaiveDate::from_ymd(2019, 10, 18).and_hms(14, 30, 0);
+        assert_eq!(settle_at(friday, 1), NaiveDate::from_ymd(2019, 10, 21).and_hms(0, 0, 0));
+        assert_eq!(settle_at(friday, 2), NaiveDate::from_ymd(2019, 10, 22).and_hms(0, 0, 0));
+        let tuesday = NaiveDate::from_ymd(2019, 10, 15).and_hms(9, 30, 0);
+        assert_eq!(settle_at(tuesday, 1), NaiveDate::from_ymd(2019, 10, 16).and_hms(0, 0, 0));
+        // 周末成交的，顺延到下一个交易日之后
+        let sunday = NaiveDate::from_ymd(2019, 10, 20).and_hms(9, 30, 0);
+        assert_eq!(settle_at(sunday, 1), NaiveDate::from_ymd(2019, 10, 21).and_hms(0, 0, 0));
+    }
+}
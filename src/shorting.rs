@@ -0,0 +1,58 @@
+use chrono::NaiveDateTime;
+
+use crate::fees::RATE_UNIT;
+use crate::common::env_non_negative;
+use crate::money::Money;
+
+// 卖空（融券）设置，费率的单位同手续费，为百万分之一
+#[derive(Debug, Clone)]
+pub struct ShortSelling {
+    pub collateral_rate: i64,   // 卖空时需冻结的现金担保占卖空市值的比例
+    pub borrow_fee_rate: i64,   // 每日借券费率，按借券时的市值计算
+}
+
+const DEFAULT_COLLATERAL_RATE : i64 = 1_500_000;
+
+// 借券费用按自然日计提，返回 since 到 now 之间跨过的零点个数
+pub fn accrued_days(since: NaiveDateTime, now: NaiveDateTime) -> i64 {
+    std::cmp::max((now.date() - since.date()).num_days(), 0)
+}
+
+impl ShortSelling {
+    pub fn from_env() -> ShortSelling {
+        ShortSelling {
+            collateral_rate: env_non_negative("SHORT_COLLATERAL_RATE", DEFAULT_COLLATERAL_RATE),
+            borrow_fee_rate: env_non_negative("SHORT_BORROW_FEE_RATE", 0),
+        }
+    }
+
+    // 卖空市值为 value 时需冻结的现金担保，不足一分按一分计
+    pub fn collateral(&self, value: Money) -> Money {
+        value.ratio_ceil(self.collateral_rate, RATE_UNIT)
+    }
+}
+
+// 借券 days 天的费用，rate 为借券时约定的每日费率
+pub fn borrow_fee(value: Money, rate: i64, days: i64) -> Money {
+    value.ratio_ceil(rate * days, RATE_UNIT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_borrow_fee() {
+        let shorting = ShortSelling { collateral_rate: 1_500_000, borrow_fee_rate: 300 };
+        assert_eq!(shorting.collateral(Money(10_000)), Money(15_000));
+        // 万分之三的日费率，借 3 天
+        assert_eq!(borrow_fee(Money(1_000_000), shorting.borrow_fee_rate, 3), Money(900));
+        assert_eq!(borrow_fee(Money(1), 300, 1), Money(1));
+        assert_eq!(borrow_fee(Money(1_000_000), 300, 0), Money(0));
+
+        let borrowed = NaiveDate::from_ymd(2019, 11, 15).and_hms(14, 30, 0);
+        assert_eq!(accrued_days(borrowed, NaiveDate::from_ymd(2019, 11, 15).and_hms(23, 59, 0)), 0);
+        assert_eq!(accrued_days(borrowed, NaiveDate::from_ymd(2019, 11, 18).and_hms(0, 0, 0)), 3);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::money::{Money, Price, price_tick};
+
+use std::convert::TryFrom;
+
+// 拆股（合股）比例：每 denominator 股旧股变为 numerator 股新股，例如 2:1 拆股、1:10 合股
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitRatio {
+    pub numerator: i64,
+    pub denominator: i64,
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+impl SplitRatio {
+    // 比例必须为正且不等于 1，返回约分后的比例
+    pub fn new(numerator: i64, denominator: i64) -> Option<SplitRatio> {
+        if numerator <= 0 || denominator <= 0 || numerator == denominator {
+            return None;
+        }
+        let g = gcd(numerator, denominator);
+        Some(SplitRatio { numerator: numerator / g, denominator: denominator / g })
+    }
+
+    // 调整后的整股数，零股舍去；超出股数的表示范围时返回 None
+    pub fn shares(&self, shares: i64) -> Option<i64> {
+        i64::try_from(shares as i128 * self.numerator as i128 / self.denominator as i128).ok()
+    }
+
+    // 调整后不足一股的部分，单位为 1/denominator 股；超出表示范围时返回 None
+    pub fn fraction(&self, shares: i64) -> Option<i64> {
+        i64::try_from(shares as i128 * self.numerator as i128 % self.denominator as i128).ok()
+    }
+
+    // 零股按 price 折算的现金，不足一分舍去
+    pub fn fraction_cash(&self, shares: i64, price: Price) -> Option<Money> {
+        self.fraction(shares).map(|fraction| price.as_money().ratio_floor(fraction, self.denominator))
+    }
+
+    // 调整后的价格，向下取到最小变动单位，用于买入委托；超出价格的表示范围时返回 None
+    pub fn price_floor(&self, price: Price, decimals: i16) -> Option<Price> {
+        let tick = price_tick(decimals) as i128;
+        let raw = price.0 as i128 * self.denominator as i128 / self.numerator as i128;
+        i32::try_from(raw / tick * tick).ok().map(Price)
+    }
+
+    // 调整后的价格，向上取到最小变动单位，用于卖出委托和参考价；超出价格的表示范围时返回 None
+    pub fn price_ceil(&self, price: Price, decimals: i16) -> Option<Price> {
+        let tick = price_tick(decimals) as i128;
+        let n = price.0 as i128 * self.denominator as i128;
+        let d = self.numerator as i128 * tick;
+        i32::try_from((n + d - 1) / d * tick).ok().map(Price)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_ratio() {
+        assert_eq!(SplitRatio::new(4, 2), Some(SplitRatio { numerator: 2, denominator: 1 }));
+        assert_eq!(SplitRatio::new(3, 3), None);
+        assert_eq!(SplitRatio::new(0, 1), None);
+
+        // 3:2 拆股，101 股变为 151.5 股
+        let split = SplitRatio::new(3, 2).unwrap();
+        assert_eq!(split.shares(101), Some(151));
+        assert_eq!(split.fraction(101), Some(1));
+        assert_eq!(split.fraction_cash(101, Price(1001)), Some(Money(500)));
+        assert_eq!(split.price_floor(Price(1001), 2), Some(Price(667)));
+        assert_eq!(split.price_ceil(Price(1001), 2), Some(Price(668)));
+        assert_eq!(split.price_floor(Price(1001), 1), Some(Price(660)));
+        assert_eq!(split.price_ceil(Price(1001), 1), Some(Price(670)));
+
+        // 1:10 合股
+        let reverse = SplitRatio::new(1, 10).unwrap();
+        assert_eq!(reverse.shares(25), Some(2));
+        assert_eq!(reverse.fraction(25), Some(5));
+        assert_eq!(reverse.price_floor(Price(123), 2), Some(Price(1230)));
+        assert_eq!(reverse.fraction_cash(25, Price(1230)), Some(Money(615)));
+    }
+
+    #[test]
+    fn test_split_shares_overflow() {
+        // 2:1 拆股，股数翻倍，恰好不超过 i64 的上限时仍可调整
+        let split = SplitRatio::new(2, 1).unwrap();
+        assert_eq!(split.shares(std::i64::MAX / 2), Some(std::i64::MAX - 1));
+        assert_eq!(split.fraction(std::i64::MAX / 2), Some(0));
+        assert_eq!(split.shares(std::i64::MAX / 2 + 1), None);
+        assert_eq!(split.shares(std::i64::MAX), None);
+
+        // 3:2 拆股，中间结果超出 i64 但调整后的股数仍在范围内
+        let split = SplitRatio::new(3, 2).unwrap();
+        assert_eq!(split.shares(std::i64::MAX / 3 * 2), Some(std::i64::MAX / 3 * 3));
+        assert_eq!(split.fraction(std::i64::MAX), Some(1));
+    }
+
+    #[test]
+    fn test_split_price_overflow() {
+        // 1:2 合股，价格翻倍，恰好不超过 i32 的上限时仍可调整
+        let reverse = SplitRatio::new(1, 2).unwrap();
+        assert_eq!(reverse.price_floor(Price(std::i32::MAX / 2), 2), Some(Price(std::i32::MAX - 1)));
+        assert_eq!(reverse.price_ceil(Price(std::i32::MAX / 2), 2), Some(Price(std::i32::MAX - 1)));
+        assert_eq!(reverse.price_floor(Price(std::i32::MAX / 2 + 1), 2), None);
+        assert_eq!(reverse.price_ceil(Price(std::i32::MAX / 2 + 1), 2), None);
+        assert_eq!(reverse.price_ceil(Price(std::i32::MAX), 2), None);
+    }
+}
@@ -0,0 +1,170 @@
+// WebSocket（RFC 6455）消息的读写。帧的解析和编码（掩码、长度、控制帧不超过 125 字节）使用 actix-http 的实现，
+// 这里把分片的消息拼接完整，并拒绝分片的控制帧
+use actix_http::ws::{Message, OpCode, Parser};
+use actix_web::web::{Bytes, BytesMut};
+
+// 客户端发来的单条消息（含所有分片）最大长度，订阅命令都很短
+pub const MAX_MESSAGE_SIZE : usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+pub struct MessageReader {
+    fragments: Option<(OpCode, BytesMut)>     // 尚未收完的分片消息的类型和已收到的内容
+}
+
+impl MessageReader {
+    // 从 buffer 开头读取一条完整的消息并移除已解析的字节；数据不够一条消息时返回 None。
+    // 分片消息之间可以穿插控制帧，控制帧先返回
+    pub fn read(&mut self, buffer: &mut BytesMut) -> Result<Option<Message>, String> {
+        loop {
+            let (finished, opcode, payload) = match Parser::parse(buffer, true, MAX_MESSAGE_SIZE)
+                .map_err(|ws_err| format!("WebSocket 协议错误：{}", ws_err))? {
+                Some(frame) => frame,
+                None => return Ok(None)
+            };
+            let payload = payload.unwrap_or_else(BytesMut::new);
+
+            match opcode {
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    if !finished {
+                        return Err(format!("WebSocket 控制帧不能分片。"));
+                    }
+                    return Ok(Some(match opcode {
+                        OpCode::Ping => Message::Ping(String::from_utf8_lossy(&payload).into()),
+                        OpCode::Pong => Message::Pong(String::from_utf8_lossy(&payload).into()),
+                        _ => Message::Close(Parser::parse_close_payload(&payload))
+                    }));
+                },
+                OpCode::Text | OpCode::Binary => {
+                    if self.fragments.is_some() {
+                        return Err(format!("上一条分片的 WebSocket 消息尚未结束。"));
+                    }
+                    if finished {
+                        return to_message(opcode, payload).map(Some);
+                    }
+                    self.fragments = Some((opcode, payload));
+                },
+                OpCode::Continue => {
+                    let (opcode, mut data) = self.fragments.take()
+                        .ok_or_else(|| format!("收到了不属于任何消息的 WebSocket 分片。"))?;
+                    if data.len() + payload.len() > MAX_MESSAGE_SIZE {
+                        return Err(format!("WebSocket 消息过长，最多 {} 字节。", MAX_MESSAGE_SIZE));
+                    }
+                    data.extend_from_slice(&payload);
+                    if finished {
+                        return to_message(opcode, data).map(Some);
+                    }
+                    self.fragments = Some((opcode, data));
+                },
+                OpCode::Bad => return Err(format!("不支持的 WebSocket 帧类型。"))
+            }
+        }
+    }
+}
+
+fn to_message(opcode: OpCode, data: BytesMut) -> Result<Message, String> {
+    match opcode {
+        OpCode::Text => String::from_utf8(data.to_vec())
+            .map(Message::Text)
+            .map_err(|_| format!("WebSocket 文本消息不是有效的 UTF-8 编码。")),
+        _ => Ok(Message::Binary(data.freeze()))
+    }
+}
+
+// 服务端发出的消息不分片、不带掩码
+pub fn encode(message: Message) -> Bytes {
+    let mut frame = BytesMut::new();
+    match message {
+        Message::Text(text) => Parser::write_message(&mut frame, text, OpCode::Text, true, false),
+        Message::Binary(data) => Parser::write_message(&mut frame, data, OpCode::Binary, true, false),
+        Message::Ping(text) => Parser::write_message(&mut frame, text, OpCode::Ping, true, false),
+        Message::Pong(text) => Parser::write_message(&mut frame, text, OpCode::Pong, true, false),
+        Message::Close(reason) => Parser::write_close(&mut frame, reason, false),
+        Message::Nop => ()
+    }
+    frame.freeze()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_http::ws::{CloseCode, CloseReason};
+
+    // 客户端发出的帧带掩码
+    fn masked(buffer: &mut BytesMut, opcode: OpCode, payload: &[u8], finished: bool) {
+        Parser::write_message(buffer, Bytes::from(payload), opcode, finished, true);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(&encode(Message::Text("Hello".to_owned()))[..], &[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+        assert_eq!(&encode(Message::Binary(Bytes::from(&[0u8; 256][..])))[..4], &[0x82, 0x7E, 0x01, 0x00]);
+        assert_eq!(&encode(Message::Close(Some(CloseCode::Normal.into())))[..], &[0x88, 0x02, 0x03, 0xE8]);
+    }
+
+    #[test]
+    fn test_read() {
+        let mut reader = MessageReader::default();
+        let mut buffer = BytesMut::new();
+        masked(&mut buffer, OpCode::Text, b"Hello", true);
+        masked(&mut buffer, OpCode::Ping, &[0u8; 100], true);
+
+        // 不完整的帧等待更多数据
+        let mut partial = BytesMut::from(&buffer[..8]);
+        assert_eq!(reader.read(&mut partial), Ok(None));
+        assert_eq!(partial.len(), 8);
+
+        assert_eq!(reader.read(&mut buffer), Ok(Some(Message::Text("Hello".to_owned()))));
+        assert_eq!(reader.read(&mut buffer), Ok(Some(Message::Ping(String::from_utf8(vec![0u8; 100]).unwrap()))));
+        assert!(buffer.is_empty());
+
+        // 分片的消息拼接完整，中间的控制帧先返回
+        masked(&mut buffer, OpCode::Text, b"Hel", false);
+        masked(&mut buffer, OpCode::Ping, b"", true);
+        masked(&mut buffer, OpCode::Continue, b"l", false);
+        masked(&mut buffer, OpCode::Continue, b"o", true);
+        assert_eq!(reader.read(&mut buffer), Ok(Some(Message::Ping(String::new()))));
+        assert_eq!(reader.read(&mut buffer), Ok(Some(Message::Text("Hello".to_owned()))));
+        assert!(buffer.is_empty());
+
+        let mut close = BytesMut::new();
+        masked(&mut close, OpCode::Close, &[0x03, 0xE8], true);
+        assert_eq!(reader.read(&mut close), Ok(Some(Message::Close(Some(CloseReason { code: CloseCode::Normal, description: None })))));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        // 没有掩码
+        let mut buffer = BytesMut::from(&encode(Message::Text("Hello".to_owned()))[..]);
+        assert!(MessageReader::default().read(&mut buffer).is_err());
+
+        // 分片的控制帧
+        let mut buffer = BytesMut::new();
+        masked(&mut buffer, OpCode::Ping, b"Hel", false);
+        assert!(MessageReader::default().read(&mut buffer).is_err());
+
+        // 超过 125 字节的控制帧
+        let mut buffer = BytesMut::new();
+        masked(&mut buffer, OpCode::Ping, &[0u8; 126], true);
+        assert!(MessageReader::default().read(&mut buffer).is_err());
+
+        // 没有开头的分片，以及上一条分片消息未结束时开始新消息
+        let mut buffer = BytesMut::new();
+        masked(&mut buffer, OpCode::Continue, b"lo", true);
+        assert!(MessageReader::default().read(&mut buffer).is_err());
+        let mut buffer = BytesMut::new();
+        masked(&mut buffer, OpCode::Text, b"Hel", false);
+        masked(&mut buffer, OpCode::Text, b"lo", true);
+        assert!(MessageReader::default().read(&mut buffer).is_err());
+
+        // 拼接后超长
+        let mut buffer = BytesMut::new();
+        masked(&mut buffer, OpCode::Binary, &vec![0u8; MAX_MESSAGE_SIZE], false);
+        masked(&mut buffer, OpCode::Continue, b"!", true);
+        assert!(MessageReader::default().read(&mut buffer).is_err());
+
+        // 无效的 UTF-8 文本
+        let mut buffer = BytesMut::new();
+        masked(&mut buffer, OpCode::Text, &[0xFF, 0xFE], true);
+        assert!(MessageReader::default().read(&mut buffer).is_err());
+    }
+}
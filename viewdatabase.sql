@@ -1,6 +1,8 @@
-SELECT id AS user_id, name, created_at, balance FROM users LIMIT 1000;
+SELECT id AS user_id, name, created_at FROM users LIMIT 1000;
 
-SELECT id AS stock_id, name AS stock_name, into_market, into_market_at FROM stocks LIMIT 1000;
+SELECT user_id, users.name AS user_name, currency, balance, updated_at FROM user_wallets INNER JOIN users ON user_wallets.user_id = users.id LIMIT 1000;
+
+SELECT id AS stock_id, name AS stock_name, currency, into_market, into_market_at FROM stocks LIMIT 1000;
 
 SELECT
 stocks.id AS stock_id,
@@ -69,3 +71,132 @@ price,
 amount,
 deals.created_at
 FROM deals INNER JOIN users AS buy_users ON deals.buy_user_id = buy_users.id LEFT JOIN users AS sell_users ON deals.sell_user_id = sell_users.id INNER JOIN stocks ON deals.stock_id = stocks.id LIMIT 1000;
+
+SELECT
+transfers.id AS transfer_id,
+from_users.id AS from_user_id,
+from_users.name AS from_user_name,
+to_users.id AS to_user_id,
+to_users.name AS to_user_name,
+stocks.id AS stock_id,
+stocks.name AS stock_name,
+transfers.currency,
+amount,
+memo,
+transfers.created_at
+FROM transfers INNER JOIN users AS from_users ON transfers.from_user_id = from_users.id INNER JOIN users AS to_users ON transfers.to_user_id = to_users.id LEFT JOIN stocks ON transfers.stock_id = stocks.id LIMIT 1000;
+
+SELECT * FROM fx_rates LIMIT 1000;
+
+SELECT
+users.id AS lend_user_id,
+users.name AS user_name,
+stocks.id AS stock_id,
+stocks.name AS stock_name,
+available,
+lent,
+lending_pool.updated_at AS updated_at
+FROM lending_pool INNER JOIN users ON lending_pool.user_id = users.id INNER JOIN stocks ON lending_pool.stock_id = stocks.id LIMIT 1000;
+
+SELECT
+stock_loans.id AS loan_id,
+borrowers.id AS borrower_id,
+borrowers.name AS borrower_name,
+lenders.id AS lender_id,
+lenders.name AS lender_name,
+stocks.id AS stock_id,
+stocks.name AS stock_name,
+amount,
+price,
+collateral,
+stock_loans.currency,
+fee_rate,
+fees_paid,
+stock_loans.created_at,
+closed_at
+FROM stock_loans INNER JOIN users AS borrowers ON stock_loans.borrower_id = borrowers.id INNER JOIN users AS lenders ON stock_loans.lender_id = lenders.id INNER JOIN stocks ON stock_loans.stock_id = stocks.id LIMIT 1000;
+
+SELECT user_id, users.name AS user_name, currency, principal, updated_at FROM margin_loans INNER JOIN users ON margin_loans.user_id = users.id LIMIT 1000;
+
+SELECT
+dividends.id AS dividend_id,
+stocks.id AS stock_id,
+stocks.name AS stock_name,
+users.name AS issuer_name,
+dividends.currency,
+amount_per_share,
+record_date,
+pay_date,
+total,
+recorded_at,
+paid_at
+FROM dividends INNER JOIN stocks ON dividends.stock_id = stocks.id INNER JOIN users ON dividends.issuer_id = users.id LIMIT 1000;
+
+SELECT
+offerings.id AS offering_id,
+stocks.id AS stock_id,
+stocks.name AS stock_name,
+users.name AS issuer_name,
+offerings.currency,
+price,
+size,
+remaining,
+starts_at,
+ends_at
+FROM offerings INNER JOIN stocks ON offerings.stock_id = stocks.id INNER JOIN users ON offerings.issuer_id = users.id LIMIT 1000;
+
+SELECT
+ipo_subscriptions.id AS subscription_id,
+stocks.name AS stock_name,
+users.name AS user_name,
+amount,
+frozen,
+allocated,
+refund,
+ipo_subscriptions.created_at,
+allocated_at
+FROM ipo_subscriptions INNER JOIN stocks ON ipo_subscriptions.stock_id = stocks.id INNER JOIN users ON ipo_subscriptions.user_id = users.id LIMIT 1000;
+
+SELECT
+buybacks.id AS buyback_id,
+stocks.name AS stock_name,
+users.name AS issuer_name,
+buybacks.currency,
+budget,
+max_price,
+spent,
+repurchased,
+buybacks.created_at,
+closed_at
+FROM buybacks INNER JOIN stocks ON buybacks.stock_id = stocks.id INNER JOIN users ON buybacks.issuer_id = users.id LIMIT 1000;
+
+SELECT
+name,
+currency,
+members,
+divisor,
+updated_at
+FROM market_indices LIMIT 1000;
+
+SELECT
+index_name,
+period,
+bucket,
+open,
+high,
+low,
+close
+FROM index_candles ORDER BY bucket DESC LIMIT 1000;
+
+SELECT
+order_events.id AS event_id,
+order_id,
+side,
+stocks.name AS stock_name,
+users.name AS user_name,
+kind,
+order_events.price,
+volume,
+unfulfilled,
+order_events.created_at
+FROM order_events INNER JOIN stocks ON order_events.stock_id = stocks.id INNER JOIN users ON order_events.user_id = users.id ORDER BY order_events.id DESC LIMIT 1000;